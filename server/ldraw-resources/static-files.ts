@@ -11,6 +11,9 @@ LDRAWRouter.use("/bundle", BundleRouter)
 LDRAWRouter.use("/data/parts", express.static(`${process.env.LDRAW_LIB}/parts`));
 LDRAWRouter.use("/data/parts", express.static(`${process.env.LDRAW_LIB}/p`));
 
+// what the bake binary writes, fetched when the viewer prefers baked bricks
+LDRAWRouter.use("/baked", express.static(process.env.BAKED_DIR ?? `${process.env.LDRAW_LIB}/baked`));
+
 LDRAWRouter.use("/config/LDConfig.ldr", express.static(`${process.env.LDRAW_LIB}/LDConfig.ldr`));
 LDRAWRouter.use("/license/CAlicense.txt", express.static(`${process.env.LDRAW_LIB}/CAlicense.txt`));
 LDRAWRouter.use("/license/CAlicense4.txt", express.static(`${process.env.LDRAW_LIB}/CAlicense4.txt`));
@@ -0,0 +1,805 @@
+use std::collections::HashMap;
+use std::ops::Mul;
+
+use three_d::{vec3, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3};
+use web_sys::AbortSignal;
+
+use crate::{
+    coordinates::{CoordinateSystem, OutputFrame, Units},
+    errors::{LoadError, LoadErrorKind},
+    parser::{
+        colors::{ColorFinish, ColorTable, LDrawColor, EDGE_COLOR, MAIN_COLOR},
+        part::{
+            fetch_bytes, LDrawAuthor, LDrawBrick, LDrawContour, LDrawFile, LDrawOptionalContour,
+            LDrawSubfile, LDrawTriangle,
+        },
+        tokenizer::{BFCDirection, Color, LDrawType},
+    },
+    progress::{LoadEvent, Progress},
+    scene::{part_instances, references, MeshKey, Scene, SceneOptions, TransparencySort},
+    worker::{PayloadMesh, PayloadRange, ScenePayload},
+};
+
+// A baked brick is every part flattened into one mesh plus a table of where the
+// parts are placed, so loading it is a single fetch and a straight read.
+//
+//   header   "LDRB", version u16, flags u16, payload length u32, CRC-32 of the payload u32
+//   payload  strings, colours, entry file, parts, instances, the scene if flagged
+//
+// Everything is little endian. Positions are f32, indices are u16 when the part has few
+// enough vertices and u32 otherwise. The scene is the one the viewer draws, in LDraw
+// space, as a buffer of vertices that meshes take ranges of and a matrix per instance,
+// so it is only placed in the output frame on load instead of built again.
+
+pub const MAGIC: [u8; 4] = *b"LDRB";
+// bump whenever the layout changes, older files are then reported as stale
+pub const VERSION: u16 = 2;
+const HEADER_SIZE: usize = 16;
+const NONE: u32 = u32::MAX;
+// the flags in the header
+const WITH_SCENE: u16 = 1;
+
+// the brick alone, which is what the cache and the worker pass around
+pub fn encode(brick: &LDrawBrick) -> Vec<u8> {
+    write(brick, None)
+}
+
+// with the scene built from `brick` by baked_scene, as the baker writes it
+pub fn encode_with_scene(brick: &LDrawBrick, scene: &Scene) -> Vec<u8> {
+    write(brick, Some(scene))
+}
+
+// the scene a baked brick carries, built with the default options in LDraw space.
+// Windows with another instance threshold still draw it as it was baked
+pub fn baked_scene(brick: &LDrawBrick) -> Scene {
+    Scene::build(
+        brick,
+        &SceneOptions {
+            frame: OutputFrame::new(CoordinateSystem::LDraw, Units::LDU),
+            transparency_sort: TransparencySort::PerInstance,
+            ..SceneOptions::default()
+        },
+    )
+}
+
+fn write(brick: &LDrawBrick, scene: Option<&Scene>) -> Vec<u8> {
+    let mut strings = Strings::default();
+    let mut payload = Writer::default();
+
+    let instances = if brick.files.contains_key(&brick.entry_file) {
+        part_instances(brick)
+    } else {
+        Vec::new()
+    };
+
+    let mut parts: Vec<&str> = Vec::new();
+    for instance in instances.iter() {
+        if !parts.contains(&instance.filename.as_str()) {
+            parts.push(&instance.filename);
+        }
+    }
+
+    let mut body = Writer::default();
+    let colors = brick.colors.colors();
+    body.u32(colors.len() as u32);
+    for color in colors {
+        body.u32(strings.index(&color.name));
+        body.u32(color.code);
+        body.bytes(&color.value);
+        body.bytes(&color.edge);
+        body.bytes(&[color.alpha, color.luminance]);
+        let (finish, material) = match &color.finish {
+            ColorFinish::Plain => (0, NONE),
+            ColorFinish::Chrome => (1, NONE),
+            ColorFinish::Pearlescent => (2, NONE),
+            ColorFinish::Rubber => (3, NONE),
+            ColorFinish::MatteMetallic => (4, NONE),
+            ColorFinish::Metal => (5, NONE),
+            ColorFinish::Material(name) => (6, strings.index(name)),
+        };
+        body.bytes(&[finish]);
+        body.u32(material);
+    }
+
+    body.u32(strings.index(&brick.entry_file));
+
+    body.u32(parts.len() as u32);
+    for name in parts.iter() {
+        let mut part = FlatPart::default();
+        if let Some(file) = brick.files.get(*name) {
+            part.flatten(
+                brick,
+                file,
+                MAIN_COLOR,
+                Matrix4::identity(),
+                &file.bfc_direction,
+            );
+        }
+        body.u32(strings.index(name));
+        part.write(&mut body);
+    }
+
+    body.u32(instances.len() as u32);
+    for instance in instances.iter() {
+        let part = parts
+            .iter()
+            .position(|name| *name == instance.filename)
+            .unwrap();
+        body.u32(part as u32);
+        body.u32(instance.color);
+        let matrix = instance.transformation;
+        for column in [matrix.x, matrix.y, matrix.z, matrix.w] {
+            body.vector(column.truncate());
+        }
+        match &instance.source {
+            Some((file, line)) => {
+                body.u32(strings.index(file));
+                body.u32(*line as u32);
+            }
+            None => {
+                body.u32(NONE);
+                body.u32(0);
+            }
+        }
+    }
+
+    if let Some(scene) = scene {
+        write_scene(&ScenePayload::from_scene(scene), &mut body, &mut strings);
+    }
+
+    // the strings come first, so they can be read before anything refers to them
+    payload.u32(strings.list.len() as u32);
+    for string in strings.list.iter() {
+        payload.u32(string.len() as u32);
+        payload.bytes(string.as_bytes());
+    }
+    payload.bytes(&body.0);
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + payload.0.len());
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    let flags = if scene.is_some() { WITH_SCENE } else { 0 };
+    bytes.extend_from_slice(&flags.to_le_bytes());
+    bytes.extend_from_slice(&(payload.0.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32(&payload.0).to_le_bytes());
+    bytes.extend_from_slice(&payload.0);
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Result<LDrawBrick, LoadError> {
+    decode_with_scene(bytes).map(|(brick, _)| brick)
+}
+
+// the header is checked before anything else is read, a file from another version or
+// one that was cut off or changed on the way is rejected as stale. The scene is in
+// LDraw space, Scene::framed places it
+pub fn decode_with_scene(bytes: &[u8]) -> Result<(LDrawBrick, Option<Scene>), LoadError> {
+    let stale = |message: String| LoadError::new(LoadErrorKind::Stale, message);
+
+    if bytes.len() < HEADER_SIZE || bytes[..4] != MAGIC {
+        return Err(stale("not a baked brick".to_string()));
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(stale(format!(
+            "baked with format version {}, this build reads version {}",
+            version, VERSION
+        )));
+    }
+    let length = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize;
+    let checksum = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]);
+    let payload = &bytes[HEADER_SIZE..];
+    if payload.len() != length {
+        return Err(stale(format!(
+            "expected {} bytes after the header, found {}",
+            length,
+            payload.len()
+        )));
+    }
+    if crc32(payload) != checksum {
+        return Err(stale("the checksum does not match".to_string()));
+    }
+    let flags = u16::from_le_bytes([bytes[6], bytes[7]]);
+
+    read_brick(
+        &mut Reader {
+            bytes: payload,
+            at: 0,
+        },
+        flags & WITH_SCENE != 0,
+    )
+    .map_err(stale)
+}
+
+// fetches the baked brick the server keeps next to the bundles, with its scene when the
+// baker stored one
+pub async fn fetch_baked(
+    id: &str,
+    signal: Option<&AbortSignal>,
+    progress: &Progress,
+) -> Result<(LDrawBrick, Option<Scene>), LoadError> {
+    let file = format!("{}.ldrb", id);
+    let url = format!("http://localhost:3000/ldraw/baked/{}", file);
+    progress.emit(LoadEvent::FilesResolved {
+        resolved: 0,
+        total: 1,
+    });
+    let bytes = fetch_bytes(&url, &file, signal).await?;
+    progress.emit(LoadEvent::BytesFetched {
+        file: file.to_string(),
+        bytes: bytes.len(),
+    });
+
+    let brick = decode_with_scene(&bytes).map_err(|error| error.in_file(&file))?;
+    progress.emit(LoadEvent::FilesResolved {
+        resolved: 1,
+        total: 1,
+    });
+    Ok(brick)
+}
+
+fn read_brick(
+    reader: &mut Reader,
+    with_scene: bool,
+) -> Result<(LDrawBrick, Option<Scene>), String> {
+    let count = reader.u32()? as usize;
+    let mut strings = Vec::with_capacity(count.min(reader.remaining()));
+    for _ in 0..count {
+        let length = reader.u32()? as usize;
+        let bytes = reader.bytes(length)?;
+        strings.push(String::from_utf8(bytes.to_vec()).map_err(|error| error.to_string())?);
+    }
+    let string = |index: u32| {
+        strings
+            .get(index as usize)
+            .cloned()
+            .ok_or_else(|| format!("string {} is out of range", index))
+    };
+
+    let mut colors = ColorTable::new();
+    for _ in 0..reader.u32()? {
+        let name = string(reader.u32()?)?;
+        let code = reader.u32()?;
+        let value = reader.array::<3>()?;
+        let edge = reader.array::<3>()?;
+        let [alpha, luminance, finish] = reader.array::<3>()?;
+        let material = reader.u32()?;
+        let finish = match finish {
+            0 => ColorFinish::Plain,
+            1 => ColorFinish::Chrome,
+            2 => ColorFinish::Pearlescent,
+            3 => ColorFinish::Rubber,
+            4 => ColorFinish::MatteMetallic,
+            5 => ColorFinish::Metal,
+            _ => ColorFinish::Material(string(material)?),
+        };
+        colors.insert(LDrawColor {
+            name,
+            code,
+            value,
+            edge,
+            alpha,
+            luminance,
+            finish,
+        });
+    }
+
+    let entry_file = string(reader.u32()?)?;
+
+    let mut files = HashMap::new();
+    let mut parts = Vec::new();
+    for _ in 0..reader.u32()? {
+        let name = string(reader.u32()?)?;
+        let mut file = empty_file(&name);
+        file.ldraw_type = Some(LDrawType::Part);
+        FlatPart::read(reader, &mut file)?;
+        parts.push(name.to_string());
+        files.insert(name, file);
+    }
+
+    // instances go back into the files that placed them, with their transformation
+    // relative to the entry file, so sources and picking stay the same
+    let mut models: Vec<LDrawFile> = Vec::new();
+    let mut loose_part = None;
+    for _ in 0..reader.u32()? {
+        let part = reader.u32()? as usize;
+        let filename = parts
+            .get(part)
+            .ok_or_else(|| format!("part {} is out of range", part))?;
+        let color = reader.u32()?;
+        let columns = [
+            reader.vector()?,
+            reader.vector()?,
+            reader.vector()?,
+            reader.vector()?,
+        ];
+        let source = reader.u32()?;
+        let line = reader.u32()? as usize;
+        if source == NONE {
+            loose_part = Some(filename.to_string());
+            continue;
+        }
+
+        let source = string(source)?;
+        let model = match models.iter_mut().position(|model| model.name == source) {
+            Some(index) => &mut models[index],
+            None => {
+                models.push(empty_file(&source));
+                models.last_mut().unwrap()
+            }
+        };
+        let transformation = Matrix3::from_cols(columns[0], columns[1], columns[2]).transpose();
+        model.subfiles.push(LDrawSubfile {
+            color: Color { value: color },
+            bfc_direction: BFCDirection::CCW,
+            transformation,
+            translation: columns[3],
+            filename: filename.to_string(),
+            line,
+            inverted: transformation.determinant() < 0.0,
+        });
+    }
+
+    let scene = if with_scene {
+        Some(read_scene(reader, &string)?)
+    } else {
+        None
+    };
+
+    // a part loaded on its own is its own entry file
+    if let (Some(part), true) = (loose_part, models.is_empty()) {
+        if part != entry_file {
+            if let Some(mut file) = files.remove(&part) {
+                file.name = entry_file.to_string();
+                files.insert(entry_file.to_string(), file);
+            }
+        }
+        let brick = LDrawBrick {
+            entry_file,
+            files,
+            colors,
+        };
+        return Ok((brick, scene));
+    }
+
+    let mut entry = match models.iter().position(|model| model.name == entry_file) {
+        Some(index) => models.remove(index),
+        None => empty_file(&entry_file),
+    };
+    for model in models {
+        entry.subfiles.push(LDrawSubfile {
+            color: Color { value: MAIN_COLOR },
+            bfc_direction: BFCDirection::CCW,
+            transformation: Matrix3::identity(),
+            translation: vec3(0.0, 0.0, 0.0),
+            filename: model.name.to_string(),
+            line: 0,
+            inverted: false,
+        });
+        files.insert(model.name.to_string(), model);
+    }
+    files.insert(entry_file.to_string(), entry);
+
+    let brick = LDrawBrick {
+        entry_file,
+        files,
+        colors,
+    };
+    Ok((brick, scene))
+}
+
+fn write_scene(payload: &ScenePayload, writer: &mut Writer, strings: &mut Strings) {
+    let range = |writer: &mut Writer, range: &PayloadRange| {
+        writer.u32(range.color);
+        writer.bytes(&[range.transparent as u8]);
+        writer.u32(range.start as u32);
+        writer.u32(range.count as u32);
+    };
+
+    writer.u32(payload.vertices.len() as u32 / 3);
+    for value in payload.vertices.iter() {
+        writer.bytes(&value.to_le_bytes());
+    }
+    writer.u32(payload.meshes.len() as u32);
+    for mesh in payload.meshes.iter() {
+        writer.u32(strings.index(&mesh.key.filename));
+        writer.u32(mesh.key.color);
+        writer.bytes(&[(mesh.key.winding == BFCDirection::CCW) as u8]);
+        for ranges in [&mesh.groups, &mesh.edges] {
+            writer.u32(ranges.len() as u32);
+            for group in ranges.iter() {
+                range(writer, group);
+            }
+        }
+    }
+    writer.u32(payload.instances.len() as u32);
+    for (mesh, matrix) in payload
+        .instances
+        .iter()
+        .zip(payload.matrices.chunks_exact(16))
+    {
+        writer.u32(*mesh);
+        for value in matrix {
+            writer.bytes(&value.to_le_bytes());
+        }
+    }
+}
+
+fn read_scene(
+    reader: &mut Reader,
+    string: &impl Fn(u32) -> Result<String, String>,
+) -> Result<Scene, String> {
+    let float = |reader: &mut Reader| Ok::<f32, String>(f32::from_le_bytes(reader.array::<4>()?));
+    let ranges = |reader: &mut Reader| -> Result<Vec<PayloadRange>, String> {
+        let mut ranges = Vec::new();
+        for _ in 0..reader.u32()? {
+            let color = reader.u32()?;
+            let [transparent] = reader.array::<1>()?;
+            ranges.push(PayloadRange {
+                color,
+                transparent: transparent != 0,
+                start: reader.u32()? as usize,
+                count: reader.u32()? as usize,
+            });
+        }
+        Ok(ranges)
+    };
+
+    let mut payload = ScenePayload::default();
+    let count = reader.u32()? as usize * 3;
+    payload.vertices.reserve(count.min(reader.remaining() / 4));
+    for _ in 0..count {
+        payload.vertices.push(float(reader)?);
+    }
+    for _ in 0..reader.u32()? {
+        let filename = string(reader.u32()?)?;
+        let color = reader.u32()?;
+        let [ccw] = reader.array::<1>()?;
+        payload.meshes.push(PayloadMesh {
+            key: MeshKey {
+                filename,
+                color,
+                winding: if ccw != 0 {
+                    BFCDirection::CCW
+                } else {
+                    BFCDirection::CW
+                },
+            },
+            groups: ranges(reader)?,
+            edges: ranges(reader)?,
+        });
+    }
+    for _ in 0..reader.u32()? {
+        payload.instances.push(reader.u32()?);
+        for _ in 0..16 {
+            payload.matrices.push(float(reader)?);
+        }
+    }
+
+    if !payload.fits() {
+        return Err("the scene refers past its buffers".to_string());
+    }
+    Ok(payload.into_scene())
+}
+
+fn empty_file(name: &str) -> LDrawFile {
+    LDrawFile {
+        name: name.to_string(),
+        title: String::new(),
+        author: LDrawAuthor {
+            name: String::new(),
+            username: None,
+        },
+        ldraw_type: None,
+        bfc_direction: BFCDirection::CCW,
+        lines: Vec::new(),
+        optional_lines: Vec::new(),
+        triangles: Vec::new(),
+        subfiles: Vec::new(),
+        warnings: Vec::new(),
+    }
+}
+
+// a part with all its subfiles expanded into part space. Main and edge colour stay
+// symbolic, so one flattened part serves every colour it is placed in
+// colour and positions of each primitive group, in the order they were written
+type Groups = Vec<(u32, Vec<Vector3<f32>>)>;
+
+#[derive(Default)]
+struct FlatPart {
+    triangles: Vec<(u32, [Vector3<f32>; 3])>,
+    lines: Vec<(u32, [Vector3<f32>; 2])>,
+    optional_lines: Vec<(u32, [Vector3<f32>; 4])>,
+}
+
+impl FlatPart {
+    // winds triangles the way the scene builder does, so they come out counter-clockwise
+    fn flatten(
+        &mut self,
+        brick: &LDrawBrick,
+        file: &LDrawFile,
+        color: u32,
+        matrix: Matrix4<f32>,
+        winding: &BFCDirection,
+    ) {
+        let point = |position: &Vector3<f32>| matrix.mul(position.extend(1.0)).truncate();
+        let resolve = |code: u32| inherit(&brick.colors, code, color);
+
+        for triangle in file.triangles.iter() {
+            let (y, z) = if matches!(winding, &BFCDirection::CCW) {
+                (triangle.y, triangle.z)
+            } else {
+                (triangle.z, triangle.y)
+            };
+            self.triangles.push((
+                resolve(triangle.color.value),
+                [point(&triangle.x), point(&y), point(&z)],
+            ));
+        }
+        for line in file.lines.iter() {
+            self.lines
+                .push((resolve(line.color.value), [point(&line.x), point(&line.y)]));
+        }
+        for line in file.optional_lines.iter() {
+            self.optional_lines.push((
+                resolve(line.color.value),
+                [
+                    point(&line.x),
+                    point(&line.y),
+                    point(&line.ox),
+                    point(&line.oy),
+                ],
+            ));
+        }
+
+        for reference in references(brick, file, color, winding) {
+            self.flatten(
+                brick,
+                reference.file,
+                resolve(reference.subfile.color.value),
+                matrix.mul(reference.matrix()),
+                &reference.winding,
+            );
+        }
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        // only vertices that are exactly the same are shared
+        let mut vertices: Vec<Vector3<f32>> = Vec::new();
+        let mut lookup: HashMap<[u32; 3], u32> = HashMap::new();
+        let mut index = |position: &Vector3<f32>| {
+            let bits = [position.x, position.y, position.z].map(f32::to_bits);
+            *lookup.entry(bits).or_insert_with(|| {
+                vertices.push(*position);
+                vertices.len() as u32 - 1
+            })
+        };
+        let triangles = groups(&self.triangles, &mut index);
+        let lines = groups(&self.lines, &mut index);
+        let optional_lines = groups(&self.optional_lines, &mut index);
+
+        writer.u32(vertices.len() as u32);
+        for vertex in vertices.iter() {
+            writer.vector(*vertex);
+        }
+        let wide = vertices.len() > u16::MAX as usize + 1;
+        writer.bytes(&[if wide { 4 } else { 2 }]);
+        for groups in [triangles, lines, optional_lines] {
+            writer.u32(groups.len() as u32);
+            for (color, indices) in groups {
+                writer.u32(color);
+                writer.u32(indices.len() as u32);
+                for index in indices {
+                    if wide {
+                        writer.u32(index);
+                    } else {
+                        writer.bytes(&(index as u16).to_le_bytes());
+                    }
+                }
+            }
+        }
+    }
+
+    fn read(reader: &mut Reader, file: &mut LDrawFile) -> Result<(), String> {
+        let count = reader.u32()? as usize;
+        let mut vertices = Vec::with_capacity(count.min(reader.remaining() / 12));
+        for _ in 0..count {
+            vertices.push(reader.vector()?);
+        }
+        let [width] = reader.array::<1>()?;
+
+        let read_groups = |reader: &mut Reader, size: usize| -> Result<Groups, String> {
+            let mut groups = Vec::new();
+            for _ in 0..reader.u32()? {
+                let color = reader.u32()?;
+                let count = reader.u32()? as usize;
+                if !count.is_multiple_of(size) {
+                    return Err(format!("{} indices do not make whole primitives", count));
+                }
+                let mut positions = Vec::with_capacity(count.min(reader.remaining()));
+                for _ in 0..count {
+                    let index = if width == 4 {
+                        reader.u32()? as usize
+                    } else {
+                        let [low, high] = reader.array::<2>()?;
+                        u16::from_le_bytes([low, high]) as usize
+                    };
+                    let vertex = vertices
+                        .get(index)
+                        .ok_or_else(|| format!("vertex {} is out of range", index))?;
+                    positions.push(*vertex);
+                }
+                groups.push((color, positions));
+            }
+            Ok(groups)
+        };
+
+        for (code, positions) in read_groups(reader, 3)? {
+            for triangle in positions.chunks_exact(3) {
+                file.triangles.push(LDrawTriangle {
+                    color: Color { value: code },
+                    x: triangle[0],
+                    y: triangle[1],
+                    z: triangle[2],
+                });
+            }
+        }
+        for (code, positions) in read_groups(reader, 2)? {
+            for line in positions.chunks_exact(2) {
+                file.lines.push(LDrawContour {
+                    color: Color { value: code },
+                    x: line[0],
+                    y: line[1],
+                });
+            }
+        }
+        for (code, positions) in read_groups(reader, 4)? {
+            for line in positions.chunks_exact(4) {
+                file.optional_lines.push(LDrawOptionalContour {
+                    color: Color { value: code },
+                    x: line[0],
+                    y: line[1],
+                    ox: line[2],
+                    oy: line[3],
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// the indices of each colour, in the order the colours first show up
+fn groups<const N: usize>(
+    primitives: &[(u32, [Vector3<f32>; N])],
+    index: &mut impl FnMut(&Vector3<f32>) -> u32,
+) -> Vec<(u32, Vec<u32>)> {
+    let mut groups: Vec<(u32, Vec<u32>)> = Vec::new();
+    for (color, positions) in primitives.iter() {
+        let position = match groups.iter().position(|(other, _)| other == color) {
+            Some(position) => position,
+            None => {
+                groups.push((*color, Vec::new()));
+                groups.len() - 1
+            }
+        };
+        groups[position].1.extend(positions.iter().map(&mut *index));
+    }
+    groups
+}
+
+// like ColorTable::resolve_code, except that edge colour inside main colour stays edge
+// colour until the part is placed
+fn inherit(colors: &ColorTable, code: u32, parent: u32) -> u32 {
+    if code == EDGE_COLOR && parent == MAIN_COLOR {
+        EDGE_COLOR
+    } else {
+        colors.resolve_code(code, parent)
+    }
+}
+
+#[derive(Default)]
+struct Strings {
+    list: Vec<String>,
+    lookup: HashMap<String, u32>,
+}
+
+impl Strings {
+    fn index(&mut self, string: &str) -> u32 {
+        if let Some(index) = self.lookup.get(string) {
+            return *index;
+        }
+        self.list.push(string.to_string());
+        let index = self.list.len() as u32 - 1;
+        self.lookup.insert(string.to_string(), index);
+        index
+    }
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn vector(&mut self, vector: Vector3<f32>) {
+        for axis in 0..3 {
+            self.bytes(&vector[axis].to_le_bytes());
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.at
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if length > self.remaining() {
+            return Err(format!(
+                "the file ends {} bytes early",
+                length - self.remaining()
+            ));
+        }
+        let bytes = &self.bytes[self.at..self.at + length];
+        self.at += length;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array::<4>()?))
+    }
+
+    fn vector(&mut self) -> Result<Vector3<f32>, String> {
+        let mut vector = vec3(0.0, 0.0, 0.0);
+        for axis in 0..3 {
+            vector[axis] = f32::from_le_bytes(self.array::<4>()?);
+        }
+        Ok(vector)
+    }
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+// CRC-32 as used by zip and PNG
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
@@ -0,0 +1,123 @@
+// Bakes a model or part from an LDraw library into the binary format the viewer loads
+// with a single request:
+//
+//   bake <ldraw library> <model file or part name> [output]
+//
+// The output defaults to the name of the model with an .ldrb extension, which is what
+// the viewer asks for when it prefers baked bricks. The server hands out the files in
+// $BAKED_DIR, or baked/ in the LDraw library, as /ldraw/baked.
+
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use ldraw_renderer::{
+    baked,
+    parser::{
+        colors::ColorTable,
+        part::{parse_file, LDrawBrick, LDrawFile},
+    },
+};
+
+// where subfiles are looked for, after the directory of the model itself
+const LIBRARY_FOLDERS: [&str; 5] = ["parts", "p", "models", "unofficial/parts", "unofficial/p"];
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 || args.len() > 4 {
+        eprintln!("usage: bake <ldraw library> <model file or part name> [output]");
+        process::exit(2);
+    }
+
+    let library = PathBuf::from(&args[1]);
+    let model = PathBuf::from(&args[2]);
+    if let Err(message) = run(&library, &model, args.get(3).map(PathBuf::from)) {
+        eprintln!("bake: {}", message);
+        process::exit(1);
+    }
+}
+
+fn run(library: &Path, model: &Path, output: Option<PathBuf>) -> Result<(), String> {
+    let mut colors = ColorTable::new();
+    let ldconfig = library.join("LDConfig.ldr");
+    parse_file("LDConfig.ldr", read_lines(&ldconfig)?, &mut colors)
+        .map_err(|error| error.to_string())?;
+
+    let entry_file = model
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("{} is not a file name", model.display()))?
+        .to_string();
+    let mut folders = Vec::new();
+    if let Some(parent) = model.parent() {
+        folders.push(parent.to_path_buf());
+    }
+    folders.extend(LIBRARY_FOLDERS.iter().map(|folder| library.join(folder)));
+
+    let mut files: HashMap<String, LDrawFile> = HashMap::new();
+    let mut queue = VecDeque::from([entry_file.to_string()]);
+    while let Some(name) = queue.pop_front() {
+        if files.contains_key(&name) {
+            continue;
+        }
+        let path = match find(&folders, &name) {
+            Some(path) => path,
+            None if name == entry_file => return Err(format!("{} was not found", name)),
+            None => {
+                eprintln!("bake: missing subfile {}", name);
+                continue;
+            }
+        };
+
+        let mut file = parse_file(&name, read_lines(&path)?, &mut colors)
+            .map_err(|error| error.to_string())?;
+        for warning in file.warnings.iter() {
+            eprintln!("bake: {}", warning);
+        }
+        // references use the name they were written with, whatever the header says
+        file.name = name.to_string();
+        queue.extend(
+            file.subfiles
+                .iter()
+                .map(|subfile| subfile.filename.to_string()),
+        );
+        files.insert(name, file);
+    }
+
+    let count = files.len();
+    let brick = LDrawBrick {
+        entry_file: entry_file.to_string(),
+        files,
+        colors,
+    };
+    let bytes = baked::encode_with_scene(&brick, &baked::baked_scene(&brick));
+
+    let output = output.unwrap_or_else(|| Path::new(&entry_file).with_extension("ldrb"));
+    fs::write(&output, &bytes).map_err(|error| format!("{}: {}", output.display(), error))?;
+    println!(
+        "baked {} files into {} ({} bytes)",
+        count,
+        output.display(),
+        bytes.len()
+    );
+    Ok(())
+}
+
+// tries the name as written and in lower case, like the official library is stored
+fn find(folders: &[PathBuf], name: &str) -> Option<PathBuf> {
+    let name = name.replace('\\', "/");
+    folders
+        .iter()
+        .flat_map(|folder| [folder.join(&name), folder.join(name.to_lowercase())])
+        .find(|path| path.is_file())
+}
+
+fn read_lines(path: &Path) -> Result<Vec<String>, String> {
+    let bytes = fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+    Ok(String::from_utf8_lossy(&bytes)
+        .lines()
+        .map(|line| line.to_string())
+        .collect())
+}
@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::ops::Mul;
+
+use three_d::{vec3, InnerSpace, Matrix3, Matrix4, Vector3};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    coordinates::OutputFrame,
+    parser::part::{LDrawBrick, LDrawFile},
+    scene::{part_instances, subfile_matrix, PartInstance},
+};
+
+// LDraw unit sizes of the basic building blocks
+const LDU_PER_STUD: f32 = 20.0;
+const LDU_PER_BRICK: f32 = 24.0;
+const LDU_PER_PLATE: f32 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Self {
+            min: vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Vector3<f32>>) -> Self {
+        let mut aabb = Self::empty();
+        for point in points {
+            aabb.extend(point);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn extend(&mut self, point: &Vector3<f32>) {
+        self.min = vec3(
+            self.min.x.min(point.x),
+            self.min.y.min(point.y),
+            self.min.z.min(point.z),
+        );
+        self.max = vec3(
+            self.max.x.max(point.x),
+            self.max.y.max(point.y),
+            self.max.z.max(point.z),
+        );
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        if other.is_empty() {
+            return *self;
+        }
+        let mut aabb = *self;
+        aabb.extend(&other.min);
+        aabb.extend(&other.max);
+        aabb
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        if self.is_empty() {
+            vec3(0.0, 0.0, 0.0)
+        } else {
+            self.max - self.min
+        }
+    }
+
+    // radius of the bounding sphere around the box
+    pub fn radius(&self) -> f32 {
+        self.size().magnitude() * 0.5
+    }
+
+    pub fn corners(&self) -> [Vector3<f32>; 8] {
+        [
+            vec3(self.min.x, self.min.y, self.min.z),
+            vec3(self.max.x, self.min.y, self.min.z),
+            vec3(self.min.x, self.max.y, self.min.z),
+            vec3(self.max.x, self.max.y, self.min.z),
+            vec3(self.min.x, self.min.y, self.max.z),
+            vec3(self.max.x, self.min.y, self.max.z),
+            vec3(self.min.x, self.max.y, self.max.z),
+            vec3(self.max.x, self.max.y, self.max.z),
+        ]
+    }
+
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        let corners: Vec<Vector3<f32>> = self
+            .corners()
+            .iter()
+            .map(|corner| matrix.mul(corner.extend(1.0)).truncate())
+            .collect();
+        Aabb::from_points(corners.iter())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Obb {
+    pub center: Vector3<f32>,
+    // unit length axes
+    pub axes: [Vector3<f32>; 3],
+    pub half_extents: Vector3<f32>,
+}
+
+impl Obb {
+    pub fn from_aabb(aabb: &Aabb, matrix: &Matrix4<f32>) -> Obb {
+        let center = matrix.mul(aabb.center().extend(1.0)).truncate();
+        let half_size = aabb.size() * 0.5;
+        let x = matrix.x.truncate();
+        let y = matrix.y.truncate();
+        let z = matrix.z.truncate();
+
+        Obb {
+            center,
+            axes: [
+                normalize_or(x, Vector3::unit_x()),
+                normalize_or(y, Vector3::unit_y()),
+                normalize_or(z, Vector3::unit_z()),
+            ],
+            half_extents: vec3(
+                half_size.x * x.magnitude(),
+                half_size.y * y.magnitude(),
+                half_size.z * z.magnitude(),
+            ),
+        }
+    }
+
+    // fits a box along the principal axes of the given points
+    pub fn from_points(points: &[Vector3<f32>]) -> Obb {
+        if points.is_empty() {
+            return Obb {
+                center: vec3(0.0, 0.0, 0.0),
+                axes: [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()],
+                half_extents: vec3(0.0, 0.0, 0.0),
+            };
+        }
+
+        let mean = points
+            .iter()
+            .fold(vec3(0.0, 0.0, 0.0), |sum, point| sum + point)
+            / points.len() as f32;
+        let zero = vec3(0.0, 0.0, 0.0);
+        let mut covariance = Matrix3::from_cols(zero, zero, zero);
+        for point in points {
+            let d = point - mean;
+            covariance.x += d * d.x;
+            covariance.y += d * d.y;
+            covariance.z += d * d.z;
+        }
+        let axes = principal_axes(covariance / points.len() as f32);
+
+        let mut min = vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for point in points {
+            let d = point - mean;
+            for (i, axis) in axes.iter().enumerate() {
+                let projected = d.dot(*axis);
+                min[i] = min[i].min(projected);
+                max[i] = max[i].max(projected);
+            }
+        }
+
+        let local_center = (min + max) * 0.5;
+        Obb {
+            center: mean
+                + axes[0] * local_center.x
+                + axes[1] * local_center.y
+                + axes[2] * local_center.z,
+            axes,
+            half_extents: (max - min) * 0.5,
+        }
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.half_extents * 2.0
+    }
+
+    pub fn corners(&self) -> [Vector3<f32>; 8] {
+        let mut corners = [self.center; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            for axis in 0..3 {
+                let sign = if i & (1 << axis) == 0 { -1.0 } else { 1.0 };
+                *corner += self.axes[axis] * self.half_extents[axis] * sign;
+            }
+        }
+        corners
+    }
+
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Obb {
+        let local = Aabb {
+            min: -self.half_extents,
+            max: self.half_extents,
+        };
+        let basis = Matrix4::from(Matrix3::from_cols(self.axes[0], self.axes[1], self.axes[2]));
+        Obb::from_aabb(
+            &local,
+            &matrix
+                .mul(Matrix4::from_translation(self.center))
+                .mul(basis),
+        )
+    }
+}
+
+fn normalize_or(vector: Vector3<f32>, fallback: Vector3<f32>) -> Vector3<f32> {
+    if vector.magnitude2() > 0.0 {
+        vector.normalize()
+    } else {
+        fallback
+    }
+}
+
+// eigenvectors of a symmetric matrix via Jacobi rotations
+fn principal_axes(matrix: Matrix3<f32>) -> [Vector3<f32>; 3] {
+    let mut a = [[0.0f32; 3]; 3];
+    for (i, row) in a.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = matrix[j][i];
+        }
+    }
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0f32]];
+
+    for _ in 0..32 {
+        let (p, q) = [(0, 1), (0, 2), (1, 2)]
+            .iter()
+            .copied()
+            .max_by(|x, y| a[x.0][x.1].abs().total_cmp(&a[y.0][y.1].abs()))
+            .unwrap();
+        if a[p][q].abs() < 1e-9 {
+            break;
+        }
+
+        let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+        let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+        let c = 1.0 / (t * t + 1.0).sqrt();
+        let s = t * c;
+
+        for k in 0..3 {
+            let akp = a[k][p];
+            let akq = a[k][q];
+            a[k][p] = c * akp - s * akq;
+            a[k][q] = s * akp + c * akq;
+        }
+        for k in 0..3 {
+            let apk = a[p][k];
+            let aqk = a[q][k];
+            a[p][k] = c * apk - s * aqk;
+            a[q][k] = s * apk + c * aqk;
+        }
+        for row in v.iter_mut() {
+            let vp = row[p];
+            let vq = row[q];
+            row[p] = c * vp - s * vq;
+            row[q] = s * vp + c * vq;
+        }
+    }
+
+    let x = vec3(v[0][0], v[1][0], v[2][0]).normalize();
+    let y = vec3(v[0][1], v[1][1], v[2][1]).normalize();
+    // keep the basis right-handed
+    [x, y, x.cross(y)]
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BoundsCache {
+    files: HashMap<String, Aabb>,
+}
+
+impl BoundsCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // bounds of a file in its own LDraw space, subfiles included
+    pub fn file(&mut self, brick: &LDrawBrick, name: &str) -> Aabb {
+        if let Some(aabb) = self.files.get(name) {
+            return *aabb;
+        }
+
+        let aabb = match brick.files.get(name) {
+            Some(file) => self.compute(brick, file),
+            None => Aabb::empty(),
+        };
+        self.files.insert(name.to_string(), aabb);
+        aabb
+    }
+
+    fn compute(&mut self, brick: &LDrawBrick, file: &LDrawFile) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for triangle in file.triangles.iter() {
+            aabb.extend(&triangle.x);
+            aabb.extend(&triangle.y);
+            aabb.extend(&triangle.z);
+        }
+        for line in file.lines.iter() {
+            aabb.extend(&line.x);
+            aabb.extend(&line.y);
+        }
+        for subfile in file.subfiles.iter() {
+            let child = self.file(brick, &subfile.filename);
+            aabb = aabb.union(&child.transform(&subfile_matrix(subfile)));
+        }
+        aabb
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PartBounds {
+    pub instance: PartInstance,
+    // both in LDraw space
+    pub aabb: Aabb,
+    pub obb: Obb,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dimensions {
+    // extents along the LDraw axes in the frame's units
+    pub width: f32,
+    pub height: f32,
+    pub depth: f32,
+    pub studs_x: f32,
+    pub studs_z: f32,
+    pub bricks: f32,
+    pub plates: f32,
+    // the box in the frame's coordinates
+    pub min_x: f32,
+    pub min_y: f32,
+    pub min_z: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+    pub max_z: f32,
+}
+
+impl Dimensions {
+    pub fn new(size: Vector3<f32>, aabb: &Aabb, frame: &OutputFrame) -> Self {
+        let framed = aabb.transform(&frame.matrix());
+        Self {
+            width: frame.length(size.x),
+            height: frame.length(size.y),
+            depth: frame.length(size.z),
+            studs_x: size.x / LDU_PER_STUD,
+            studs_z: size.z / LDU_PER_STUD,
+            bricks: size.y / LDU_PER_BRICK,
+            plates: size.y / LDU_PER_PLATE,
+            min_x: framed.min.x,
+            min_y: framed.min.y,
+            min_z: framed.min.z,
+            max_x: framed.max.x,
+            max_y: framed.max.y,
+            max_z: framed.max.z,
+        }
+    }
+
+    pub fn from_aabb(aabb: &Aabb, frame: &OutputFrame) -> Self {
+        Self::new(aabb.size(), aabb, frame)
+    }
+
+    pub fn from_obb(obb: &Obb, frame: &OutputFrame) -> Self {
+        Self::new(obb.size(), &Aabb::from_points(obb.corners().iter()), frame)
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct ModelBounds {
+    frame: OutputFrame,
+    files: BoundsCache,
+    model: Aabb,
+    model_oriented: Obb,
+    parts: Vec<PartBounds>,
+}
+
+impl ModelBounds {
+    pub fn compute(brick: &LDrawBrick, frame: OutputFrame) -> Self {
+        let mut files = BoundsCache::new();
+        let parts: Vec<PartBounds> = part_instances(brick)
+            .into_iter()
+            .map(|instance| {
+                let local = files.file(brick, &instance.filename);
+                PartBounds {
+                    aabb: local.transform(&instance.transformation),
+                    obb: Obb::from_aabb(&local, &instance.transformation),
+                    instance,
+                }
+            })
+            .collect();
+
+        let model = parts
+            .iter()
+            .fold(Aabb::empty(), |aabb, part| aabb.union(&part.aabb));
+        let corners: Vec<Vector3<f32>> = parts
+            .iter()
+            .filter(|part| !part.aabb.is_empty())
+            .flat_map(|part| part.obb.corners().to_vec())
+            .collect();
+
+        Self {
+            frame,
+            files,
+            model,
+            model_oriented: Obb::from_points(&corners),
+            parts,
+        }
+    }
+
+    // LDraw space bounds of the whole model
+    pub fn model_aabb(&self) -> &Aabb {
+        &self.model
+    }
+
+    pub fn model_obb(&self) -> &Obb {
+        &self.model_oriented
+    }
+
+    pub fn parts(&self) -> &[PartBounds] {
+        &self.parts
+    }
+
+    // the model bounds in the output frame
+    pub fn framed_aabb(&self) -> Aabb {
+        self.model.transform(&self.frame.matrix())
+    }
+}
+
+#[wasm_bindgen]
+impl ModelBounds {
+    pub fn model(&self) -> Dimensions {
+        Dimensions::from_aabb(&self.model, &self.frame)
+    }
+
+    pub fn model_oriented(&self) -> Dimensions {
+        Dimensions::from_obb(&self.model_oriented, &self.frame)
+    }
+
+    pub fn part_count(&self) -> usize {
+        self.parts.len()
+    }
+
+    pub fn part_name(&self, index: usize) -> Option<String> {
+        self.parts
+            .get(index)
+            .map(|part| part.instance.filename.to_string())
+    }
+
+    pub fn part(&self, index: usize) -> Option<Dimensions> {
+        self.parts
+            .get(index)
+            .map(|part| Dimensions::from_aabb(&part.aabb, &self.frame))
+    }
+
+    pub fn part_oriented(&self, index: usize) -> Option<Dimensions> {
+        self.parts
+            .get(index)
+            .map(|part| Dimensions::from_obb(&part.obb, &self.frame))
+    }
+
+    pub fn file(&self, name: &str) -> Option<Dimensions> {
+        self.files
+            .files
+            .get(name)
+            .map(|aabb| Dimensions::from_aabb(aabb, &self.frame))
+    }
+}
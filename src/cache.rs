@@ -0,0 +1,537 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs;
+use std::future::{ready, Future};
+use std::path::PathBuf;
+use std::pin::Pin;
+
+use js_sys::{Array, Object, Promise, Reflect, Uint8Array};
+use serde_json::{json, Value};
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    IdbDatabase, IdbObjectStore, IdbRequest, IdbTransactionMode, Window, WorkerGlobalScope,
+};
+
+use crate::{
+    baked::{self, crc32},
+    errors::{describe_js_error, LoadError, LoadErrorKind},
+    parser::part::LDrawBrick,
+};
+
+pub type CacheFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, LoadError>> + 'a>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheEntry {
+    pub key: String,
+    pub bytes: usize,
+    // milliseconds since the epoch of the last read or write, for eviction
+    pub used: u64,
+}
+
+// where cached parts are kept, the futures of the native backends are ready right away
+pub trait CacheStorage {
+    fn get<'a>(&'a self, key: &'a str) -> CacheFuture<'a, Option<Vec<u8>>>;
+    fn put<'a>(&'a self, entry: CacheEntry, bytes: Vec<u8>) -> CacheFuture<'a, ()>;
+    // records a read without writing the bytes again
+    fn touch<'a>(&'a self, entry: CacheEntry) -> CacheFuture<'a, ()>;
+    fn remove<'a>(&'a self, key: &'a str) -> CacheFuture<'a, ()>;
+    fn entries(&self) -> CacheFuture<'_, Vec<CacheEntry>>;
+}
+
+fn cache_error(message: impl Into<String>) -> LoadError {
+    LoadError::new(LoadErrorKind::Cache, message)
+}
+
+type InvalidationListener = Box<dyn Fn(&str, usize)>;
+
+// parsed parts across sessions, evicting the least recently used entries once the
+// stored bytes go over the budget
+pub struct PartCache<S> {
+    storage: S,
+    library: RefCell<String>,
+    max_bytes: usize,
+    clock: Cell<u64>,
+    listeners: RefCell<Vec<InvalidationListener>>,
+}
+
+impl<S: CacheStorage> PartCache<S> {
+    pub fn new(storage: S, library: &str, max_bytes: usize) -> Self {
+        Self {
+            storage,
+            library: RefCell::new(library.to_string()),
+            max_bytes,
+            clock: Cell::new(0),
+            listeners: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn library(&self) -> String {
+        self.library.borrow().to_string()
+    }
+
+    // stays valid whatever the library version, as long as the content is the same
+    pub fn content_key(path: &str, content: &[u8]) -> String {
+        format!("{}#{:08x}", path, crc32(content))
+    }
+
+    // dropped when the library is updated
+    pub fn library_key(&self, path: &str) -> String {
+        format!("{}@{}", path, self.library.borrow())
+    }
+
+    // called with the new version and the number of entries dropped for it
+    pub fn on_invalidate(&self, listener: impl Fn(&str, usize) + 'static) {
+        self.listeners.borrow_mut().push(Box::new(listener));
+    }
+
+    // drops the entries keyed by any other library version, also the ones left over
+    // from earlier sessions
+    pub async fn update_library(&self, library: &str) -> Result<usize, LoadError> {
+        self.library.replace(library.to_string());
+        let current = format!("@{}", library);
+
+        let mut removed = 0;
+        for entry in self.storage.entries().await? {
+            if entry.key.contains('@') && !entry.key.ends_with(&current) {
+                self.storage.remove(&entry.key).await?;
+                removed += 1;
+            }
+        }
+
+        for listener in self.listeners.borrow().iter() {
+            listener(library, removed);
+        }
+        Ok(removed)
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, LoadError> {
+        let bytes = self.storage.get(key).await?;
+        if let Some(bytes) = &bytes {
+            self.storage
+                .touch(CacheEntry {
+                    key: key.to_string(),
+                    bytes: bytes.len(),
+                    used: self.tick(),
+                })
+                .await?;
+        }
+        Ok(bytes)
+    }
+
+    pub async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), LoadError> {
+        // it would only push everything else out and then itself
+        if bytes.len() > self.max_bytes {
+            return Ok(());
+        }
+
+        let entry = CacheEntry {
+            key: key.to_string(),
+            bytes: bytes.len(),
+            used: self.tick(),
+        };
+        self.storage.put(entry, bytes).await?;
+        self.evict().await
+    }
+
+    pub async fn remove(&self, key: &str) -> Result<(), LoadError> {
+        self.storage.remove(key).await
+    }
+
+    // a brick baked by an older build is dropped and reported as missing
+    pub async fn get_brick(&self, key: &str) -> Result<Option<LDrawBrick>, LoadError> {
+        let bytes = match self.get(key).await? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        match baked::decode(&bytes) {
+            Ok(brick) => Ok(Some(brick)),
+            Err(error) if error.kind == LoadErrorKind::Stale => {
+                self.storage.remove(key).await?;
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    pub async fn put_brick(&self, key: &str, brick: &LDrawBrick) -> Result<(), LoadError> {
+        self.put(key, baked::encode(brick)).await
+    }
+
+    async fn evict(&self) -> Result<(), LoadError> {
+        let mut entries = self.storage.entries().await?;
+        let mut total: usize = entries.iter().map(|entry| entry.bytes).sum();
+        entries.sort_by_key(|entry| entry.used);
+        for entry in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            self.storage.remove(&entry.key).await?;
+            total -= entry.bytes;
+        }
+        Ok(())
+    }
+
+    // the wall clock, but never the same value twice so writes in the same
+    // millisecond keep their order
+    fn tick(&self) -> u64 {
+        let now = chrono::Utc::now().timestamp_millis().max(0) as u64;
+        let tick = now.max(self.clock.get() + 1);
+        self.clock.set(tick);
+        tick
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: RefCell<HashMap<String, (CacheEntry, Vec<u8>)>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CacheStorage for MemoryStorage {
+    fn get<'a>(&'a self, key: &'a str) -> CacheFuture<'a, Option<Vec<u8>>> {
+        let bytes = self
+            .entries
+            .borrow()
+            .get(key)
+            .map(|(_, bytes)| bytes.clone());
+        Box::pin(ready(Ok(bytes)))
+    }
+
+    fn put<'a>(&'a self, entry: CacheEntry, bytes: Vec<u8>) -> CacheFuture<'a, ()> {
+        self.entries
+            .borrow_mut()
+            .insert(entry.key.to_string(), (entry, bytes));
+        Box::pin(ready(Ok(())))
+    }
+
+    fn touch<'a>(&'a self, entry: CacheEntry) -> CacheFuture<'a, ()> {
+        if let Some((existing, _)) = self.entries.borrow_mut().get_mut(&entry.key) {
+            *existing = entry;
+        }
+        Box::pin(ready(Ok(())))
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> CacheFuture<'a, ()> {
+        self.entries.borrow_mut().remove(key);
+        Box::pin(ready(Ok(())))
+    }
+
+    fn entries(&self) -> CacheFuture<'_, Vec<CacheEntry>> {
+        let entries = self
+            .entries
+            .borrow()
+            .values()
+            .map(|(entry, _)| entry.clone())
+            .collect();
+        Box::pin(ready(Ok(entries)))
+    }
+}
+
+const FS_INDEX: &str = "index.json";
+
+// one file per entry and an index.json with the sizes and use times, for native tools
+// and tests
+#[derive(Debug)]
+pub struct FsStorage {
+    directory: PathBuf,
+    index: RefCell<HashMap<String, CacheEntry>>,
+}
+
+impl FsStorage {
+    pub fn open(directory: impl Into<PathBuf>) -> Result<Self, LoadError> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory).map_err(|error| cache_error(error.to_string()))?;
+
+        // a missing or broken index starts an empty cache
+        let index = fs::read(directory.join(FS_INDEX))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok())
+            .and_then(|value| value.as_array().cloned())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|entry| {
+                Some(CacheEntry {
+                    key: entry["key"].as_str()?.to_string(),
+                    bytes: entry["bytes"].as_u64()? as usize,
+                    used: entry["used"].as_u64()?,
+                })
+            })
+            .map(|entry| (entry.key.to_string(), entry))
+            .collect();
+
+        Ok(Self {
+            directory,
+            index: RefCell::new(index),
+        })
+    }
+
+    // keys hold slashes and other characters file systems dislike
+    fn path(&self, key: &str) -> PathBuf {
+        let name: String = key
+            .bytes()
+            .map(|byte| match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' => (byte as char).to_string(),
+                _ => format!("_{:02x}", byte),
+            })
+            .collect();
+        self.directory.join(format!("{}.bin", name))
+    }
+
+    fn write_index(&self) -> Result<(), LoadError> {
+        let entries: Vec<Value> = self
+            .index
+            .borrow()
+            .values()
+            .map(|entry| json!({ "key": entry.key, "bytes": entry.bytes, "used": entry.used }))
+            .collect();
+        fs::write(
+            self.directory.join(FS_INDEX),
+            serde_json::to_vec(&entries).unwrap(),
+        )
+        .map_err(|error| cache_error(error.to_string()))
+    }
+}
+
+impl CacheStorage for FsStorage {
+    fn get<'a>(&'a self, key: &'a str) -> CacheFuture<'a, Option<Vec<u8>>> {
+        let bytes = if self.index.borrow().contains_key(key) {
+            fs::read(self.path(key)).ok()
+        } else {
+            None
+        };
+        Box::pin(ready(Ok(bytes)))
+    }
+
+    fn put<'a>(&'a self, entry: CacheEntry, bytes: Vec<u8>) -> CacheFuture<'a, ()> {
+        let result = fs::write(self.path(&entry.key), bytes)
+            .map_err(|error| cache_error(error.to_string()))
+            .and_then(|_| {
+                self.index.borrow_mut().insert(entry.key.to_string(), entry);
+                self.write_index()
+            });
+        Box::pin(ready(result))
+    }
+
+    fn touch<'a>(&'a self, entry: CacheEntry) -> CacheFuture<'a, ()> {
+        let known = match self.index.borrow_mut().get_mut(&entry.key) {
+            Some(existing) => {
+                *existing = entry;
+                true
+            }
+            None => false,
+        };
+        Box::pin(ready(if known { self.write_index() } else { Ok(()) }))
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> CacheFuture<'a, ()> {
+        let known = self.index.borrow_mut().remove(key).is_some();
+        if known {
+            // the file may already be gone, the index is what counts
+            fs::remove_file(self.path(key)).ok();
+        }
+        Box::pin(ready(if known { self.write_index() } else { Ok(()) }))
+    }
+
+    fn entries(&self) -> CacheFuture<'_, Vec<CacheEntry>> {
+        let entries = self.index.borrow().values().cloned().collect();
+        Box::pin(ready(Ok(entries)))
+    }
+}
+
+const IDB_VERSION: u32 = 1;
+const IDB_PARTS: &str = "parts";
+const IDB_ENTRIES: &str = "entries";
+
+// the bytes and their { key, bytes, used } entries in two object stores, so the
+// entries can be listed without reading every part
+#[derive(Debug, Clone)]
+pub struct IndexedDbStorage {
+    db: IdbDatabase,
+}
+
+impl IndexedDbStorage {
+    pub async fn open(name: &str) -> Result<Self, LoadError> {
+        // bricks are also loaded from the worker, which has no window
+        let global = js_sys::global();
+        let factory = if let Some(window) = global.dyn_ref::<Window>() {
+            window.indexed_db()
+        } else if let Some(scope) = global.dyn_ref::<WorkerGlobalScope>() {
+            scope.indexed_db()
+        } else {
+            Ok(None)
+        }
+        .ok()
+        .flatten()
+        .ok_or_else(|| cache_error("IndexedDB is not available"))?;
+        let open = factory
+            .open_with_u32(name, IDB_VERSION)
+            .map_err(|error| cache_error(describe_js_error(&error)))?;
+
+        let upgrade = Closure::once_into_js({
+            let open = open.clone();
+            move || {
+                if let Ok(db) = open.result() {
+                    let db: IdbDatabase = db.unchecked_into();
+                    for store in [IDB_PARTS, IDB_ENTRIES] {
+                        if !db.object_store_names().contains(store) {
+                            db.create_object_store(store).ok();
+                        }
+                    }
+                }
+            }
+        });
+        open.set_onupgradeneeded(Some(upgrade.unchecked_ref()));
+
+        let db = request(&open).await?;
+        Ok(Self {
+            db: db.unchecked_into(),
+        })
+    }
+
+    fn stores(
+        &self,
+        mode: IdbTransactionMode,
+    ) -> Result<(IdbObjectStore, IdbObjectStore), LoadError> {
+        let names = Array::of2(
+            &JsValue::from_str(IDB_PARTS),
+            &JsValue::from_str(IDB_ENTRIES),
+        );
+        let js_error = |error: JsValue| cache_error(describe_js_error(&error));
+        let transaction = self
+            .db
+            .transaction_with_str_sequence_and_mode(&names, mode)
+            .map_err(js_error)?;
+        Ok((
+            transaction.object_store(IDB_PARTS).map_err(js_error)?,
+            transaction.object_store(IDB_ENTRIES).map_err(js_error)?,
+        ))
+    }
+}
+
+fn entry_to_js(entry: &CacheEntry) -> JsValue {
+    let object = Object::new();
+    for (key, value) in [
+        ("key", JsValue::from_str(&entry.key)),
+        ("bytes", JsValue::from_f64(entry.bytes as f64)),
+        ("used", JsValue::from_f64(entry.used as f64)),
+    ] {
+        Reflect::set(&object, &JsValue::from_str(key), &value).unwrap();
+    }
+    object.into()
+}
+
+fn entry_from_js(value: &JsValue) -> Option<CacheEntry> {
+    let field = |key: &str| Reflect::get(value, &JsValue::from_str(key)).ok();
+    Some(CacheEntry {
+        key: field("key")?.as_string()?,
+        bytes: field("bytes")?.as_f64()? as usize,
+        used: field("used")?.as_f64()? as u64,
+    })
+}
+
+// waits for a request to succeed or fail, IndexedDB only has callbacks
+async fn request(request: &IdbRequest) -> Result<JsValue, LoadError> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        let success = Closure::once_into_js(move || {
+            resolve.call0(&JsValue::NULL).ok();
+        });
+        let failure = Closure::once_into_js(move || {
+            reject.call0(&JsValue::NULL).ok();
+        });
+        request.set_onsuccess(Some(success.unchecked_ref()));
+        request.set_onerror(Some(failure.unchecked_ref()));
+    });
+    let outcome = JsFuture::from(promise).await;
+    request.set_onsuccess(None);
+    request.set_onerror(None);
+
+    match outcome {
+        Ok(_) => request
+            .result()
+            .map_err(|error| cache_error(describe_js_error(&error))),
+        Err(_) => Err(cache_error(
+            request
+                .error()
+                .ok()
+                .flatten()
+                .map(|error| error.message())
+                .unwrap_or_else(|| "an IndexedDB request failed".to_string()),
+        )),
+    }
+}
+
+impl CacheStorage for IndexedDbStorage {
+    fn get<'a>(&'a self, key: &'a str) -> CacheFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move {
+            let (parts, _) = self.stores(IdbTransactionMode::Readonly)?;
+            let get = parts
+                .get(&JsValue::from_str(key))
+                .map_err(|error| cache_error(describe_js_error(&error)))?;
+            let value = request(&get).await?;
+            Ok(value
+                .dyn_into::<Uint8Array>()
+                .ok()
+                .map(|bytes| bytes.to_vec()))
+        })
+    }
+
+    fn put<'a>(&'a self, entry: CacheEntry, bytes: Vec<u8>) -> CacheFuture<'a, ()> {
+        Box::pin(async move {
+            let (parts, entries) = self.stores(IdbTransactionMode::Readwrite)?;
+            let key = JsValue::from_str(&entry.key);
+            let js_error = |error: JsValue| cache_error(describe_js_error(&error));
+            let bytes = Uint8Array::from(bytes.as_slice());
+            parts.put_with_key(&bytes, &key).map_err(js_error)?;
+            // requests of one transaction finish in order
+            let put = entries
+                .put_with_key(&entry_to_js(&entry), &key)
+                .map_err(js_error)?;
+            request(&put).await.map(|_| ())
+        })
+    }
+
+    fn touch<'a>(&'a self, entry: CacheEntry) -> CacheFuture<'a, ()> {
+        Box::pin(async move {
+            let (_, entries) = self.stores(IdbTransactionMode::Readwrite)?;
+            let put = entries
+                .put_with_key(&entry_to_js(&entry), &JsValue::from_str(&entry.key))
+                .map_err(|error| cache_error(describe_js_error(&error)))?;
+            request(&put).await.map(|_| ())
+        })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> CacheFuture<'a, ()> {
+        Box::pin(async move {
+            let (parts, entries) = self.stores(IdbTransactionMode::Readwrite)?;
+            let key = JsValue::from_str(key);
+            let js_error = |error: JsValue| cache_error(describe_js_error(&error));
+            parts.delete(&key).map_err(js_error)?;
+            let delete = entries.delete(&key).map_err(js_error)?;
+            request(&delete).await.map(|_| ())
+        })
+    }
+
+    fn entries(&self) -> CacheFuture<'_, Vec<CacheEntry>> {
+        Box::pin(async move {
+            let (_, entries) = self.stores(IdbTransactionMode::Readonly)?;
+            let all = entries
+                .get_all()
+                .map_err(|error| cache_error(describe_js_error(&error)))?;
+            let values: Array = request(&all).await?.unchecked_into();
+            Ok(values
+                .iter()
+                .filter_map(|value| entry_from_js(&value))
+                .collect())
+        })
+    }
+}
@@ -0,0 +1,222 @@
+use three_d::{
+    degrees, ortho, perspective, vec3, Camera, Deg, EuclideanSpace, InnerSpace, Matrix4,
+    OrbitControl, Point3, ProjectionType, Vector3,
+};
+use wasm_bindgen::prelude::*;
+
+use crate::{bounds::Aabb, coordinates::OutputFrame};
+
+const DEFAULT_FIELD_OF_VIEW: f32 = 45.0;
+// leaves some room around the framed bounds
+const FRAMING_MARGIN: f32 = 1.1;
+const MAX_ZOOM_OUT: f32 = 10.0;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewPreset {
+    Front,
+    Back,
+    Left,
+    Right,
+    Top,
+    Bottom,
+    // the default 3/4 view, 30 degrees latitude and 45 degrees longitude
+    LDraw,
+}
+
+impl ViewPreset {
+    // direction from the target towards the eye and the camera up vector in LDraw space
+    pub fn orientation(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let up = vec3(0.0, -1.0, 0.0);
+        match self {
+            ViewPreset::Front => (vec3(0.0, 0.0, -1.0), up),
+            ViewPreset::Back => (vec3(0.0, 0.0, 1.0), up),
+            ViewPreset::Left => (vec3(-1.0, 0.0, 0.0), up),
+            ViewPreset::Right => (vec3(1.0, 0.0, 0.0), up),
+            ViewPreset::Top => (vec3(0.0, -1.0, 0.0), vec3(0.0, 0.0, 1.0)),
+            ViewPreset::Bottom => (vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, -1.0)),
+            ViewPreset::LDraw => {
+                let (latitude, longitude) = (30.0f32.to_radians(), 45.0f32.to_radians());
+                (
+                    vec3(
+                        latitude.cos() * longitude.sin(),
+                        -latitude.sin(),
+                        -latitude.cos() * longitude.cos(),
+                    ),
+                    up,
+                )
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraSetup {
+    pub position: Vector3<f32>,
+    pub target: Vector3<f32>,
+    pub up: Vector3<f32>,
+    pub projection: Projection,
+    // vertical field of view in degrees, used for perspective projection
+    pub field_of_view: f32,
+    // visible height at the target, used for orthographic projection
+    pub height: f32,
+    pub z_near: f32,
+    pub z_far: f32,
+    // radius of the framed bounds including the margin
+    pub radius: f32,
+}
+
+impl CameraSetup {
+    pub fn preset(
+        bounds: &Aabb,
+        preset: ViewPreset,
+        projection: Projection,
+        frame: &OutputFrame,
+        aspect: f32,
+    ) -> Self {
+        let (direction, up) = preset.orientation();
+        Self::fit(
+            bounds,
+            frame.direction(direction),
+            frame.direction(up),
+            projection,
+            aspect,
+        )
+    }
+
+    // frames the bounds (given in the output frame) looking along -direction
+    pub fn fit(
+        bounds: &Aabb,
+        direction: Vector3<f32>,
+        up: Vector3<f32>,
+        projection: Projection,
+        aspect: f32,
+    ) -> Self {
+        let (target, radius) = if bounds.is_empty() {
+            (vec3(0.0, 0.0, 0.0), 1.0)
+        } else {
+            (bounds.center(), bounds.radius().max(f32::EPSILON))
+        };
+        let radius = radius * FRAMING_MARGIN;
+        let aspect = if aspect > 0.0 { aspect } else { 1.0 };
+
+        let half_fov = (DEFAULT_FIELD_OF_VIEW * 0.5).to_radians();
+        // narrow viewports are limited by the horizontal field of view
+        let half_fov = half_fov.min((half_fov.tan() * aspect).atan());
+        let distance = match projection {
+            Projection::Perspective => radius / half_fov.sin(),
+            Projection::Orthographic => radius * 3.0,
+        };
+
+        Self {
+            position: target + direction.normalize() * distance,
+            target,
+            up: up.normalize(),
+            projection,
+            field_of_view: DEFAULT_FIELD_OF_VIEW,
+            height: 2.0 * radius / aspect.min(1.0),
+            // leaves room for zooming with the orbit control
+            z_near: radius * 0.01,
+            z_far: distance * MAX_ZOOM_OUT + radius * 2.0,
+            radius,
+        }
+    }
+
+    // picks up what the orbit control did to the camera
+    pub fn sync(&mut self, camera: &Camera) {
+        self.position = *camera.position();
+        self.target = *camera.target();
+        self.up = *camera.up();
+        if let ProjectionType::Orthographic { height } = camera.projection_type() {
+            self.height = *height;
+        }
+    }
+
+    pub fn orbit_control(&self) -> OrbitControl {
+        OrbitControl::new(
+            self.target,
+            self.radius * 0.5,
+            self.distance() * MAX_ZOOM_OUT,
+        )
+    }
+
+    pub fn distance(&self) -> f32 {
+        (self.position - self.target).magnitude()
+    }
+
+    pub fn direction(&self) -> Vector3<f32> {
+        (self.position - self.target).normalize()
+    }
+
+    // switches the projection while keeping the apparent size at the target
+    pub fn with_projection(&self, projection: Projection) -> Self {
+        let mut setup = *self;
+        let half_fov = (self.field_of_view * 0.5).to_radians();
+        match (self.projection, projection) {
+            (Projection::Perspective, Projection::Orthographic) => {
+                setup.height = 2.0 * self.distance() * half_fov.tan();
+            }
+            (Projection::Orthographic, Projection::Perspective) => {
+                let distance = self.height * 0.5 / half_fov.tan();
+                setup.position = self.target + self.direction() * distance;
+                setup.z_far = self.z_far.max(distance * 2.0);
+            }
+            _ => {}
+        }
+        setup.projection = projection;
+        setup
+    }
+
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_at_rh(
+            Point3::from_vec(self.position),
+            Point3::from_vec(self.target),
+            self.up,
+        )
+    }
+
+    pub fn projection_matrix(&self, aspect: f32) -> Matrix4<f32> {
+        match self.projection {
+            Projection::Perspective => {
+                perspective(Deg(self.field_of_view), aspect, self.z_near, self.z_far)
+            }
+            Projection::Orthographic => {
+                let half_height = self.height * 0.5;
+                let half_width = half_height * aspect;
+                ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.z_near,
+                    self.z_far,
+                )
+            }
+        }
+    }
+
+    pub fn view_projection(&self, aspect: f32) -> Matrix4<f32> {
+        self.projection_matrix(aspect) * self.view_matrix()
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.set_view(self.position, self.target, self.up);
+        match self.projection {
+            Projection::Perspective => camera.set_perspective_projection(
+                degrees(self.field_of_view),
+                self.z_near,
+                self.z_far,
+            ),
+            Projection::Orthographic => {
+                camera.set_orthographic_projection(self.height, self.z_near, self.z_far)
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use js_sys::{Function, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+// larger offscreen targets fail on most WebGL implementations
+pub const MAX_CAPTURE_SIZE: u32 = 8192;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureOptions {
+    // clears to a fully transparent background instead of the one in RenderProps
+    pub transparent: bool,
+    // renders this many samples per pixel along each axis and averages them
+    pub supersampling: u32,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            transparent: false,
+            supersampling: 1,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl CaptureOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CaptureOptions {
+    // the supersampling factor actually used, reduced until the target fits. Both come
+    // from JS, so nothing is multiplied before it is known to fit
+    pub fn samples(&self, width: u32, height: u32) -> u32 {
+        let fitting = MAX_CAPTURE_SIZE / width.max(height).max(1);
+        self.supersampling.min(fitting).max(1)
+    }
+}
+
+// an offscreen render waiting for the next frame, settles the promise returned to JS
+#[derive(Debug, Clone)]
+pub struct CaptureRequest {
+    pub width: u32,
+    pub height: u32,
+    pub options: CaptureOptions,
+    resolve: Function,
+    reject: Function,
+}
+
+impl CaptureRequest {
+    pub fn new(
+        width: u32,
+        height: u32,
+        options: CaptureOptions,
+        resolve: Function,
+        reject: Function,
+    ) -> Self {
+        Self {
+            width,
+            height,
+            options,
+            resolve,
+            reject,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err("can't capture an empty image".to_string());
+        }
+        if self.width.max(self.height) > MAX_CAPTURE_SIZE {
+            return Err(format!(
+                "can't capture images larger than {0}x{0}",
+                MAX_CAPTURE_SIZE
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn resolve(&self, png: &[u8]) {
+        let bytes = Uint8Array::from(png);
+        if let Err(error) = self.resolve.call1(&JsValue::NULL, &bytes) {
+            log::warn!("capture callback threw {:?}", error);
+        }
+    }
+
+    pub fn reject(&self, message: &str) {
+        if let Err(error) = self
+            .reject
+            .call1(&JsValue::NULL, &JsValue::from_str(message))
+        {
+            log::warn!("capture callback threw {:?}", error);
+        }
+    }
+}
+
+// averages every factor x factor block of an RGBA image, rows are kept in order. Colours
+// are weighted by their alpha, so transparent samples don't darken the edges
+pub fn downsample(pixels: &[[u8; 4]], width: u32, height: u32, factor: u32) -> Vec<[u8; 4]> {
+    if factor <= 1 {
+        return pixels.to_vec();
+    }
+
+    let (width, height, factor) = (width as usize, height as usize, factor as usize);
+    let (target_width, target_height) = (width / factor, height / factor);
+    let samples = (factor * factor) as u64;
+    let mut target = Vec::with_capacity(target_width * target_height);
+    for y in 0..target_height {
+        for x in 0..target_width {
+            let mut sum = [0u64; 4];
+            for sy in 0..factor {
+                let row = (y * factor + sy) * width;
+                for sx in 0..factor {
+                    let [r, g, b, a] = pixels[row + x * factor + sx].map(|value| value as u64);
+                    sum[0] += r * a;
+                    sum[1] += g * a;
+                    sum[2] += b * a;
+                    sum[3] += a;
+                }
+            }
+            let alpha = sum[3];
+            let color = |channel: u64| (channel + alpha / 2).checked_div(alpha).unwrap_or(0) as u8;
+            target.push([
+                color(sum[0]),
+                color(sum[1]),
+                color(sum[2]),
+                ((alpha + samples / 2) / samples) as u8,
+            ]);
+        }
+    }
+    target
+}
+
+pub fn encode_png(pixels: &[[u8; 4]], width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|error| error.to_string())?;
+        writer
+            .write_image_data(pixels.concat().as_slice())
+            .map_err(|error| error.to_string())?;
+    }
+    Ok(bytes)
+}
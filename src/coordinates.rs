@@ -0,0 +1,97 @@
+use std::ops::Mul;
+
+use three_d::{vec3, InnerSpace, Matrix3, Matrix4, Vector3};
+use wasm_bindgen::prelude::*;
+
+// the discriminants are what JS and the worker see, keep them
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoordinateSystem {
+    // -Y up, the frame the files are written in
+    LDraw = 0,
+    // right-handed, +Y up, front of the part towards +Z
+    YUp = 1,
+    // right-handed, +Z up, front of the part towards -Y
+    ZUp = 2,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Units {
+    LDU = 0,
+    Millimetres = 1,
+    Metres = 2,
+    Studs = 3,
+}
+
+impl Units {
+    // length of one LDraw unit in these units
+    pub fn per_ldu(&self) -> f32 {
+        match self {
+            Units::LDU => 1.0,
+            Units::Millimetres => 0.4,
+            Units::Metres => 0.0004,
+            Units::Studs => 1.0 / 20.0,
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputFrame {
+    pub system: CoordinateSystem,
+    pub units: Units,
+}
+
+#[wasm_bindgen]
+impl OutputFrame {
+    #[wasm_bindgen(constructor)]
+    pub fn new(system: CoordinateSystem, units: Units) -> Self {
+        Self { system, units }
+    }
+}
+
+impl Default for OutputFrame {
+    fn default() -> Self {
+        Self {
+            system: CoordinateSystem::YUp,
+            units: Units::LDU,
+        }
+    }
+}
+
+impl OutputFrame {
+    pub fn rotation(&self) -> Matrix3<f32> {
+        // Matrix3::new takes columns
+        match self.system {
+            CoordinateSystem::LDraw => Matrix3::new(1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0),
+            CoordinateSystem::YUp => Matrix3::new(1.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 0.0, -1.0),
+            CoordinateSystem::ZUp => Matrix3::new(1.0, 0.0, 0.0, 0.0, 0.0, -1.0, 0.0, 1.0, 0.0),
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.units.per_ldu()
+    }
+
+    // maps LDraw space (in LDU) into this frame
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_scale(self.scale()).mul(Matrix4::from(self.rotation()))
+    }
+
+    pub fn point(&self, point: Vector3<f32>) -> Vector3<f32> {
+        self.rotation().mul(point) * self.scale()
+    }
+
+    pub fn direction(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        self.rotation().mul(direction).normalize()
+    }
+
+    pub fn length(&self, length: f32) -> f32 {
+        length * self.scale()
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.direction(vec3(0.0, -1.0, 0.0))
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use wasm_bindgen::prelude::*;
+
+pub static LIVE_WINDOWS: AtomicUsize = AtomicUsize::new(0);
+// the geometries the live windows hold, counted again every frame from what they keep
+// around. It shows windows that are never dropped and objects piling up in one, not
+// buffers that outlive the objects owning them
+pub static FRAME_GEOMETRIES: AtomicUsize = AtomicUsize::new(0);
+
+// adds its count to a counter for as long as it is alive
+pub struct ResourceCounter {
+    counter: &'static AtomicUsize,
+    count: usize,
+}
+
+impl ResourceCounter {
+    pub fn new(counter: &'static AtomicUsize, count: usize) -> Self {
+        counter.fetch_add(count, Ordering::SeqCst);
+        Self { counter, count }
+    }
+
+    pub fn set(&mut self, count: usize) {
+        if count > self.count {
+            self.counter.fetch_add(count - self.count, Ordering::SeqCst);
+        } else {
+            self.counter.fetch_sub(self.count - count, Ordering::SeqCst);
+        }
+        self.count = count;
+    }
+}
+
+impl Drop for ResourceCounter {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(self.count, Ordering::SeqCst);
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugStats {
+    pub live_windows: usize,
+    pub frame_geometries: usize,
+}
+
+#[wasm_bindgen]
+pub fn debug_stats() -> DebugStats {
+    DebugStats {
+        live_windows: LIVE_WINDOWS.load(Ordering::SeqCst),
+        frame_geometries: FRAME_GEOMETRIES.load(Ordering::SeqCst),
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+
+use js_sys::{Object, Reflect};
+use wasm_bindgen::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadErrorKind {
+    // the request failed or the server answered with an error
+    Fetch,
+    // the server has no such part or subfile
+    MissingFile,
+    // a line could not be tokenized
+    Parse,
+    Canvas,
+    // the part cache could not be opened, read or written
+    Cache,
+    // a baked file from another version of the format or with a bad checksum
+    Stale,
+    // the WebGL context or winit window could not be created
+    Window,
+    Cancelled,
+}
+
+impl LoadErrorKind {
+    const ALL: [LoadErrorKind; 8] = [
+        LoadErrorKind::Fetch,
+        LoadErrorKind::MissingFile,
+        LoadErrorKind::Parse,
+        LoadErrorKind::Canvas,
+        LoadErrorKind::Cache,
+        LoadErrorKind::Stale,
+        LoadErrorKind::Window,
+        LoadErrorKind::Cancelled,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.as_str() == name)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoadErrorKind::Fetch => "fetch",
+            LoadErrorKind::MissingFile => "missing_file",
+            LoadErrorKind::Parse => "parse",
+            LoadErrorKind::Canvas => "canvas",
+            LoadErrorKind::Cache => "cache",
+            LoadErrorKind::Stale => "stale",
+            LoadErrorKind::Window => "window",
+            LoadErrorKind::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadError {
+    pub kind: LoadErrorKind,
+    pub file: Option<String>,
+    // one based, like in editors
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl LoadError {
+    pub fn new(kind: LoadErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            file: None,
+            line: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn parse(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line: Some(line),
+            ..Self::new(LoadErrorKind::Parse, message)
+        }
+    }
+
+    pub fn cancelled() -> Self {
+        Self::new(LoadErrorKind::Cancelled, "loading was cancelled")
+    }
+
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.file.get_or_insert_with(|| file.to_string());
+        self
+    }
+
+    // a plain { kind, file, line, message } object for JS
+    pub fn to_js(&self) -> JsValue {
+        let object = Object::new();
+        let fields = [
+            ("kind", JsValue::from_str(self.kind.as_str())),
+            (
+                "file",
+                self.file
+                    .as_deref()
+                    .map(JsValue::from_str)
+                    .unwrap_or(JsValue::NULL),
+            ),
+            (
+                "line",
+                self.line
+                    .map(|line| JsValue::from_f64(line as f64))
+                    .unwrap_or(JsValue::NULL),
+            ),
+            ("message", JsValue::from_str(&self.message)),
+        ];
+        for (key, value) in fields.iter() {
+            Reflect::set(&object, &JsValue::from_str(key), value).unwrap();
+        }
+        object.into()
+    }
+
+    // the other way round, for errors that crossed over from a worker
+    pub fn from_js(value: &JsValue) -> Option<Self> {
+        let field = |key: &str| Reflect::get(value, &JsValue::from_str(key)).ok();
+        Some(Self {
+            kind: LoadErrorKind::from_name(&field("kind")?.as_string()?)?,
+            file: field("file").and_then(|file| file.as_string()),
+            line: field("line")
+                .and_then(|line| line.as_f64())
+                .map(|line| line as usize),
+            message: field("message")?.as_string()?,
+        })
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind.as_str())?;
+        if let Some(file) = &self.file {
+            write!(f, " in {}", file)?;
+        }
+        if let Some(line) = self.line {
+            write!(f, " on line {}", line)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl From<LoadError> for JsValue {
+    fn from(error: LoadError) -> Self {
+        error.to_js()
+    }
+}
+
+// describes a rejected JS promise or thrown value
+pub fn describe_js_error(value: &JsValue) -> String {
+    value
+        .as_string()
+        .or_else(|| {
+            Reflect::get(value, &JsValue::from_str("message"))
+                .ok()
+                .and_then(|message| message.as_string())
+        })
+        .unwrap_or_else(|| format!("{:?}", value))
+}
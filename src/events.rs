@@ -2,37 +2,85 @@ use std::collections::HashMap;
 
 use winit::{
     event::Event,
-    event_loop::{EventLoop, EventLoopBuilder, EventLoopProxy, EventLoopWindowTarget},
+    event_loop::{ControlFlow, EventLoop, EventLoopBuilder, EventLoopProxy, EventLoopWindowTarget},
+    window::WindowId,
 };
 
-use crate::utils;
+use crate::{
+    camera::ViewPreset, capture::CaptureRequest, progressive::BrickUpdate, props::RenderProps,
+    selection::SelectionCommand, utils,
+};
+
+pub type WindowHandler<Q> = Box<
+    dyn FnMut(
+        &winit::event::Event<RenderingUserEvent<Q>>,
+        &winit::event_loop::EventLoopWindowTarget<RenderingUserEvent<Q>>,
+        &mut winit::event_loop::ControlFlow,
+    ),
+>;
+
+// a handler together with the winit window it draws into, so window events can be
+// routed to it
+pub struct RenderingWindow<Q: 'static> {
+    pub window_id: WindowId,
+    pub handler: WindowHandler<Q>,
+    // releases the GPU resources, called before the handler is dropped
+    pub teardown: Box<dyn FnOnce()>,
+}
+
+impl<Q: 'static> RenderingWindow<Q> {
+    pub fn dispose(self) {
+        (self.teardown)();
+        drop(self.handler);
+    }
+}
 
 pub enum RenderingUserEvent<Q: 'static> {
     InternalCreateWindow(
         usize,
         Box<
-            dyn FnOnce(
-                &EventLoopWindowTarget<RenderingUserEvent<Q>>,
-            ) -> Box<
-                dyn FnMut(
-                    &winit::event::Event<RenderingUserEvent<Q>>,
-                    &winit::event_loop::EventLoopWindowTarget<RenderingUserEvent<Q>>,
-                    &mut winit::event_loop::ControlFlow,
-                ),
-            >,
+            dyn FnOnce(&EventLoopWindowTarget<RenderingUserEvent<Q>>) -> Option<RenderingWindow<Q>>,
         >,
     ),
     InternalDeleteWindow(usize),
-    InternalUpdateProps(u8),
+    // the remaining internal events are addressed to a single window id
+    InternalUpdateProps(usize, RenderProps),
+    InternalSetView(usize, ViewPreset),
+    InternalFitToBounds(usize),
+    InternalSelection(usize, SelectionCommand),
+    InternalCapture(usize, CaptureRequest),
+    // files that arrived after the window was created
+    InternalUpdateBrick(usize, BrickUpdate),
     Other(Q),
 }
 
+impl<Q: 'static> RenderingUserEvent<Q> {
+    pub fn target(&self) -> Option<usize> {
+        match self {
+            Self::InternalCreateWindow(id, _)
+            | Self::InternalDeleteWindow(id)
+            | Self::InternalUpdateProps(id, _)
+            | Self::InternalSetView(id, _)
+            | Self::InternalFitToBounds(id)
+            | Self::InternalSelection(id, _)
+            | Self::InternalCapture(id, _)
+            | Self::InternalUpdateBrick(id, _) => Some(*id),
+            Self::Other(_) => None,
+        }
+    }
+}
+
 impl<Q: Clone + 'static> Clone for RenderingUserEvent<Q> {
     fn clone(&self) -> Self {
         match self {
             Self::InternalCreateWindow(_, _) => panic!("can't clone InternalCreateWindow"),
             Self::InternalDeleteWindow(_) => panic!("can't clone InternalDeleteWindow"),
-            Self::InternalUpdateProps(arg0) => Self::InternalUpdateProps(arg0.clone()),
+            Self::InternalUpdateProps(arg0, arg1) => Self::InternalUpdateProps(*arg0, arg1.clone()),
+            Self::InternalSetView(arg0, arg1) => Self::InternalSetView(*arg0, *arg1),
+            Self::InternalFitToBounds(arg0) => Self::InternalFitToBounds(*arg0),
+            Self::InternalSelection(arg0, arg1) => Self::InternalSelection(*arg0, arg1.clone()),
+            Self::InternalCapture(arg0, arg1) => Self::InternalCapture(*arg0, arg1.clone()),
+            Self::InternalUpdateBrick(arg0, arg1) => Self::InternalUpdateBrick(*arg0, arg1.clone()),
             Self::Other(arg0) => Self::Other(arg0.clone()),
         }
     }
@@ -55,33 +103,61 @@ impl<Q: 'static> Rendering<Q> {
         self.event_loop.create_proxy()
     }
 
-    pub fn run(self) -> ! {
-        let mut handlers: HashMap<
-            usize,
-            Box<
-                dyn FnMut(
-                    &winit::event::Event<RenderingUserEvent<Q>>,
-                    &winit::event_loop::EventLoopWindowTarget<RenderingUserEvent<Q>>,
-                    &mut winit::event_loop::ControlFlow,
-                ),
-            >,
-        > = HashMap::new();
+    // on the web the loop is spawned, run would throw an exception to escape its ! type
+    pub fn run(self) {
+        let handler = Self::handler();
+        #[cfg(target_arch = "wasm32")]
+        winit::platform::web::EventLoopExtWebSys::spawn(self.event_loop, handler);
+        #[cfg(not(target_arch = "wasm32"))]
+        self.event_loop.run(handler);
+    }
+
+    fn handler() -> impl FnMut(
+        Event<'_, RenderingUserEvent<Q>>,
+        &EventLoopWindowTarget<RenderingUserEvent<Q>>,
+        &mut ControlFlow,
+    ) {
+        let mut windows: HashMap<usize, RenderingWindow<Q>> = HashMap::new();
 
-        self.event_loop.run(move |event, target, control_flow| {
+        move |event, target, control_flow| {
             match event {
                 Event::UserEvent(RenderingUserEvent::InternalCreateWindow(id, callback)) => {
-                    handlers.insert(id, callback(target));
+                    if let Some(window) = callback(target) {
+                        windows.insert(id, window);
+                    }
                 }
                 Event::UserEvent(RenderingUserEvent::InternalDeleteWindow(id)) => {
-                    handlers.remove(&id);
+                    if let Some(window) = windows.remove(&id) {
+                        window.dispose();
+                    }
+                }
+                Event::UserEvent(ref user_event) if user_event.target().is_some() => {
+                    let id = user_event.target().unwrap();
+                    match windows.get_mut(&id) {
+                        Some(window) => (window.handler)(&event, target, control_flow),
+                        None => {
+                            log::warn!("event for unknown window {}", id);
+                            if let RenderingUserEvent::InternalCapture(_, request) = user_event {
+                                request.reject("the window was deleted");
+                            }
+                        }
+                    }
+                }
+                Event::WindowEvent { window_id, .. } | Event::RedrawRequested(window_id) => {
+                    if let Some(window) = windows
+                        .values_mut()
+                        .find(|window| window.window_id == window_id)
+                    {
+                        (window.handler)(&event, target, control_flow);
+                    }
                 }
                 event => {
                     // TODO FIXME remove our custom type wrapper RenderingUserEvent and then maybe we could use an FnOnce above
-                    for handler in handlers.values_mut() {
-                        handler(&event, target, control_flow);
+                    for window in windows.values_mut() {
+                        (window.handler)(&event, target, control_flow);
                     }
                 }
             }
-        })
+        }
     }
 }
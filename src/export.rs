@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::ops::Mul;
+
+use three_d::{Matrix4, SquareMatrix};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    parser::{
+        colors::MAIN_COLOR,
+        part::{LDrawBrick, LDrawFile},
+    },
+    scene::{references, Scene, SceneMesh},
+};
+
+pub mod gltf;
+pub mod obj;
+pub mod pov;
+pub mod print;
+pub mod stl;
+pub mod svg;
+pub mod threemf;
+
+// a file an exporter produced, exports with sidecar files return several
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportedFile {
+    name: String,
+    bytes: Vec<u8>,
+}
+
+impl ExportedFile {
+    pub fn new(name: impl Into<String>, bytes: Vec<u8>) -> Self {
+        Self {
+            name: name.into(),
+            bytes,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl ExportedFile {
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportNode {
+    pub name: String,
+    // relative to the parent node, in LDraw space
+    pub transformation: Matrix4<f32>,
+    pub mesh: Option<usize>,
+    // the file and line that placed the node, none for the root
+    pub source: Option<(String, usize)>,
+    pub children: Vec<ExportNode>,
+}
+
+impl ExportNode {
+    // depth first, with the transformation relative to the root
+    pub fn walk<'a>(
+        &'a self,
+        parent: Matrix4<f32>,
+        visit: &mut impl FnMut(&'a ExportNode, Matrix4<f32>),
+    ) {
+        let transformation = parent.mul(self.transformation);
+        visit(self, transformation);
+        for child in self.children.iter() {
+            child.walk(transformation, visit);
+        }
+    }
+}
+
+// the submodel hierarchy of a brick with one mesh per (part, colour) pair, shared by
+// every node that places it
+#[derive(Debug, Clone)]
+pub struct ExportModel {
+    pub meshes: Vec<SceneMesh>,
+    pub root: ExportNode,
+}
+
+impl ExportModel {
+    pub fn new(brick: &LDrawBrick) -> Self {
+        let mut builder = ExportBuilder {
+            brick,
+            lookup: HashMap::new(),
+            meshes: Vec::new(),
+        };
+        let root = match brick.files.get(&brick.entry_file) {
+            Some(entry_file) => builder.node(entry_file, MAIN_COLOR, Matrix4::identity(), None),
+            None => ExportNode {
+                name: brick.entry_file.to_string(),
+                transformation: Matrix4::identity(),
+                mesh: None,
+                source: None,
+                children: Vec::new(),
+            },
+        };
+
+        Self {
+            meshes: builder.meshes,
+            root,
+        }
+    }
+
+    // every node with a mesh and its transformation in LDraw space
+    pub fn instances(&self) -> Vec<(&ExportNode, Matrix4<f32>)> {
+        let mut instances = Vec::new();
+        self.root
+            .walk(Matrix4::identity(), &mut |node, transformation| {
+                if node.mesh.is_some() {
+                    instances.push((node, transformation));
+                }
+            });
+        instances
+    }
+}
+
+struct ExportBuilder<'a> {
+    brick: &'a LDrawBrick,
+    lookup: HashMap<(String, u32, bool), Option<usize>>,
+    meshes: Vec<SceneMesh>,
+}
+
+impl<'a> ExportBuilder<'a> {
+    // parts are baked whole, submodels only keep their own geometry and get a child
+    // node for every reference
+    fn node(
+        &mut self,
+        file: &LDrawFile,
+        color: u32,
+        transformation: Matrix4<f32>,
+        source: Option<(String, usize)>,
+    ) -> ExportNode {
+        let is_part = file.is_part();
+        let mesh = self.mesh(&file.name, color, is_part);
+
+        let mut children = Vec::new();
+        if !is_part {
+            for reference in references(self.brick, file, color, &file.bfc_direction) {
+                children.push(self.node(
+                    reference.file,
+                    reference.color,
+                    reference.matrix(),
+                    Some((file.name.to_string(), reference.subfile.line)),
+                ));
+            }
+        }
+
+        ExportNode {
+            name: file.name.to_string(),
+            transformation,
+            mesh,
+            source,
+            children,
+        }
+    }
+
+    fn mesh(&mut self, filename: &str, color: u32, whole: bool) -> Option<usize> {
+        let key = (filename.to_string(), color, whole);
+        if let Some(mesh) = self.lookup.get(&key) {
+            return *mesh;
+        }
+
+        let mesh = if whole {
+            Scene::part_mesh(self.brick, filename, color)
+        } else {
+            Scene::local_mesh(self.brick, filename, color)
+        };
+        let index = mesh
+            .filter(|mesh| !mesh.groups.is_empty() || !mesh.edges.is_empty())
+            .map(|mesh| {
+                self.meshes.push(mesh);
+                self.meshes.len() - 1
+            });
+        self.lookup.insert(key, index);
+        index
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+use three_d::{Matrix4, SquareMatrix, Vector3};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    coordinates::{CoordinateSystem, OutputFrame, Units},
+    export::{ExportModel, ExportNode, ExportedFile},
+    materials::MaterialParams,
+    parser::part::LDrawBrick,
+};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const LINES: u32 = 1;
+const TRIANGLES: u32 = 4;
+
+const GLB_MAGIC: u32 = 0x4654_6c67;
+const GLB_JSON: u32 = 0x4e4f_534a;
+const GLB_BIN: u32 = 0x004e_4942;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GltfOptions {
+    // glTF itself is +Y up in metres
+    pub frame: OutputFrame,
+    // type 2 lines as LINES primitives
+    pub edges: bool,
+    // a single .glb instead of .gltf with a .bin next to it
+    pub binary: bool,
+}
+
+impl Default for GltfOptions {
+    fn default() -> Self {
+        Self {
+            frame: OutputFrame::new(CoordinateSystem::YUp, Units::Metres),
+            edges: false,
+            binary: true,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl GltfOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// the JSON document and the one binary buffer it points into
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gltf {
+    pub json: Value,
+    pub bin: Vec<u8>,
+}
+
+impl Gltf {
+    pub fn new(brick: &LDrawBrick, options: &GltfOptions) -> Self {
+        let model = ExportModel::new(brick);
+        let mut writer = GltfWriter {
+            brick,
+            bin: Vec::new(),
+            buffer_views: Vec::new(),
+            accessors: Vec::new(),
+            materials: Vec::new(),
+            material_lookup: HashMap::new(),
+            nodes: Vec::new(),
+            mesh_indices: Vec::new(),
+        };
+
+        // a mesh with nothing but edges has no primitives without them and is left out
+        let mut meshes: Vec<Value> = Vec::new();
+        for mesh in model.meshes.iter() {
+            let mut primitives = Vec::new();
+            for group in mesh.groups.iter() {
+                let (positions, indices) = weld(&group.positions);
+                primitives.push(json!({
+                    "attributes": { "POSITION": writer.positions(&positions) },
+                    "indices": writer.indices(&indices),
+                    "material": writer.material(group.color, false),
+                    "mode": TRIANGLES,
+                }));
+            }
+            if options.edges {
+                for edges in mesh.edges.iter() {
+                    let (positions, indices) = weld(&edges.positions);
+                    primitives.push(json!({
+                        "attributes": { "POSITION": writer.positions(&positions) },
+                        "indices": writer.indices(&indices),
+                        "material": writer.material(edges.color, true),
+                        "mode": LINES,
+                    }));
+                }
+            }
+            if primitives.is_empty() {
+                writer.mesh_indices.push(None);
+            } else {
+                writer.mesh_indices.push(Some(meshes.len()));
+                meshes.push(json!({ "name": mesh.key.filename, "primitives": primitives }));
+            }
+        }
+
+        // the frame conversion sits on a node above the model
+        let model_root = writer.node(&model.root);
+        let root = writer.nodes.len();
+        let mut root_node = json!({
+            "name": brick.entry_file,
+            "matrix": matrix(&options.frame.matrix()),
+        });
+        if let Some(model_root) = model_root {
+            root_node["children"] = json!([model_root]);
+        }
+        writer.nodes.push(root_node);
+
+        let mut json = json!({
+            "asset": { "version": "2.0", "generator": "ldraw-renderer" },
+            "scene": 0,
+            "scenes": [{ "nodes": [root] }],
+            "nodes": writer.nodes,
+            "meshes": meshes,
+            "materials": writer.materials,
+            "accessors": writer.accessors,
+            "bufferViews": writer.buffer_views,
+            "buffers": [{ "byteLength": writer.bin.len() }],
+        });
+        if options.edges {
+            json["extensionsUsed"] = json!(["KHR_materials_unlit"]);
+        }
+        // glTF does not allow empty arrays, nor a buffer of no bytes, an empty brick has
+        // neither
+        if writer.bin.is_empty() {
+            json.as_object_mut().unwrap().remove("buffers");
+        }
+        json.as_object_mut()
+            .unwrap()
+            .retain(|_, value| value.as_array().is_none_or(|array| !array.is_empty()));
+
+        Self {
+            json,
+            bin: writer.bin,
+        }
+    }
+
+    // the .gltf document with its buffer in a file named bin_uri
+    pub fn to_gltf(&self, bin_uri: &str) -> String {
+        let mut json = self.json.clone();
+        if !self.bin.is_empty() {
+            json["buffers"][0]["uri"] = json!(bin_uri);
+        }
+        serde_json::to_string_pretty(&json).unwrap()
+    }
+
+    pub fn to_glb(&self) -> Vec<u8> {
+        let mut json = serde_json::to_vec(&self.json).unwrap();
+        pad(&mut json, b' ');
+        let mut bin = self.bin.clone();
+        pad(&mut bin, 0);
+
+        // without a buffer there is no binary chunk
+        let bin_chunk = if bin.is_empty() { 0 } else { 8 + bin.len() };
+        let length = 12 + 8 + json.len() + bin_chunk;
+        let mut glb = Vec::with_capacity(length);
+        for word in [GLB_MAGIC, 2, length as u32, json.len() as u32, GLB_JSON] {
+            glb.extend_from_slice(&word.to_le_bytes());
+        }
+        glb.extend_from_slice(&json);
+        if !bin.is_empty() {
+            for word in [bin.len() as u32, GLB_BIN] {
+                glb.extend_from_slice(&word.to_le_bytes());
+            }
+            glb.extend_from_slice(&bin);
+        }
+        glb
+    }
+
+    pub fn files(&self, name: &str, binary: bool) -> Vec<ExportedFile> {
+        if binary {
+            vec![ExportedFile::new(format!("{}.glb", name), self.to_glb())]
+        } else if self.bin.is_empty() {
+            vec![ExportedFile::new(
+                format!("{}.gltf", name),
+                self.to_gltf("").into_bytes(),
+            )]
+        } else {
+            let bin_name = format!("{}.bin", name);
+            vec![
+                ExportedFile::new(
+                    format!("{}.gltf", name),
+                    self.to_gltf(&bin_name).into_bytes(),
+                ),
+                ExportedFile::new(bin_name, self.bin.clone()),
+            ]
+        }
+    }
+}
+
+struct GltfWriter<'a> {
+    brick: &'a LDrawBrick,
+    bin: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    materials: Vec<Value>,
+    material_lookup: HashMap<(u32, bool), usize>,
+    nodes: Vec<Value>,
+    // the glTF mesh of every model mesh, none for those left out
+    mesh_indices: Vec<Option<usize>>,
+}
+
+impl<'a> GltfWriter<'a> {
+    fn buffer_view(&mut self, bytes: &[u8], target: u32) -> usize {
+        pad(&mut self.bin, 0);
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": bytes.len(),
+            "target": target,
+        }));
+        self.bin.extend_from_slice(bytes);
+        self.buffer_views.len() - 1
+    }
+
+    fn positions(&mut self, positions: &[Vector3<f32>]) -> usize {
+        let mut bytes = Vec::with_capacity(positions.len() * 12);
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for position in positions.iter() {
+            for axis in 0..3 {
+                bytes.extend_from_slice(&position[axis].to_le_bytes());
+                min[axis] = min[axis].min(position[axis]);
+                max[axis] = max[axis].max(position[axis]);
+            }
+        }
+
+        let view = self.buffer_view(&bytes, ARRAY_BUFFER);
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": positions.len(),
+            "type": "VEC3",
+            "min": min,
+            "max": max,
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn indices(&mut self, indices: &[u32]) -> usize {
+        let bytes: Vec<u8> = indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes())
+            .collect();
+        let view = self.buffer_view(&bytes, ELEMENT_ARRAY_BUFFER);
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+
+    // lines are unlit, surfaces get the PBR parameters of their LDConfig finish
+    fn material(&mut self, code: u32, unlit: bool) -> usize {
+        if let Some(index) = self.material_lookup.get(&(code, unlit)) {
+            return *index;
+        }
+
+        let color = self.brick.colors.resolve(code);
+        let params = MaterialParams::from_color(&color);
+        let [r, g, b, a] = params.albedo;
+        let mut material = json!({
+            "name": format!("ldraw_{}_{}", code, color.name),
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [linear(r), linear(g), linear(b), a as f32 / 255.0],
+                "metallicFactor": params.metallic,
+                "roughnessFactor": params.roughness,
+            },
+            "doubleSided": true,
+        });
+        if params.is_transparent() {
+            material["alphaMode"] = json!("BLEND");
+        }
+        if params.emissive != [0, 0, 0] {
+            material["emissiveFactor"] = json!(params.emissive.map(linear));
+        }
+        if unlit {
+            material["extensions"] = json!({ "KHR_materials_unlit": {} });
+        }
+
+        self.materials.push(material);
+        let index = self.materials.len() - 1;
+        self.material_lookup.insert((code, unlit), index);
+        index
+    }
+
+    // none for a node that would draw nothing
+    fn node(&mut self, node: &ExportNode) -> Option<usize> {
+        let children: Vec<usize> = node
+            .children
+            .iter()
+            .filter_map(|child| self.node(child))
+            .collect();
+        let mesh = node.mesh.and_then(|mesh| self.mesh_indices[mesh]);
+        if mesh.is_none() && children.is_empty() {
+            return None;
+        }
+
+        let mut value = json!({ "name": node.name });
+        if node.transformation != Matrix4::identity() {
+            value["matrix"] = json!(matrix(&node.transformation));
+        }
+        if let Some(mesh) = mesh {
+            value["mesh"] = json!(mesh);
+        }
+        if !children.is_empty() {
+            value["children"] = json!(children);
+        }
+        if let Some((file, line)) = &node.source {
+            value["extras"] = json!({ "source_file": file, "source_line": line });
+        }
+
+        self.nodes.push(value);
+        Some(self.nodes.len() - 1)
+    }
+}
+
+// shares identical vertices, returns the unique positions and an index per input vertex
+fn weld(positions: &[Vector3<f32>]) -> (Vec<Vector3<f32>>, Vec<u32>) {
+    let mut unique = Vec::new();
+    let mut lookup: HashMap<[u32; 3], u32> = HashMap::new();
+    let indices = positions
+        .iter()
+        .map(|position| {
+            let key = [
+                position.x.to_bits(),
+                position.y.to_bits(),
+                position.z.to_bits(),
+            ];
+            *lookup.entry(key).or_insert_with(|| {
+                unique.push(*position);
+                unique.len() as u32 - 1
+            })
+        })
+        .collect();
+    (unique, indices)
+}
+
+// column major, like glTF wants it
+fn matrix(matrix: &Matrix4<f32>) -> [f32; 16] {
+    let columns: &[[f32; 4]; 4] = matrix.as_ref();
+    let mut values = [0.0; 16];
+    for (column, values) in columns.iter().zip(values.chunks_exact_mut(4)) {
+        values.copy_from_slice(column);
+    }
+    values
+}
+
+// glTF colour factors are linear, LDConfig colours are sRGB
+fn linear(channel: u8) -> f32 {
+    let value = channel as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn pad(bytes: &mut Vec<u8>, with: u8) {
+    bytes.resize(bytes.len().div_ceil(4) * 4, with);
+}
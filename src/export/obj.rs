@@ -0,0 +1,179 @@
+use std::fmt::Write;
+use std::ops::{Mul, Range};
+
+use three_d::{InnerSpace, Matrix, Matrix3, SquareMatrix};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    coordinates::OutputFrame,
+    export::{ExportModel, ExportedFile},
+    materials::MaterialParams,
+    parser::part::LDrawBrick,
+    smoothing::{smooth, SmoothedMesh},
+};
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ObjOptions {
+    pub frame: OutputFrame,
+}
+
+#[wasm_bindgen]
+impl ObjOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// the .obj text and the .mtl library it refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Obj {
+    pub obj: String,
+    pub mtl: String,
+}
+
+// a mesh welded and smoothed once, with the colour groups as ranges of its triangles
+struct ObjMesh {
+    smoothed: SmoothedMesh,
+    groups: Vec<(u32, Range<usize>)>,
+}
+
+impl Obj {
+    // one group per part instance, every instance with its own vertices but sharing
+    // them between its triangles
+    pub fn new(brick: &LDrawBrick, options: &ObjOptions, mtl_file: &str) -> Self {
+        let model = ExportModel::new(brick);
+        let meshes: Vec<ObjMesh> = model
+            .meshes
+            .iter()
+            .map(|mesh| {
+                let mut triangles = Vec::new();
+                let mut groups = Vec::new();
+                for group in mesh.groups.iter() {
+                    let start = triangles.len();
+                    for triangle in group.positions.chunks_exact(3) {
+                        triangles.push([triangle[0], triangle[1], triangle[2]]);
+                    }
+                    groups.push((group.color, start..triangles.len()));
+                }
+                let hard_edges: Vec<_> = mesh
+                    .edges
+                    .iter()
+                    .flat_map(|edges| edges.positions.chunks_exact(2))
+                    .map(|line| [line[0], line[1]])
+                    .collect();
+                ObjMesh {
+                    smoothed: smooth(&triangles, &hard_edges),
+                    groups,
+                }
+            })
+            .collect();
+
+        let mut obj = String::new();
+        writeln!(obj, "# {}\nmtllib {}", brick.entry_file, mtl_file).unwrap();
+
+        let frame = options.frame.matrix();
+        let mut colors: Vec<u32> = Vec::new();
+        let (mut positions, mut normals) = (1, 1);
+        for (index, (node, transformation)) in model.instances().into_iter().enumerate() {
+            let mesh = &meshes[node.mesh.unwrap()];
+            if mesh.smoothed.corners.is_empty() {
+                continue;
+            }
+
+            let transformation = frame.mul(transformation);
+            let linear = Matrix3::from_cols(
+                transformation.x.truncate(),
+                transformation.y.truncate(),
+                transformation.z.truncate(),
+            );
+            let normal_matrix = linear
+                .invert()
+                .map(|inverse| inverse.transpose())
+                .unwrap_or_else(Matrix3::identity);
+            // mirrored parts would turn inside out
+            let flip = linear.determinant() < 0.0;
+
+            writeln!(obj, "g {}_{}", name(&node.name), index + 1).unwrap();
+            for position in mesh.smoothed.positions.iter() {
+                let position = transformation.mul(position.extend(1.0)).truncate();
+                writeln!(obj, "v {} {} {}", position.x, position.y, position.z).unwrap();
+            }
+            for normal in mesh.smoothed.normals.iter() {
+                let normal = normal_matrix.mul(*normal).normalize();
+                writeln!(obj, "vn {} {} {}", normal.x, normal.y, normal.z).unwrap();
+            }
+
+            for (color, triangles) in mesh.groups.iter() {
+                if triangles.is_empty() {
+                    continue;
+                }
+                if !colors.contains(color) {
+                    colors.push(*color);
+                }
+                writeln!(obj, "usemtl {}", material_name(brick, *color)).unwrap();
+                for corners in mesh.smoothed.corners[triangles.clone()].iter() {
+                    let [a, b, c] = corners;
+                    obj.push('f');
+                    for (position, normal) in if flip { [a, c, b] } else { [a, b, c] } {
+                        write!(obj, " {}//{}", positions + position, normals + normal).unwrap();
+                    }
+                    obj.push('\n');
+                }
+            }
+
+            positions += mesh.smoothed.positions.len() as u32;
+            normals += mesh.smoothed.normals.len() as u32;
+        }
+
+        let mut mtl = String::new();
+        for color in colors.iter() {
+            let params = MaterialParams::from_color(&brick.colors.resolve(*color));
+            let [r, g, b, a] = params.albedo.map(|channel| channel as f32 / 255.0);
+            // metals reflect in their own colour, everything else in white
+            let specular = if params.metallic > 0.5 {
+                [r, g, b]
+            } else {
+                [0.5; 3]
+            };
+            writeln!(mtl, "newmtl {}", material_name(brick, *color)).unwrap();
+            writeln!(mtl, "Kd {} {} {}", r, g, b).unwrap();
+            writeln!(mtl, "Ks {} {} {}", specular[0], specular[1], specular[2]).unwrap();
+            writeln!(mtl, "Ns {}", (1.0 - params.roughness).powi(2) * 1000.0).unwrap();
+            if params.emissive != [0, 0, 0] {
+                let [r, g, b] = params.emissive.map(|channel| channel as f32 / 255.0);
+                writeln!(mtl, "Ke {} {} {}", r, g, b).unwrap();
+            }
+            writeln!(mtl, "d {}", a).unwrap();
+            // the PBR extension Blender and others read
+            writeln!(mtl, "Pr {}\nPm {}", params.roughness, params.metallic).unwrap();
+            writeln!(
+                mtl,
+                "illum {}\n",
+                if params.is_transparent() { 4 } else { 2 }
+            )
+            .unwrap();
+        }
+
+        Self { obj, mtl }
+    }
+
+    pub fn files(brick: &LDrawBrick, options: &ObjOptions, name: &str) -> Vec<ExportedFile> {
+        let mtl_file = format!("{}.mtl", name);
+        let obj = Self::new(brick, options, &mtl_file);
+        vec![
+            ExportedFile::new(format!("{}.obj", name), obj.obj.into_bytes()),
+            ExportedFile::new(mtl_file, obj.mtl.into_bytes()),
+        ]
+    }
+}
+
+// ldraw_4_Red, names in OBJ and MTL files end at the first space
+fn material_name(brick: &LDrawBrick, code: u32) -> String {
+    format!("ldraw_{}_{}", code, name(&brick.colors.resolve(code).name))
+}
+
+fn name(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join("_")
+}
@@ -0,0 +1,380 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+use std::ops::Mul;
+
+use three_d::{Matrix4, Vector3};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    bounds::ModelBounds,
+    camera::{CameraSetup, Projection, ViewPreset},
+    coordinates::OutputFrame,
+    parser::{
+        colors::{ColorFinish, LDrawColor, MAIN_COLOR},
+        part::{LDrawBrick, LDrawFile},
+    },
+    scene::references,
+    smoothing::smooth,
+};
+
+// older POV-Ray versions refuse longer identifiers
+const MAX_IDENTIFIER: usize = 40;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PovOptions {
+    pub preset: ViewPreset,
+    pub projection: Projection,
+    // the image size the camera is framed for
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for PovOptions {
+    fn default() -> Self {
+        Self {
+            preset: ViewPreset::LDraw,
+            projection: Projection::Perspective,
+            width: 1024,
+            height: 768,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl PovOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// a scene file in the spirit of L3P: every file is declared once and placed with
+// object transforms, geometry in the main colour is left untextured so it takes the
+// material of whatever references it
+pub fn write(brick: &LDrawBrick, options: &PovOptions) -> String {
+    let mut writer = PovWriter {
+        brick,
+        identifiers: HashMap::new(),
+        taken: HashSet::new(),
+        colors: BTreeSet::new(),
+        declarations: String::new(),
+    };
+    let model = brick
+        .files
+        .get(&brick.entry_file)
+        .and_then(|file| writer.declare(file));
+
+    let mut pov = String::new();
+    writeln!(
+        pov,
+        "// {}\n#version 3.7;\nglobal_settings {{ assumed_gamma 1.0 }}\n",
+        brick.entry_file
+    )
+    .unwrap();
+
+    writer.colors.insert(MAIN_COLOR);
+    for code in writer.colors.iter() {
+        pov.push_str(&material(&brick.colors.resolve(*code)));
+    }
+    pov.push_str(&writer.declarations);
+
+    // the viewer frames in right-handed +Y up, POV-Ray is left-handed
+    let frame = OutputFrame::default();
+    let flip = Matrix4::from_nonuniform_scale(1.0, 1.0, -1.0);
+    if let Some(model) = model {
+        writeln!(
+            pov,
+            "object {{\n  {}\n  matrix {}\n  material {{ {} }}\n}}\n",
+            model,
+            matrix(&flip.mul(frame.matrix())),
+            color_identifier(MAIN_COLOR)
+        )
+        .unwrap();
+    }
+
+    let bounds = ModelBounds::compute(brick, frame).framed_aabb();
+    let aspect = options.width as f32 / options.height.max(1) as f32;
+    let camera = CameraSetup::preset(&bounds, options.preset, options.projection, &frame, aspect);
+    pov.push_str(&camera_and_lights(&camera, aspect));
+    pov
+}
+
+struct PovWriter<'a> {
+    brick: &'a LDrawBrick,
+    // none for files without any triangles down the tree, POV-Ray can't declare those
+    identifiers: HashMap<String, Option<String>>,
+    taken: HashSet<String>,
+    colors: BTreeSet<u32>,
+    declarations: String,
+}
+
+impl<'a> PovWriter<'a> {
+    // declares the file after everything it references and returns its identifier
+    fn declare(&mut self, file: &LDrawFile) -> Option<String> {
+        if let Some(identifier) = self.identifiers.get(&file.name) {
+            return identifier.clone();
+        }
+        // guards against files that reference themselves
+        self.identifiers.insert(file.name.to_string(), None);
+
+        let mut items = Vec::new();
+
+        let mut groups: Vec<(u32, Vec<[Vector3<f32>; 3]>)> = Vec::new();
+        for triangle in file.triangles.iter() {
+            let code = triangle.color.value;
+            let positions = [triangle.x, triangle.y, triangle.z];
+            match groups.iter_mut().find(|(color, _)| *color == code) {
+                Some((_, triangles)) => triangles.push(positions),
+                None => groups.push((code, vec![positions])),
+            }
+        }
+        let hard_edges: Vec<[Vector3<f32>; 2]> =
+            file.lines.iter().map(|line| [line.x, line.y]).collect();
+        for (code, triangles) in groups.iter() {
+            items.push(self.mesh(*code, triangles, &hard_edges));
+        }
+
+        // declared once per file, so colours stay codes for the material to resolve
+        let brick = self.brick;
+        for reference in references(brick, file, MAIN_COLOR, &file.bfc_direction) {
+            if let Some(identifier) = self.declare(reference.file) {
+                let mut item = format!(
+                    "object {{\n    {}\n    matrix {}\n",
+                    identifier,
+                    matrix(&reference.matrix())
+                );
+                item.push_str(&self.material(reference.subfile.color.value));
+                item.push_str("  }");
+                items.push(item);
+            }
+        }
+
+        if items.is_empty() {
+            return None;
+        }
+
+        let identifier = self.identifier(&file.name);
+        writeln!(self.declarations, "// {}", file.name).unwrap();
+        if items.len() == 1 {
+            writeln!(
+                self.declarations,
+                "#declare {} = {}\n",
+                identifier, items[0]
+            )
+            .unwrap();
+        } else {
+            writeln!(self.declarations, "#declare {} = union {{", identifier).unwrap();
+            for item in items.iter() {
+                writeln!(self.declarations, "  {}", item).unwrap();
+            }
+            self.declarations.push_str("}\n\n");
+        }
+
+        self.identifiers
+            .insert(file.name.to_string(), Some(identifier.to_string()));
+        Some(identifier)
+    }
+
+    fn mesh(
+        &mut self,
+        code: u32,
+        triangles: &[[Vector3<f32>; 3]],
+        hard_edges: &[[Vector3<f32>; 2]],
+    ) -> String {
+        let smoothed = smooth(triangles, hard_edges);
+        let vector = |value: &Vector3<f32>| format!("<{}, {}, {}>", value.x, value.y, value.z);
+
+        let mut mesh = String::from("mesh2 {\n");
+        let mut list = |name: &str, items: Vec<String>| {
+            writeln!(
+                mesh,
+                "    {} {{\n      {},\n      {}\n    }}",
+                name,
+                items.len(),
+                items.join(",\n      ")
+            )
+            .unwrap();
+        };
+        list(
+            "vertex_vectors",
+            smoothed.positions.iter().map(vector).collect(),
+        );
+        list(
+            "normal_vectors",
+            smoothed.normals.iter().map(vector).collect(),
+        );
+        list(
+            "face_indices",
+            smoothed
+                .corners
+                .iter()
+                .map(|[a, b, c]| format!("<{}, {}, {}>", a.0, b.0, c.0))
+                .collect(),
+        );
+        list(
+            "normal_indices",
+            smoothed
+                .corners
+                .iter()
+                .map(|[a, b, c]| format!("<{}, {}, {}>", a.1, b.1, c.1))
+                .collect(),
+        );
+        mesh.push_str(&self.material(code));
+        mesh.push_str("  }");
+        mesh
+    }
+
+    // nothing for the main colour, the reference supplies it
+    fn material(&mut self, code: u32) -> String {
+        if code == MAIN_COLOR {
+            return String::new();
+        }
+        self.colors.insert(code);
+        format!("    material {{ {} }}\n", color_identifier(code))
+    }
+
+    fn identifier(&mut self, filename: &str) -> String {
+        let base: String = format!("ldraw_{}", filename.to_lowercase())
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .take(MAX_IDENTIFIER)
+            .collect();
+
+        let mut identifier = base.to_string();
+        let mut counter = 1;
+        while self.taken.contains(&identifier) {
+            counter += 1;
+            let suffix = format!("_{}", counter);
+            let length = base.len().min(MAX_IDENTIFIER - suffix.len());
+            identifier = format!("{}{}", &base[..length], suffix);
+        }
+        self.taken.insert(identifier.to_string());
+        identifier
+    }
+}
+
+// direct colours are named after their hex value
+fn color_identifier(code: u32) -> String {
+    if code & 0xff00_0000 == 0x0200_0000 {
+        format!("ldraw_color_{:06x}", code & 0x00ff_ffff)
+    } else {
+        format!("ldraw_color_{}", code)
+    }
+}
+
+fn material(color: &LDrawColor) -> String {
+    let [r, g, b] = color.value.map(|channel| channel as f32 / 255.0);
+    let filter = if color.is_transparent() {
+        1.0 - color.alpha as f32 / 255.0
+    } else {
+        0.0
+    };
+
+    let mut finish = match color.finish {
+        ColorFinish::Chrome => {
+            "ambient 0.1 diffuse 0.2 specular 0.8 roughness 0.005 metallic brilliance 4 reflection { 0.6 metallic }".to_string()
+        }
+        ColorFinish::Metal => {
+            "ambient 0.1 diffuse 0.5 specular 0.6 roughness 0.02 metallic reflection { 0.3 metallic }".to_string()
+        }
+        ColorFinish::MatteMetallic => {
+            "ambient 0.1 diffuse 0.6 specular 0.3 roughness 0.05 metallic reflection { 0.1 metallic }".to_string()
+        }
+        ColorFinish::Pearlescent => {
+            "ambient 0.1 diffuse 0.6 specular 0.5 roughness 0.02 irid { 0.15 thickness 0.3 turbulence 0.2 }".to_string()
+        }
+        ColorFinish::Rubber => "ambient 0.1 diffuse 0.8 specular 0.05 roughness 0.3".to_string(),
+        _ if color.is_transparent() => {
+            "ambient 0.1 diffuse 0.3 phong 0.9 phong_size 80 reflection 0.08".to_string()
+        }
+        _ => "ambient 0.1 diffuse 0.7 phong 0.4 phong_size 40 reflection 0.03".to_string(),
+    };
+    if color.luminance > 0 {
+        write!(finish, " emission {}", color.luminance as f32 / 255.0).unwrap();
+    }
+
+    let mut material = format!(
+        "// {}\n#declare {} = material {{\n  texture {{\n    pigment {{ srgbf <{}, {}, {}, {}> }}\n    finish {{ {} }}\n  }}\n",
+        color.name,
+        color_identifier(color.code),
+        r,
+        g,
+        b,
+        filter,
+        finish
+    );
+    if color.is_transparent() {
+        material.push_str("  interior { ior 1.5 }\n");
+    }
+    material.push_str("}\n\n");
+    material
+}
+
+// POV-Ray transforms row vectors, so the columns of the matrix are written as rows
+fn matrix(matrix: &Matrix4<f32>) -> String {
+    let values: Vec<String> = [matrix.x, matrix.y, matrix.z, matrix.w]
+        .iter()
+        .flat_map(|column| [column.x, column.y, column.z])
+        .map(|value| value.to_string())
+        .collect();
+    format!("<{}>", values.join(", "))
+}
+
+// the camera set up like the viewer's and a key, fill and rim light around it
+fn camera_and_lights(camera: &CameraSetup, aspect: f32) -> String {
+    let pov = |vector: Vector3<f32>| format!("<{}, {}, {}>", vector.x, vector.y, -vector.z);
+
+    let mut text = String::from("camera {\n");
+    match camera.projection {
+        Projection::Perspective => {
+            let half_fov = (camera.field_of_view * 0.5).to_radians();
+            let horizontal = 2.0 * (half_fov.tan() * aspect).atan();
+            writeln!(
+                text,
+                "  perspective\n  right x * {}\n  up y\n  angle {}",
+                aspect,
+                horizontal.to_degrees()
+            )
+            .unwrap();
+        }
+        Projection::Orthographic => {
+            writeln!(
+                text,
+                "  orthographic\n  right x * {}\n  up y * {}",
+                camera.height * aspect,
+                camera.height
+            )
+            .unwrap();
+        }
+    }
+    writeln!(
+        text,
+        "  location {}\n  sky {}\n  look_at {}\n}}\n",
+        pov(camera.position),
+        pov(camera.up),
+        pov(camera.target)
+    )
+    .unwrap();
+
+    let direction = camera.direction();
+    let side = direction.cross(camera.up);
+    let distance = camera.distance();
+    let lights = [
+        (direction + camera.up * 0.8 - side * 0.6, 0.8),
+        (direction + camera.up * 0.2 + side * 0.9, 0.4),
+        (-direction + camera.up * 1.2, 0.3),
+    ];
+    for (offset, intensity) in lights.iter() {
+        let position = camera.target + offset * distance;
+        writeln!(
+            text,
+            "light_source {{ {} color rgb {} }}",
+            pov(position),
+            intensity
+        )
+        .unwrap();
+    }
+    text.push_str("\nbackground { color rgb 1 }\n");
+    text
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::ops::Mul;
+
+use js_sys::Array;
+use three_d::{vec3, InnerSpace, Matrix4, Quaternion, SquareMatrix, Vector3};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    coordinates::{CoordinateSystem, OutputFrame, Units},
+    export::{ExportModel, ExportedFile},
+    parser::part::LDrawBrick,
+};
+
+// vertices closer than this many millimetres are merged when welding
+const WELD_TOLERANCE: f32 = 1e-3;
+// faces within about 8 degrees of a direction count as lying on it
+const FLAT_COSINE: f32 = 0.99;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrintOptions {
+    // binary STL, ignored for 3MF
+    pub binary: bool,
+    // merges coincident vertices, so the mesh can be checked for holes
+    pub weld: bool,
+    // rotates the largest flat face onto the build plate and moves the model onto it
+    pub orient: bool,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        Self {
+            binary: true,
+            weld: true,
+            orient: false,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl PrintOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// what a slicer would complain about, counted over all objects
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeshReport {
+    pub objects: usize,
+    pub triangles: usize,
+    // edges with only one triangle
+    pub open_edges: usize,
+    // edges shared by more than two triangles
+    pub non_manifold_edges: usize,
+}
+
+#[wasm_bindgen]
+impl MeshReport {
+    pub fn is_manifold(&self) -> bool {
+        self.open_edges == 0 && self.non_manifold_edges == 0
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PrintMesh {
+    pub positions: Vec<Vector3<f32>>,
+    // counter-clockwise seen from outside
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl PrintMesh {
+    fn push(&mut self, triangle: [Vector3<f32>; 3]) {
+        let first = self.positions.len() as u32;
+        self.positions.extend_from_slice(&triangle);
+        self.triangles.push([first, first + 1, first + 2]);
+    }
+
+    pub fn triangle(&self, triangle: &[u32; 3]) -> [Vector3<f32>; 3] {
+        triangle.map(|index| self.positions[index as usize])
+    }
+
+    pub fn weld(&self) -> PrintMesh {
+        let mut welded = PrintMesh::default();
+        let mut lookup: HashMap<[i64; 3], u32> = HashMap::new();
+        let mut index = |position: Vector3<f32>| {
+            let key = [position.x, position.y, position.z]
+                .map(|value| (value / WELD_TOLERANCE).round() as i64);
+            *lookup.entry(key).or_insert_with(|| {
+                welded.positions.push(position);
+                welded.positions.len() as u32 - 1
+            })
+        };
+
+        let triangles: Vec<[u32; 3]> = self
+            .triangles
+            .iter()
+            .map(|triangle| triangle.map(|vertex| index(self.positions[vertex as usize])))
+            .collect();
+        // triangles that collapsed to a line or a point
+        welded.triangles = triangles
+            .into_iter()
+            .filter(|[a, b, c]| a != b && b != c && a != c)
+            .collect();
+        welded
+    }
+
+    fn report(&self, report: &mut MeshReport) {
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+        for triangle in self.triangles.iter() {
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+
+        report.objects += 1;
+        report.triangles += self.triangles.len();
+        report.open_edges += edges.values().filter(|count| **count == 1).count();
+        report.non_manifold_edges += edges.values().filter(|count| **count > 2).count();
+    }
+
+    fn transform(&mut self, matrix: &Matrix4<f32>) {
+        for position in self.positions.iter_mut() {
+            *position = matrix.mul(position.extend(1.0)).truncate();
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintObject {
+    pub name: String,
+    pub color: [u8; 4],
+    pub mesh: PrintMesh,
+}
+
+// one object per part in millimetres, +Z up
+pub fn print_objects(brick: &LDrawBrick, options: &PrintOptions) -> (Vec<PrintObject>, MeshReport) {
+    let frame = OutputFrame::new(CoordinateSystem::ZUp, Units::Millimetres).matrix();
+    let model = ExportModel::new(brick);
+
+    let mut objects = Vec::new();
+    for (node, transformation) in model.instances() {
+        let transformation = frame.mul(transformation);
+        // mirrored parts would turn inside out
+        let flip = transformation.determinant() < 0.0;
+        let mesh = &model.meshes[node.mesh.unwrap()];
+
+        // parts that are split into several colours keep the colour they were placed in
+        let mut print_mesh = PrintMesh::default();
+        for group in mesh.groups.iter() {
+            for triangle in group.positions.chunks_exact(3) {
+                let point =
+                    |position: &Vector3<f32>| transformation.mul(position.extend(1.0)).truncate();
+                let (a, b, c) = (
+                    point(&triangle[0]),
+                    point(&triangle[1]),
+                    point(&triangle[2]),
+                );
+                print_mesh.push(if flip { [a, c, b] } else { [a, b, c] });
+            }
+        }
+
+        objects.push(PrintObject {
+            name: node.name.to_string(),
+            color: brick.colors.resolve(mesh.key.color).rgba(),
+            mesh: print_mesh,
+        });
+    }
+
+    if options.orient {
+        orient(&mut objects);
+    }
+
+    let mut report = MeshReport::default();
+    for object in objects.iter_mut() {
+        let welded = object.mesh.weld();
+        welded.report(&mut report);
+        if options.weld {
+            object.mesh = welded;
+        }
+    }
+
+    (objects, report)
+}
+
+// puts the direction with the most flat area facing down and the model on z = 0,
+// centred on the origin
+fn orient(objects: &mut [PrintObject]) {
+    let mut areas: Vec<(Vector3<f32>, f32)> = Vec::new();
+    for object in objects.iter() {
+        for triangle in object.mesh.triangles.iter() {
+            let [a, b, c] = object.mesh.triangle(triangle);
+            let cross = (b - a).cross(c - a);
+            let area = cross.magnitude() * 0.5;
+            if area <= 0.0 {
+                continue;
+            }
+            let normal = cross.normalize();
+            match areas
+                .iter_mut()
+                .find(|(direction, _)| direction.dot(normal) > FLAT_COSINE)
+            {
+                Some((_, total)) => *total += area,
+                None => areas.push((normal, area)),
+            }
+        }
+    }
+
+    let down = vec3(0.0, 0.0, -1.0);
+    let rotation = areas
+        .iter()
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(normal, _)| {
+            Matrix4::from(Quaternion::from_arc(
+                *normal,
+                down,
+                Some(vec3(1.0, 0.0, 0.0)),
+            ))
+        })
+        .unwrap_or_else(Matrix4::identity);
+
+    for object in objects.iter_mut() {
+        object.mesh.transform(&rotation);
+    }
+
+    let mut min = vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    let mut max = vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for position in objects
+        .iter()
+        .flat_map(|object| object.mesh.positions.iter())
+    {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(position[axis]);
+            max[axis] = max[axis].max(position[axis]);
+        }
+    }
+    if min.x > max.x {
+        return;
+    }
+    let offset = vec3(-(min.x + max.x) * 0.5, -(min.y + max.y) * 0.5, -min.z);
+    for object in objects.iter_mut() {
+        object.mesh.transform(&Matrix4::from_translation(offset));
+    }
+}
+
+// the files of an STL or 3MF export and what was found while checking the meshes
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintExport {
+    files: Vec<ExportedFile>,
+    report: MeshReport,
+}
+
+impl PrintExport {
+    pub fn new(files: Vec<ExportedFile>, report: MeshReport) -> Self {
+        Self { files, report }
+    }
+}
+
+#[wasm_bindgen]
+impl PrintExport {
+    // an array of ExportedFile
+    #[wasm_bindgen(getter)]
+    pub fn files(&self) -> Array {
+        self.files.iter().cloned().map(JsValue::from).collect()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn report(&self) -> MeshReport {
+        self.report
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt::Write;
+
+use three_d::{InnerSpace, Vector3};
+
+use crate::export::print::PrintObject;
+
+fn normal([a, b, c]: &[Vector3<f32>; 3]) -> Vector3<f32> {
+    let cross = (b - a).cross(c - a);
+    if cross.magnitude2() > 0.0 {
+        cross.normalize()
+    } else {
+        cross
+    }
+}
+
+// all objects in one solid, STL has no notion of objects or colours
+pub fn write_binary(objects: &[PrintObject]) -> Vec<u8> {
+    let count: usize = objects
+        .iter()
+        .map(|object| object.mesh.triangles.len())
+        .sum();
+
+    let mut bytes = Vec::with_capacity(84 + count * 50);
+    let mut header = [0u8; 80];
+    let title = b"ldraw-renderer";
+    header[..title.len()].copy_from_slice(title);
+    bytes.extend_from_slice(&header);
+    bytes.extend_from_slice(&(count as u32).to_le_bytes());
+
+    for object in objects.iter() {
+        for triangle in object.mesh.triangles.iter() {
+            let triangle = object.mesh.triangle(triangle);
+            let normal = normal(&triangle);
+            for vector in std::iter::once(&normal).chain(triangle.iter()) {
+                for axis in 0..3 {
+                    bytes.extend_from_slice(&vector[axis].to_le_bytes());
+                }
+            }
+            // attribute byte count
+            bytes.extend_from_slice(&[0, 0]);
+        }
+    }
+    bytes
+}
+
+pub fn write_ascii(name: &str, objects: &[PrintObject]) -> String {
+    let mut stl = String::new();
+    writeln!(stl, "solid {}", name).unwrap();
+    for object in objects.iter() {
+        for triangle in object.mesh.triangles.iter() {
+            let triangle = object.mesh.triangle(triangle);
+            let normal = normal(&triangle);
+            writeln!(
+                stl,
+                "  facet normal {:e} {:e} {:e}",
+                normal.x, normal.y, normal.z
+            )
+            .unwrap();
+            stl.push_str("    outer loop\n");
+            for vertex in triangle.iter() {
+                writeln!(
+                    stl,
+                    "      vertex {:e} {:e} {:e}",
+                    vertex.x, vertex.y, vertex.z
+                )
+                .unwrap();
+            }
+            stl.push_str("    endloop\n  endfacet\n");
+        }
+    }
+    writeln!(stl, "endsolid {}", name).unwrap();
+    stl
+}
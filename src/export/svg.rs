@@ -0,0 +1,469 @@
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::ops::Mul;
+
+use three_d::{vec2, Matrix4, Vector2, Vector3, Vector4};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    bounds::ModelBounds,
+    camera::{CameraSetup, Projection, ViewPreset},
+    coordinates::OutputFrame,
+    parser::{
+        colors::ColorTable,
+        part::{LDrawBrick, LDrawFile},
+    },
+    raster::{RasterScene, RasterTriangle, Rasterizer},
+    scene::{part_instances, references},
+};
+
+// the depth buffer for hidden lines has this many samples per pixel along each axis
+const SUPERSAMPLING: u32 = 2;
+// how far behind a surface a line may be and still count as lying on it, relative to
+// the size of the model
+const DEPTH_TOLERANCE: f32 = 2e-3;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SvgOptions {
+    pub preset: ViewPreset,
+    pub projection: Projection,
+    pub width: u32,
+    pub height: u32,
+    // flat coloured faces under the lines, lines only otherwise
+    pub faces: bool,
+    pub line_width: f32,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            preset: ViewPreset::LDraw,
+            projection: Projection::Perspective,
+            width: 1024,
+            height: 768,
+            faces: true,
+            line_width: 1.0,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl SvgOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// the geometry of one part instance in the output frame, with resolved colours
+#[derive(Default)]
+struct SvgPart {
+    filename: String,
+    color: u32,
+    triangles: Vec<([Vector3<f32>; 3], u32)>,
+    lines: Vec<([Vector3<f32>; 2], u32)>,
+    // the line followed by its two control points
+    optional_lines: Vec<([Vector3<f32>; 4], u32)>,
+}
+
+// line art of the model: a layer of faces painted back to front and a layer of the
+// edges and active conditional lines that no surface hides, with a group per part
+// instance in each so they can be styled by index, file or colour. Fails for sizes the
+// depth buffer can't be allocated for
+pub fn write(brick: &LDrawBrick, options: &SvgOptions) -> Result<String, String> {
+    let frame = OutputFrame::default();
+    let parts: Vec<SvgPart> = part_instances(brick)
+        .into_iter()
+        .map(|instance| {
+            let mut part = SvgPart {
+                filename: instance.filename.to_string(),
+                color: instance.color,
+                ..SvgPart::default()
+            };
+            if let Some(file) = brick.files.get(&instance.filename) {
+                collect(
+                    brick,
+                    file,
+                    instance.color,
+                    frame.matrix().mul(instance.transformation),
+                    &mut part,
+                );
+            }
+            part
+        })
+        .collect();
+
+    let (width, height) = (options.width.max(1), options.height.max(1));
+    let aspect = width as f32 / height as f32;
+    let bounds = ModelBounds::compute(brick, frame).framed_aabb();
+    let camera = CameraSetup::preset(&bounds, options.preset, options.projection, &frame, aspect);
+    let projector = Projector::new(&parts, &brick.colors, &camera, width, height)?;
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n  <title>{2}</title>",
+        width,
+        height,
+        escape(&brick.entry_file)
+    )
+    .unwrap();
+    svg.push_str(
+        "  <style>\n    .faces path { stroke-width: 0.5; stroke-linejoin: round; }\n    .lines path { fill: none; stroke-linecap: round; }\n  </style>\n",
+    );
+
+    // parts further away are painted first, so the groups are ordered by depth
+    let mut order: Vec<usize> = (0..parts.len()).collect();
+    let depths: Vec<f32> = parts
+        .iter()
+        .map(|part| projector.part_depth(part))
+        .collect();
+    order.sort_by(|a, b| {
+        depths[*b]
+            .partial_cmp(&depths[*a])
+            .unwrap_or(Ordering::Equal)
+    });
+
+    if options.faces {
+        svg.push_str("  <g class=\"faces\">\n");
+        for index in order.iter() {
+            let part = &parts[*index];
+            svg.push_str(&group_start(*index, part));
+            for (color, path) in projector.faces(part) {
+                let [r, g, b, a] = brick.colors.resolve(color).rgba();
+                write!(
+                    svg,
+                    "      <path d=\"{}\" fill=\"#{:02x}{:02x}{:02x}\" stroke=\"#{:02x}{:02x}{:02x}\"",
+                    path, r, g, b, r, g, b
+                )
+                .unwrap();
+                if a < 255 {
+                    write!(svg, " fill-opacity=\"{:.3}\"", a as f32 / 255.0).unwrap();
+                }
+                svg.push_str("/>\n");
+            }
+            svg.push_str("    </g>\n");
+        }
+        svg.push_str("  </g>\n");
+    }
+
+    writeln!(
+        svg,
+        "  <g class=\"lines\" stroke-width=\"{}\">",
+        options.line_width
+    )
+    .unwrap();
+    for index in order.iter() {
+        let part = &parts[*index];
+        svg.push_str(&group_start(*index, part));
+
+        let optional: Vec<([Vector3<f32>; 2], u32)> = part
+            .optional_lines
+            .iter()
+            .filter(|(line, _)| projector.is_active(line))
+            .map(|([a, b, _, _], color)| ([*a, *b], *color))
+            .collect();
+        for (class, lines) in [("edges", &part.lines), ("conditional", &optional)] {
+            let mut colors: Vec<u32> = lines.iter().map(|(_, color)| *color).collect();
+            colors.sort_unstable();
+            colors.dedup();
+            for color in colors {
+                let mut path = String::new();
+                for (line, _) in lines.iter().filter(|(_, other)| *other == color) {
+                    for (from, to) in projector.visible_segments(line) {
+                        write!(path, "M{:.2} {:.2}L{:.2} {:.2}", from.x, from.y, to.x, to.y)
+                            .unwrap();
+                    }
+                }
+                if path.is_empty() {
+                    continue;
+                }
+                let [r, g, b, _] = brick.colors.resolve(color).rgba();
+                writeln!(
+                    svg,
+                    "      <path class=\"{}\" d=\"{}\" stroke=\"#{:02x}{:02x}{:02x}\"/>",
+                    class, path, r, g, b
+                )
+                .unwrap();
+            }
+        }
+        svg.push_str("    </g>\n");
+    }
+    svg.push_str("  </g>\n</svg>\n");
+    Ok(svg)
+}
+
+fn group_start(index: usize, part: &SvgPart) -> String {
+    format!(
+        "    <g class=\"part part-{}\" data-file=\"{}\" data-color=\"{}\">\n",
+        index,
+        escape(&part.filename),
+        part.color
+    )
+}
+
+fn collect(
+    brick: &LDrawBrick,
+    file: &LDrawFile,
+    color: u32,
+    matrix: Matrix4<f32>,
+    part: &mut SvgPart,
+) {
+    let point = |position: &Vector3<f32>| matrix.mul(position.extend(1.0)).truncate();
+    let resolve = |code: u32| brick.colors.resolve_code(code, color);
+
+    for triangle in file.triangles.iter() {
+        part.triangles.push((
+            [point(&triangle.x), point(&triangle.y), point(&triangle.z)],
+            resolve(triangle.color.value),
+        ));
+    }
+    for line in file.lines.iter() {
+        part.lines
+            .push(([point(&line.x), point(&line.y)], resolve(line.color.value)));
+    }
+    for line in file.optional_lines.iter() {
+        part.optional_lines.push((
+            [
+                point(&line.x),
+                point(&line.y),
+                point(&line.ox),
+                point(&line.oy),
+            ],
+            resolve(line.color.value),
+        ));
+    }
+    for reference in references(brick, file, color, &file.bfc_direction) {
+        collect(
+            brick,
+            reference.file,
+            reference.color,
+            matrix.mul(reference.matrix()),
+            part,
+        );
+    }
+}
+
+// the camera projection and a depth buffer of the opaque surfaces to test lines against
+struct Projector {
+    camera: CameraSetup,
+    view_projection: Matrix4<f32>,
+    width: f32,
+    height: f32,
+    depth: Rasterizer,
+}
+
+impl Projector {
+    fn new(
+        parts: &[SvgPart],
+        colors: &ColorTable,
+        camera: &CameraSetup,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, String> {
+        let triangles = parts
+            .iter()
+            .flat_map(|part| part.triangles.iter())
+            .filter(|(_, color)| !colors.is_transparent(*color))
+            .map(|(positions, _)| RasterTriangle {
+                positions: *positions,
+                color: [0, 0, 0, 255],
+            })
+            .collect();
+        // saturating, the rasterizer turns down what is too large
+        let mut depth = Rasterizer::new(
+            width.saturating_mul(SUPERSAMPLING),
+            height.saturating_mul(SUPERSAMPLING),
+        )?;
+        depth.draw(
+            &RasterScene {
+                triangles,
+                lines: Vec::new(),
+            },
+            camera,
+        );
+
+        Ok(Self {
+            camera: *camera,
+            view_projection: camera.view_projection(width as f32 / height as f32),
+            width: width as f32,
+            height: height as f32,
+            depth,
+        })
+    }
+
+    fn clip(&self, position: &Vector3<f32>) -> Vector4<f32> {
+        self.view_projection.mul(position.extend(1.0))
+    }
+
+    // pixel position and depth in 0..1, none behind the camera
+    fn screen(&self, clip: Vector4<f32>) -> Option<Vector3<f32>> {
+        if clip.w <= 0.0 || clip.z < -clip.w {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+        Some(Vector3::new(
+            (ndc.x + 1.0) * 0.5 * self.width,
+            (1.0 - ndc.y) * 0.5 * self.height,
+            (ndc.z + 1.0) * 0.5,
+        ))
+    }
+
+    // distance along the view direction for a depth buffer value
+    fn linear_depth(&self, depth: f32) -> f32 {
+        let (near, far) = (self.camera.z_near, self.camera.z_far);
+        let ndc = depth * 2.0 - 1.0;
+        match self.camera.projection {
+            Projection::Perspective => 2.0 * far * near / (far + near - ndc * (far - near)),
+            Projection::Orthographic => (ndc * (far - near) + far + near) * 0.5,
+        }
+    }
+
+    fn part_depth(&self, part: &SvgPart) -> f32 {
+        let depths: Vec<f32> = part
+            .triangles
+            .iter()
+            .flat_map(|(positions, _)| positions.iter())
+            .filter_map(|position| self.screen(self.clip(position)))
+            .map(|screen| screen.z)
+            .collect();
+        if depths.is_empty() {
+            return f32::INFINITY;
+        }
+        depths.iter().sum::<f32>() / depths.len() as f32
+    }
+
+    // the part's triangles back to front as one path per run of the same colour,
+    // leaving out triangles the depth buffer says are covered
+    fn faces(&self, part: &SvgPart) -> Vec<(u32, String)> {
+        let mut faces: Vec<([Vector3<f32>; 3], u32)> = part
+            .triangles
+            .iter()
+            .filter_map(|(positions, color)| {
+                let a = self.screen(self.clip(&positions[0]))?;
+                let b = self.screen(self.clip(&positions[1]))?;
+                let c = self.screen(self.clip(&positions[2]))?;
+                Some(([a, b, c], *color))
+            })
+            .filter(|(points, _)| self.is_face_visible(points))
+            .collect();
+        let depth = |points: &[Vector3<f32>; 3]| points[0].z + points[1].z + points[2].z;
+        faces.sort_by(|a, b| {
+            depth(&b.0)
+                .partial_cmp(&depth(&a.0))
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let mut paths: Vec<(u32, String)> = Vec::new();
+        for ([a, b, c], color) in faces {
+            if paths.last().map(|(last, _)| *last != color).unwrap_or(true) {
+                paths.push((color, String::new()));
+            }
+            let path = &mut paths.last_mut().unwrap().1;
+            write!(
+                path,
+                "M{:.2} {:.2}L{:.2} {:.2}L{:.2} {:.2}Z",
+                a.x, a.y, b.x, b.y, c.x, c.y
+            )
+            .unwrap();
+        }
+        paths
+    }
+
+    // sampled at the centre and towards the corners
+    fn is_face_visible(&self, points: &[Vector3<f32>; 3]) -> bool {
+        let center = (points[0] + points[1] + points[2]) / 3.0;
+        std::iter::once(center)
+            .chain(points.iter().map(|point| center + (point - center) * 0.75))
+            .any(|sample| self.is_visible(sample))
+    }
+
+    fn is_visible(&self, point: Vector3<f32>) -> bool {
+        let x = (point.x * SUPERSAMPLING as f32).floor() as i64;
+        let y = (point.y * SUPERSAMPLING as f32).floor() as i64;
+        // a line on a silhouette lies next to whatever is behind it
+        let mut nearest = f32::NEG_INFINITY;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (x, y) = (x + dx, y + dy);
+                let depth = if x < 0 || y < 0 {
+                    f32::INFINITY
+                } else {
+                    self.depth.depth(x as u32, y as u32)
+                };
+                nearest = nearest.max(depth);
+            }
+        }
+        if nearest == f32::INFINITY {
+            return true;
+        }
+        self.linear_depth(point.z)
+            <= self.linear_depth(nearest) + self.camera.radius * DEPTH_TOLERANCE
+    }
+
+    // the pieces of the line no surface covers, in pixels
+    fn visible_segments(&self, line: &[Vector3<f32>; 2]) -> Vec<(Vector2<f32>, Vector2<f32>)> {
+        let (from, to) = (self.clip(&line[0]), self.clip(&line[1]));
+        let (start, end) = match (self.screen(from), self.screen(to)) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Vec::new(),
+        };
+
+        // interpolated in clip space, so depths stay right under perspective
+        let delta = end - start;
+        let length = delta.x.hypot(delta.y);
+        let steps = (length * SUPERSAMPLING as f32).ceil().max(1.0) as usize;
+        let samples: Vec<Option<Vector3<f32>>> = (0..=steps)
+            .map(|step| {
+                let t = step as f32 / steps as f32;
+                self.screen(from + (to - from) * t)
+                    .filter(|point| self.is_visible(*point))
+            })
+            .collect();
+
+        let mut segments = Vec::new();
+        let mut run: Option<(Vector3<f32>, Vector3<f32>)> = None;
+        for sample in samples {
+            run = match (run, sample) {
+                (None, Some(point)) => Some((point, point)),
+                (Some((first, _)), Some(point)) => Some((first, point)),
+                (Some(segment), None) => {
+                    segments.push(segment);
+                    None
+                }
+                (None, None) => None,
+            };
+        }
+        segments.extend(run);
+        segments
+            .into_iter()
+            .filter(|(first, last)| first != last)
+            .map(|(first, last)| (vec2(first.x, first.y), vec2(last.x, last.y)))
+            .collect()
+    }
+
+    // a conditional line shows when both control points fall on the same side of it
+    fn is_active(&self, line: &[Vector3<f32>; 4]) -> bool {
+        let points: Option<Vec<Vector3<f32>>> = line
+            .iter()
+            .map(|position| self.screen(self.clip(position)))
+            .collect();
+        let points = match points {
+            Some(points) => points,
+            None => return false,
+        };
+        let side = |control: Vector3<f32>| {
+            (points[1].x - points[0].x) * (control.y - points[0].y)
+                - (points[1].y - points[0].y) * (control.x - points[0].x)
+        };
+        side(points[2]) * side(points[3]) > 0.0
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,113 @@
+use std::fmt::Write as _;
+use std::io::{Cursor, Write};
+
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::export::print::PrintObject;
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+  <Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+  <Default Extension="model" ContentType="application/vnd.ms-package.3dmanufacturing-3dmodel+xml"/>
+</Types>
+"#;
+
+const RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Target="/3D/3dmodel.model" Id="rel0" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>
+"#;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// the model part of the package, one object per part coloured through a base material
+pub fn model_xml(objects: &[PrintObject]) -> String {
+    let mut xml = String::new();
+    xml.push_str(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <model unit=\"millimeter\" xml:lang=\"en-US\" \
+         xmlns=\"http://schemas.microsoft.com/3dmanufacturing/core/2015/02\">\n  <resources>\n",
+    );
+
+    let mut colors: Vec<[u8; 4]> = Vec::new();
+    for object in objects.iter() {
+        if !colors.contains(&object.color) {
+            colors.push(object.color);
+        }
+    }
+    xml.push_str("    <basematerials id=\"1\">\n");
+    for [r, g, b, a] in colors.iter() {
+        writeln!(
+            xml,
+            "      <base name=\"#{0:02X}{1:02X}{2:02X}\" displaycolor=\"#{0:02X}{1:02X}{2:02X}{3:02X}\"/>",
+            r, g, b, a
+        )
+        .unwrap();
+    }
+    xml.push_str("    </basematerials>\n");
+
+    // id 1 is taken by the materials
+    for (index, object) in objects.iter().enumerate() {
+        let color = colors
+            .iter()
+            .position(|color| *color == object.color)
+            .unwrap();
+        writeln!(
+            xml,
+            "    <object id=\"{}\" name=\"{}\" type=\"model\" pid=\"1\" pindex=\"{}\">\n      <mesh>\n        <vertices>",
+            index + 2,
+            escape(&object.name),
+            color
+        )
+        .unwrap();
+        for vertex in object.mesh.positions.iter() {
+            writeln!(
+                xml,
+                "          <vertex x=\"{}\" y=\"{}\" z=\"{}\"/>",
+                vertex.x, vertex.y, vertex.z
+            )
+            .unwrap();
+        }
+        xml.push_str("        </vertices>\n        <triangles>\n");
+        for [v1, v2, v3] in object.mesh.triangles.iter() {
+            writeln!(
+                xml,
+                "          <triangle v1=\"{}\" v2=\"{}\" v3=\"{}\"/>",
+                v1, v2, v3
+            )
+            .unwrap();
+        }
+        xml.push_str("        </triangles>\n      </mesh>\n    </object>\n");
+    }
+
+    xml.push_str("  </resources>\n  <build>\n");
+    for index in 0..objects.len() {
+        writeln!(xml, "    <item objectid=\"{}\"/>", index + 2).unwrap();
+    }
+    xml.push_str("  </build>\n</model>\n");
+    xml
+}
+
+pub fn write(objects: &[PrintObject]) -> Result<Vec<u8>, String> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let entries = [
+        ("[Content_Types].xml", CONTENT_TYPES.to_string()),
+        ("_rels/.rels", RELATIONSHIPS.to_string()),
+        ("3D/3dmodel.model", model_xml(objects)),
+    ];
+    for (name, content) in entries.iter() {
+        zip.start_file(*name, options)
+            .map_err(|error| error.to_string())?;
+        zip.write_all(content.as_bytes())
+            .map_err(|error| error.to_string())?;
+    }
+    zip.finish()
+        .map(|cursor| cursor.into_inner())
+        .map_err(|error| error.to_string())
+}
@@ -0,0 +1,178 @@
+use js_sys::{Function, Promise};
+use wasm_bindgen::prelude::*;
+use web_sys::{AbortController, AbortSignal};
+use winit::event_loop::EventLoopProxy;
+
+use crate::{
+    camera::ViewPreset,
+    capture::{CaptureOptions, CaptureRequest},
+    events::RenderingUserEvent,
+    picking::PickListeners,
+    progress::Progress,
+    props::RenderProps,
+    selection::{SelectionCommand, SelectionStyle, UnselectedStyle},
+};
+
+// what create_window resolves to, the window lives until dispose or cancel is called
+#[wasm_bindgen]
+pub struct WindowHandle {
+    id: usize,
+    proxy: EventLoopProxy<RenderingUserEvent<()>>,
+    abort: AbortController,
+    progress: Progress,
+    picks: PickListeners,
+}
+
+impl WindowHandle {
+    pub fn new(
+        id: usize,
+        proxy: EventLoopProxy<RenderingUserEvent<()>>,
+        abort: AbortController,
+        progress: Progress,
+        picks: PickListeners,
+    ) -> Self {
+        Self {
+            id,
+            proxy,
+            abort,
+            progress,
+            picks,
+        }
+    }
+
+    fn send_selection(&self, command: SelectionCommand) {
+        self.proxy
+            .send_event(RenderingUserEvent::InternalSelection(self.id, command))
+            .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
+    }
+}
+
+#[wasm_bindgen]
+impl WindowHandle {
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    // the listener is called with { type, ... } load and lifecycle events
+    pub fn add_listener(&self, listener: Function) {
+        self.progress.add_listener(listener);
+    }
+
+    pub fn remove_listener(&self, listener: &Function) {
+        self.progress.remove_listener(listener);
+    }
+
+    // called with a PickResult, or null when nothing was hit
+    pub fn on_click(&self, listener: Option<Function>) {
+        self.picks.set_click(listener);
+    }
+
+    // called whenever the part under the cursor changes, null when it leaves the model
+    pub fn on_hover(&self, listener: Option<Function>) {
+        self.picks.set_hover(listener);
+    }
+
+    pub fn update(&self, props: &RenderProps) {
+        self.proxy
+            .send_event(RenderingUserEvent::InternalUpdateProps(
+                self.id,
+                props.clone(),
+            ))
+            .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
+    }
+
+    pub fn set_view(&self, preset: ViewPreset) {
+        self.proxy
+            .send_event(RenderingUserEvent::InternalSetView(self.id, preset))
+            .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
+    }
+
+    pub fn fit_to_bounds(&self) {
+        self.proxy
+            .send_event(RenderingUserEvent::InternalFitToBounds(self.id))
+            .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
+    }
+
+    // instances are indices into the part instances, as reported by PickResult.instance
+    pub fn select(&self, instances: Vec<u32>) {
+        self.send_selection(SelectionCommand::Select(
+            instances
+                .into_iter()
+                .map(|instance| instance as usize)
+                .collect(),
+        ));
+    }
+
+    pub fn deselect(&self, instances: Vec<u32>) {
+        self.send_selection(SelectionCommand::Deselect(
+            instances
+                .into_iter()
+                .map(|instance| instance as usize)
+                .collect(),
+        ));
+    }
+
+    pub fn clear_selection(&self) {
+        self.send_selection(SelectionCommand::Clear);
+    }
+
+    // the predicate is called with { instance, filename, color, color_name, source_file,
+    // source_line } for every part and adds those it returns true for
+    pub fn select_where(&self, predicate: Function) {
+        self.send_selection(SelectionCommand::SelectWhere(predicate));
+    }
+
+    pub fn set_selection_style(
+        &self,
+        style: SelectionStyle,
+        unselected: UnselectedStyle,
+        r: u8,
+        g: u8,
+        b: u8,
+    ) {
+        self.send_selection(SelectionCommand::SetStyle(style, unselected, [r, g, b]));
+    }
+
+    // renders the current view offscreen and resolves to the PNG bytes as a Uint8Array
+    pub fn capture(&self, width: u32, height: u32, options: &CaptureOptions) -> Promise {
+        let options = *options;
+        Promise::new(&mut |resolve, reject| {
+            let request = CaptureRequest::new(width, height, options, resolve, reject);
+            if let Err(message) = request.validate() {
+                request.reject(&message);
+                return;
+            }
+            self.proxy
+                .send_event(RenderingUserEvent::InternalCapture(self.id, request))
+                .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
+        })
+    }
+
+    pub fn dispose(&self) {
+        self.proxy
+            .send_event(RenderingUserEvent::InternalDeleteWindow(self.id))
+            .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
+    }
+
+    // aborts whatever the window is still fetching and disposes it
+    pub fn cancel(&self) {
+        self.abort.abort();
+        self.dispose();
+    }
+}
+
+// a controller that is also aborted when the given signal from JS is
+pub fn linked_abort_controller(signal: Option<&AbortSignal>) -> Result<AbortController, JsValue> {
+    let controller = AbortController::new()?;
+    if let Some(signal) = signal {
+        if signal.aborted() {
+            controller.abort();
+        } else {
+            let linked = controller.clone();
+            let forward = Closure::once_into_js(move || linked.abort());
+            signal.add_event_listener_with_callback("abort", forward.unchecked_ref())?;
+        }
+    }
+    Ok(controller)
+}
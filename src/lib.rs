@@ -1,13 +1,60 @@
 mod events;
+mod handle;
 mod rendering;
 mod utils;
 
+pub mod baked;
+pub mod bounds;
+pub mod cache;
+pub mod camera;
+pub mod capture;
+pub mod coordinates;
+pub mod debug;
+pub mod errors;
+pub mod export;
+pub mod materials;
 pub mod parser;
+pub mod picking;
+pub mod progress;
+pub mod progressive;
+pub mod props;
+pub mod raster;
+pub mod scene;
+pub mod selection;
+pub mod smoothing;
+pub mod worker;
 
-use events::{Rendering, RenderingUserEvent};
-use three_d::{Window, WindowError, WindowSettings};
+use bounds::ModelBounds;
+use cache::{IndexedDbStorage, PartCache};
+use camera::ViewPreset;
+use coordinates::OutputFrame;
+use errors::{LoadError, LoadErrorKind};
+use events::{Rendering, RenderingUserEvent, RenderingWindow};
+use export::{
+    gltf::{Gltf, GltfOptions},
+    obj::{Obj, ObjOptions},
+    pov::{self, PovOptions},
+    print::{print_objects, PrintExport, PrintOptions},
+    stl,
+    svg::{self, SvgOptions},
+    threemf, ExportedFile,
+};
+pub use handle::WindowHandle;
+use js_sys::Array;
+use js_sys::Function;
+use picking::PickListeners;
+use progress::{LoadEvent, Progress};
+use progressive::{LoadedBrick, ProgressiveLoad};
+use props::RenderProps;
+use scene::{Scene, SceneOptions, TransparencySort};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use three_d::{Window, WindowSettings};
 use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{AbortSignal, HtmlCanvasElement, Worker};
 use winit::event_loop::{EventLoopProxy, EventLoopWindowTarget};
+use worker::WorkerClient;
 
 use parser::part::{self, LDrawBrick};
 
@@ -19,6 +66,8 @@ use crate::rendering::render_brick;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+const CACHE_DATABASE: &str = "ldraw-renderer";
+
 #[non_exhaustive]
 #[wasm_bindgen]
 pub struct RenderingNever(Rendering<()>);
@@ -32,7 +81,18 @@ impl RenderingNever {
 
     #[wasm_bindgen]
     pub fn get_proxy(&self) -> CustomEventLoopProxy {
-        CustomEventLoopProxy(self.0.get_proxy(), 0)
+        CustomEventLoopProxy {
+            proxy: self.0.get_proxy(),
+            next_id: Cell::new(0),
+            settings: RefCell::new(LoadSettings {
+                scene_options: SceneOptions::default(),
+                props: RenderProps::default(),
+                prefer_baked: false,
+                progressive: true,
+                cache: None,
+                worker: None,
+            }),
+        }
     }
 
     #[wasm_bindgen]
@@ -41,114 +101,490 @@ impl RenderingNever {
     }
 }
 
+// every method takes &self and no borrow is held across an await, so JS can start a load
+// or change a setting while another load is still pending
 #[wasm_bindgen]
-pub struct CustomEventLoopProxy(EventLoopProxy<RenderingUserEvent<()>>, usize);
+pub struct CustomEventLoopProxy {
+    proxy: EventLoopProxy<RenderingUserEvent<()>>,
+    next_id: Cell<usize>,
+    settings: RefCell<LoadSettings>,
+}
+
+// what windows are loaded with, each load works on a copy taken when it starts
+#[derive(Clone)]
+struct LoadSettings {
+    scene_options: SceneOptions,
+    props: RenderProps,
+    // try the baked brick before the bundle of text files
+    prefer_baked: bool,
+    // open the window before every file is parsed, with placeholders for the missing parts
+    progressive: bool,
+    cache: Option<Rc<PartCache<IndexedDbStorage>>>,
+    worker: Option<Rc<WorkerClient>>,
+}
 
 #[wasm_bindgen]
 impl CustomEventLoopProxy {
     #[wasm_bindgen]
     pub fn send_event(&self) {
-        self.0
+        self.proxy
             .send_event(RenderingUserEvent::Other(()))
             .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
     }
 
+    // resolves to a WindowHandle or rejects with { kind, file, line, message }, aborting
+    // the signal cancels the fetches of the part. The listener gets the progress events
+    // until the handle is there to add more
     #[wasm_bindgen]
-    pub async fn create_window(&mut self, canvas_id: &str, brick_id: &str) -> usize {
-        let brick_result = part::parse_part(brick_id).await;
-        let brick = brick_result.ok().unwrap(); // TODO: handle errors form parser !!!
-        let value = create_window(canvas_id, brick);
-        let id = self.1;
-        self.0
-            .send_event(RenderingUserEvent::InternalCreateWindow(id, value))
-            .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
-        self.1 += 1;
-        id
+    pub async fn create_window(
+        &self,
+        canvas_id: &str,
+        brick_id: &str,
+        signal: Option<AbortSignal>,
+        listener: Option<Function>,
+    ) -> Result<WindowHandle, JsValue> {
+        let progress = Progress::new();
+        if let Some(listener) = listener {
+            progress.add_listener(listener);
+        }
+
+        let result = self
+            .load_window(canvas_id, brick_id, signal, progress.clone())
+            .await;
+        if let Err(error) = &result {
+            progress.emit(LoadEvent::Error(error.clone()));
+        }
+        Ok(result?)
+    }
+
+    async fn load_window(
+        &self,
+        canvas_id: &str,
+        brick_id: &str,
+        signal: Option<AbortSignal>,
+        progress: Progress,
+    ) -> Result<WindowHandle, LoadError> {
+        let canvas = find_canvas(canvas_id)?;
+        let abort = handle::linked_abort_controller(signal.as_ref()).map_err(|error| {
+            LoadError::new(LoadErrorKind::Fetch, errors::describe_js_error(&error))
+        })?;
+        let id = self.take_id();
+        let settings = self.settings.borrow().clone();
+
+        let signal = abort.signal();
+        let (loaded, rest) = settings.load_brick(brick_id, &signal, &progress).await?;
+        if signal.aborted() {
+            return Err(LoadError::cancelled());
+        }
+
+        let picks = PickListeners::new();
+        self.send_window(
+            id,
+            canvas,
+            loaded,
+            &settings,
+            progress.clone(),
+            picks.clone(),
+        );
+
+        if let Some(rest) = rest {
+            let proxy = self.proxy.clone();
+            let cache = settings.cache.clone();
+            let brick_id = brick_id.to_string();
+            let progress = progress.clone();
+            spawn_local(async move {
+                match rest.run(id, &proxy, &signal, &progress).await {
+                    Ok(brick) => {
+                        if let Some(cache) = cache {
+                            cache_brick(&cache, &brick_id, &brick, &progress).await;
+                        }
+                    }
+                    Err(error) if error.kind == LoadErrorKind::Cancelled => {}
+                    Err(error) => progress.emit(LoadEvent::Error(error)),
+                }
+            });
+        }
+
+        Ok(WindowHandle::new(
+            id,
+            self.proxy.clone(),
+            abort,
+            progress,
+            picks,
+        ))
     }
 
     #[wasm_bindgen]
     pub fn delete_window(&self, id: usize) {
-        self.0
+        self.proxy
             .send_event(RenderingUserEvent::InternalDeleteWindow(id))
             .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
     }
 
     #[wasm_bindgen]
-    pub fn update_prop(&self, value: u8) {
-        self.0
-            .send_event(RenderingUserEvent::InternalUpdateProps(value))
+    pub fn set_view(&self, id: usize, preset: ViewPreset) {
+        self.proxy
+            .send_event(RenderingUserEvent::InternalSetView(id, preset))
+            .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
+    }
+
+    #[wasm_bindgen]
+    pub fn fit_to_bounds(&self, id: usize) {
+        self.proxy
+            .send_event(RenderingUserEvent::InternalFitToBounds(id))
             .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
     }
+
+    // applies to windows created afterwards
+    #[wasm_bindgen]
+    pub fn set_output_frame(&self, frame: OutputFrame) {
+        self.settings.borrow_mut().scene_options.frame = frame;
+    }
+
+    // parts are fetched, parsed and built into scenes in this worker, which runs
+    // worker.ts. None loads them on this thread again
+    #[wasm_bindgen]
+    pub fn set_worker(&self, worker: Option<Worker>) {
+        self.settings.borrow_mut().worker = worker.map(|worker| Rc::new(WorkerClient::new(worker)));
+    }
+
+    // applies to windows created afterwards, only when parts are loaded on this thread
+    #[wasm_bindgen]
+    pub fn set_progressive(&self, progressive: bool) {
+        self.settings.borrow_mut().progressive = progressive;
+    }
+
+    // applies to windows created afterwards
+    #[wasm_bindgen]
+    pub fn set_prefer_baked(&self, prefer_baked: bool) {
+        self.settings.borrow_mut().prefer_baked = prefer_baked;
+    }
+
+    // keeps parsed parts in IndexedDB across sessions, entries of other library versions
+    // are dropped right away. The listener is called with (version, removed) whenever the
+    // library is updated
+    #[wasm_bindgen]
+    pub async fn enable_cache(
+        &self,
+        library_version: String,
+        max_bytes: usize,
+        listener: Option<Function>,
+    ) -> Result<(), JsValue> {
+        let storage = IndexedDbStorage::open(CACHE_DATABASE).await?;
+        let cache = PartCache::new(storage, &library_version, max_bytes);
+        if let Some(listener) = listener {
+            cache.on_invalidate(move |version, removed| {
+                let version = JsValue::from_str(version);
+                let removed = JsValue::from_f64(removed as f64);
+                if let Err(error) = listener.call2(&JsValue::NULL, &version, &removed) {
+                    log::warn!("cache listener threw {:?}", error);
+                }
+            });
+        }
+        cache.update_library(&library_version).await?;
+        self.settings.borrow_mut().cache = Some(Rc::new(cache));
+        Ok(())
+    }
+
+    // resolves to the number of cached parts that were dropped
+    #[wasm_bindgen]
+    pub async fn update_library(&self, library_version: String) -> Result<usize, JsValue> {
+        let cache = self.settings.borrow().cache.clone();
+        match cache {
+            Some(cache) => Ok(cache.update_library(&library_version).await?),
+            None => Ok(0),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn disable_cache(&self) {
+        self.settings.borrow_mut().cache = None;
+    }
+
+    // applies to windows created afterwards
+    #[wasm_bindgen]
+    pub fn set_transparency_sort(&self, sort: TransparencySort) {
+        self.settings.borrow_mut().scene_options.transparency_sort = sort;
+    }
+
+    #[wasm_bindgen]
+    pub fn update_props(&self, id: usize, props: &RenderProps) {
+        self.proxy
+            .send_event(RenderingUserEvent::InternalUpdateProps(id, props.clone()))
+            .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
+    }
+
+    // applies to windows created afterwards
+    #[wasm_bindgen]
+    pub fn set_default_props(&self, props: &RenderProps) {
+        self.settings.borrow_mut().props = props.clone();
+    }
 }
 
-#[wasm_bindgen]
-pub struct RenderingParams {
-    red: i32,
+// for callers in Rust that already have the brick, such as the tests
+impl CustomEventLoopProxy {
+    // opens a window with the current settings and returns its id for the other methods
+    pub fn open_window(&self, canvas: HtmlCanvasElement, loaded: LoadedBrick) -> usize {
+        let id = self.take_id();
+        let settings = self.settings.borrow().clone();
+        self.send_window(
+            id,
+            canvas,
+            loaded,
+            &settings,
+            Progress::new(),
+            PickListeners::new(),
+        );
+        id
+    }
+
+    fn take_id(&self) -> usize {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    fn send_window(
+        &self,
+        id: usize,
+        canvas: HtmlCanvasElement,
+        loaded: LoadedBrick,
+        settings: &LoadSettings,
+        progress: Progress,
+        picks: PickListeners,
+    ) {
+        let value = create_window(
+            canvas,
+            loaded,
+            settings.scene_options.clone(),
+            settings.props.clone(),
+            progress,
+            picks,
+        );
+        self.proxy
+            .send_event(RenderingUserEvent::InternalCreateWindow(id, value))
+            .unwrap_or_else(|_| panic!("Something went horribly wrong!"));
+    }
 }
 
-#[wasm_bindgen]
-impl RenderingParams {
-    #[wasm_bindgen(constructor)]
-    pub fn new(val: i32) -> RenderingParams {
-        RenderingParams { red: val }
+impl LoadSettings {
+    // the cache is only an optimisation, when it fails the brick is loaded as usual. A
+    // scene comes with it when the worker built one. Loaded on this thread, the window
+    // gets the brick once the model file is parsed and the rest follows in the background
+    async fn load_brick(
+        &self,
+        brick_id: &str,
+        signal: &AbortSignal,
+        progress: &Progress,
+    ) -> Result<(LoadedBrick, Option<ProgressiveLoad>), LoadError> {
+        if let Some(cache) = &self.cache {
+            let key = cache.library_key(&format!("{}.dat", brick_id));
+            match cache.get_brick(&key).await {
+                Ok(Some(brick)) => return Ok((LoadedBrick::complete(brick, None), None)),
+                Ok(None) => {}
+                Err(error) => progress.emit(LoadEvent::Warning(error)),
+            }
+        }
+
+        if self.progressive && self.worker.is_none() && !self.prefer_baked {
+            let load = ProgressiveLoad::start(brick_id, signal, progress).await?;
+            return Ok((load.loaded(), Some(load)));
+        }
+        let (brick, scene) = self.fetch_brick(brick_id, signal, progress).await?;
+        if let Some(cache) = &self.cache {
+            cache_brick(cache, brick_id, &brick, progress).await;
+        }
+        Ok((LoadedBrick::complete(brick, scene), None))
     }
 
-    pub fn get(&self) -> i32 {
-        self.red
+    async fn fetch_brick(
+        &self,
+        brick_id: &str,
+        signal: &AbortSignal,
+        progress: &Progress,
+    ) -> Result<(LDrawBrick, Option<Scene>), LoadError> {
+        match &self.worker {
+            Some(worker) => {
+                let (brick, scene) = worker
+                    .load(
+                        brick_id,
+                        &self.scene_options,
+                        self.prefer_baked,
+                        signal,
+                        progress,
+                    )
+                    .await?;
+                Ok((brick, Some(scene)))
+            }
+            None => {
+                let (brick, baked) =
+                    fetch_brick(brick_id, self.prefer_baked, signal, progress).await?;
+                Ok((brick, baked.map(|scene| scene.framed(&self.scene_options))))
+            }
+        }
     }
+}
 
-    pub fn set(&mut self, val: i32) {
-        self.red = val;
+async fn cache_brick(
+    cache: &PartCache<IndexedDbStorage>,
+    brick_id: &str,
+    brick: &LDrawBrick,
+    progress: &Progress,
+) {
+    let key = cache.library_key(&format!("{}.dat", brick_id));
+    if let Err(error) = cache.put_brick(&key, brick).await {
+        progress.emit(LoadEvent::Warning(error));
     }
 }
 
-pub fn create_window(
-    canvas_id: &str,
-    brick: LDrawBrick,
-) -> Box<
-    dyn FnOnce(
-        &EventLoopWindowTarget<RenderingUserEvent<()>>,
-    ) -> Box<
-        dyn FnMut(
-            &winit::event::Event<RenderingUserEvent<()>>,
-            &winit::event_loop::EventLoopWindowTarget<RenderingUserEvent<()>>,
-            &mut winit::event_loop::ControlFlow,
-        ),
-    >,
-> {
-    wasm_logger::init(wasm_logger::Config::default());
+// a missing or stale baked brick is reported as a warning and the text files are loaded
+// instead. A baked scene comes along in LDraw space
+async fn fetch_brick(
+    brick_id: &str,
+    prefer_baked: bool,
+    signal: &AbortSignal,
+    progress: &Progress,
+) -> Result<(LDrawBrick, Option<Scene>), LoadError> {
+    if prefer_baked {
+        match baked::fetch_baked(brick_id, Some(signal), progress).await {
+            Ok(baked) => return Ok(baked),
+            Err(error)
+                if matches!(
+                    error.kind,
+                    LoadErrorKind::MissingFile | LoadErrorKind::Stale
+                ) =>
+            {
+                progress.emit(LoadEvent::Warning(error));
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    Ok((
+        part::parse_part(brick_id, Some(signal), progress).await?,
+        None,
+    ))
+}
+
+#[wasm_bindgen]
+pub async fn measure_part(brick_id: &str, frame: OutputFrame) -> Result<ModelBounds, JsValue> {
+    let brick = part::parse_part(brick_id, None, &Progress::new()).await?;
+    Ok(ModelBounds::compute(&brick, frame))
+}
+
+// resolves to an array of ExportedFile, a .glb or a .gltf and its .bin
+#[wasm_bindgen]
+pub async fn export_gltf(brick_id: &str, options: &GltfOptions) -> Result<Array, JsValue> {
+    let brick = part::parse_part(brick_id, None, &Progress::new()).await?;
+    let files = Gltf::new(&brick, options).files(brick_id, options.binary);
+    Ok(files.into_iter().map(JsValue::from).collect())
+}
+
+// an .obj with its .mtl, smoothed the way LDraw parts are meant to look
+#[wasm_bindgen]
+pub async fn export_obj(brick_id: &str, options: &ObjOptions) -> Result<Array, JsValue> {
+    let brick = part::parse_part(brick_id, None, &Progress::new()).await?;
+    let files = Obj::files(&brick, options, brick_id);
+    Ok(files.into_iter().map(JsValue::from).collect())
+}
+
+// a POV-Ray scene framed like the viewer would frame the model
+#[wasm_bindgen]
+pub async fn export_pov(brick_id: &str, options: &PovOptions) -> Result<ExportedFile, JsValue> {
+    let brick = part::parse_part(brick_id, None, &Progress::new()).await?;
+    let scene = pov::write(&brick, options);
+    Ok(ExportedFile::new(
+        format!("{}.pov", brick_id),
+        scene.into_bytes(),
+    ))
+}
 
-    let websys_window = web_sys::window()
-        .ok_or(WindowError::WindowCreation)
-        .unwrap();
-    let document = websys_window
-        .document()
-        .ok_or(WindowError::DocumentMissing)
-        .unwrap();
-    let canvas_element = document
+// vector line art with hidden lines removed, one group per part
+#[wasm_bindgen]
+pub async fn export_svg(brick_id: &str, options: &SvgOptions) -> Result<ExportedFile, JsValue> {
+    let brick = part::parse_part(brick_id, None, &Progress::new()).await?;
+    let drawing = svg::write(&brick, options).map_err(|message| JsValue::from_str(&message))?;
+    Ok(ExportedFile::new(
+        format!("{}.svg", brick_id),
+        drawing.into_bytes(),
+    ))
+}
+
+// binary or ASCII STL in millimetres, together with a report on the mesh
+#[wasm_bindgen]
+pub async fn export_stl(brick_id: &str, options: &PrintOptions) -> Result<PrintExport, JsValue> {
+    let brick = part::parse_part(brick_id, None, &Progress::new()).await?;
+    let (objects, report) = print_objects(&brick, options);
+    let bytes = if options.binary {
+        stl::write_binary(&objects)
+    } else {
+        stl::write_ascii(brick_id, &objects).into_bytes()
+    };
+    let file = ExportedFile::new(format!("{}.stl", brick_id), bytes);
+    Ok(PrintExport::new(vec![file], report))
+}
+
+// a 3MF package with one coloured object per part
+#[wasm_bindgen]
+pub async fn export_3mf(brick_id: &str, options: &PrintOptions) -> Result<PrintExport, JsValue> {
+    let brick = part::parse_part(brick_id, None, &Progress::new()).await?;
+    let (objects, report) = print_objects(&brick, options);
+    let bytes = threemf::write(&objects).map_err(|message| JsValue::from_str(&message))?;
+    let file = ExportedFile::new(format!("{}.3mf", brick_id), bytes);
+    Ok(PrintExport::new(vec![file], report))
+}
+
+fn find_canvas(canvas_id: &str) -> Result<HtmlCanvasElement, LoadError> {
+    let canvas_error = |message: String| LoadError::new(LoadErrorKind::Canvas, message);
+
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| canvas_error("there is no document to look for canvases in".to_string()))?;
+    document
         .get_element_by_id(canvas_id)
-        .expect("settings doesn't contain canvas and DOM doesn't have a canvas element either")
-        .dyn_into::<web_sys::HtmlCanvasElement>()
-        .map_err(|e| WindowError::CanvasConvertFailed(format!("{:?}", e)))
-        .unwrap();
+        .ok_or_else(|| canvas_error(format!("no element with id {}", canvas_id)))?
+        .dyn_into::<HtmlCanvasElement>()
+        .map_err(|_| canvas_error(format!("{} is not a canvas", canvas_id)))
+}
 
-    println!("canvas {}", canvas_element.id());
+// a scene built elsewhere, by the worker, is used as it is
+pub fn create_window(
+    canvas: HtmlCanvasElement,
+    loaded: LoadedBrick,
+    options: SceneOptions,
+    props: RenderProps,
+    progress: Progress,
+    picks: PickListeners,
+) -> Box<dyn FnOnce(&EventLoopWindowTarget<RenderingUserEvent<()>>) -> Option<RenderingWindow<()>>>
+{
+    wasm_logger::init(wasm_logger::Config::default());
 
     // callback should have properties as struct
     let callback = Box::new(
-        |event_loop: &EventLoopWindowTarget<RenderingUserEvent<()>>| {
+        move |event_loop: &EventLoopWindowTarget<RenderingUserEvent<()>>| {
+            let canvas_id = canvas.id();
             let window = Window::from_event_loop(
                 WindowSettings {
                     title: "Instanced Shapes!".to_string(),
                     max_size: Some((1280, 720)),
-                    canvas: Some(canvas_element),
+                    canvas: Some(canvas.clone()),
                     ..Default::default()
                 },
                 event_loop,
-            )
-            .unwrap();
+            );
 
-            render_brick(window, brick)
+            match window {
+                Ok(window) => Some(render_brick(
+                    window, canvas, loaded, options, props, progress, picks,
+                )),
+                Err(error) => {
+                    log::error!("could not create a window on {}: {:?}", canvas_id, error);
+                    progress.emit(LoadEvent::Error(LoadError::new(
+                        LoadErrorKind::Window,
+                        format!("could not create a window on {}: {:?}", canvas_id, error),
+                    )));
+                    None
+                }
+            }
         },
     );
     callback
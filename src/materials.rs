@@ -0,0 +1,66 @@
+use three_d::{Color, CpuMaterial};
+
+use crate::parser::colors::{ColorFinish, LDrawColor};
+
+// renderer independent description of an LDraw colour's surface, shared by the
+// renderer and the exporters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialParams {
+    pub albedo: [u8; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub sheen: f32,
+    pub emissive: [u8; 3],
+}
+
+impl MaterialParams {
+    pub fn from_color(color: &LDrawColor) -> Self {
+        let (metallic, roughness, sheen) = match color.finish {
+            ColorFinish::Plain => (0.0, 0.3, 0.0),
+            ColorFinish::Chrome => (1.0, 0.1, 0.0),
+            ColorFinish::Metal => (1.0, 0.35, 0.0),
+            ColorFinish::MatteMetallic => (0.8, 0.6, 0.0),
+            ColorFinish::Pearlescent => (0.3, 0.25, 0.6),
+            ColorFinish::Rubber => (0.0, 0.9, 0.0),
+            ColorFinish::Material(_) => (0.1, 0.4, 0.0),
+        };
+
+        let luminance = color.luminance as f32 / 255.0;
+        Self {
+            albedo: color.rgba(),
+            metallic,
+            roughness,
+            sheen,
+            emissive: [
+                (color.value[0] as f32 * luminance) as u8,
+                (color.value[1] as f32 * luminance) as u8,
+                (color.value[2] as f32 * luminance) as u8,
+            ],
+        }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.albedo[3] < 255
+    }
+
+    pub fn to_cpu_material(&self) -> CpuMaterial {
+        // three-d has no sheen, approximate it with a smoother, slightly metallic surface
+        CpuMaterial {
+            albedo: Color {
+                r: self.albedo[0],
+                g: self.albedo[1],
+                b: self.albedo[2],
+                a: self.albedo[3],
+            },
+            metallic: (self.metallic + 0.2 * self.sheen).min(1.0),
+            roughness: self.roughness * (1.0 - 0.5 * self.sheen),
+            emissive: Color {
+                r: self.emissive[0],
+                g: self.emissive[1],
+                b: self.emissive[2],
+                a: 255,
+            },
+            ..Default::default()
+        }
+    }
+}
@@ -1,2 +1,4 @@
+pub mod bundle;
+pub mod colors;
 pub mod part;
 pub mod tokenizer;
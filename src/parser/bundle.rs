@@ -0,0 +1,236 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read};
+
+use web_sys::AbortSignal;
+use zip::ZipArchive;
+
+use crate::{
+    errors::{LoadError, LoadErrorKind},
+    parser::{
+        colors::{ColorTable, LDrawColor},
+        part::{fetch_bytes, parse_file, split_lines, LDrawBrick, LDrawFile},
+    },
+    progress::{LoadEvent, Progress},
+};
+
+// the files of a `<id>.zip` from the server, the whole dependency closure of one part
+#[derive(Debug, Clone, Default)]
+pub struct Bundle {
+    files: HashMap<String, String>,
+}
+
+impl Bundle {
+    pub fn read(bytes: &[u8]) -> Result<Self, LoadError> {
+        let zip_error =
+            |error: zip::result::ZipError| LoadError::new(LoadErrorKind::Fetch, error.to_string());
+
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(zip_error)?;
+        let mut files = HashMap::new();
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index).map_err(zip_error)?;
+            if entry.is_dir() {
+                continue;
+            }
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content).map_err(|error| {
+                LoadError::new(LoadErrorKind::Fetch, error.to_string()).in_file(entry.name())
+            })?;
+            files.insert(
+                entry.name().replace('\\', "/"),
+                String::from_utf8_lossy(&content).into_owned(),
+            );
+        }
+
+        Ok(Self { files })
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // references are case insensitive, the library on the server is mostly lower case
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let name = name.replace('\\', "/");
+        self.files
+            .get(&name)
+            .or_else(|| self.files.get(&name.to_lowercase()))
+            .or_else(|| {
+                self.files
+                    .iter()
+                    .find(|(file, _)| file.eq_ignore_ascii_case(&name))
+                    .map(|(_, text)| text)
+            })
+            .map(|text| text.as_str())
+    }
+
+    // parses the entry file and everything it references, files are keyed by the name
+    // they are referenced with
+    pub fn resolve(
+        &self,
+        entry_file: &str,
+        colors: ColorTable,
+        progress: &Progress,
+    ) -> Result<LDrawBrick, LoadError> {
+        let mut resolver = Resolver::new(entry_file, colors);
+        while !resolver.is_done() {
+            resolver.step(self, progress)?;
+        }
+        Ok(resolver.finish())
+    }
+}
+
+// resolves a bundle one file at a time, so parsing can be spread over several frames
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    entry_file: String,
+    colors: ColorTable,
+    files: HashMap<String, LDrawFile>,
+    // depth first, so the parts of a model complete one after another
+    stack: Vec<String>,
+    // the names on the stack, each file is queued once however often it is referenced
+    queued: HashSet<String>,
+    // not in the bundle, warned about once
+    missing: HashSet<String>,
+    // parsed since the last call to take_fresh
+    fresh: Vec<String>,
+    // defined by the files parsed since the last call to take_fresh_colors
+    fresh_colors: Vec<LDrawColor>,
+}
+
+impl Resolver {
+    pub fn new(entry_file: &str, colors: ColorTable) -> Self {
+        Self {
+            entry_file: entry_file.to_string(),
+            colors,
+            files: HashMap::new(),
+            stack: vec![entry_file.to_string()],
+            queued: HashSet::from([entry_file.to_string()]),
+            missing: HashSet::new(),
+            fresh: Vec::new(),
+            fresh_colors: Vec::new(),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    // referenced by a parsed file but not parsed yet
+    pub fn pending(&self) -> HashSet<String> {
+        self.queued.clone()
+    }
+
+    // parses the next file, a missing subfile only leaves a hole like other viewers do
+    pub fn step(&mut self, bundle: &Bundle, progress: &Progress) -> Result<(), LoadError> {
+        let name = match self.stack.pop() {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        self.queued.remove(&name);
+        let text = match bundle.get(&name) {
+            Some(text) => text,
+            None => {
+                let error = LoadError::new(
+                    LoadErrorKind::MissingFile,
+                    format!("{} is not in the bundle", name),
+                )
+                .in_file(&name);
+                if name == self.entry_file {
+                    return Err(error);
+                }
+                progress.emit(LoadEvent::Warning(error));
+                self.missing.insert(name);
+                self.emit_resolved(progress);
+                return Ok(());
+            }
+        };
+
+        let mut defined = ColorTable::new();
+        let mut file = parse_file(&name, split_lines(text), &mut defined)?;
+        for color in defined.colors() {
+            self.colors.insert(color.clone());
+            self.fresh_colors.push(color.clone());
+        }
+        for warning in file.warnings.iter() {
+            progress.emit(LoadEvent::Warning(warning.clone()));
+        }
+        file.name = name.to_string();
+        self.files.insert(name.to_string(), file);
+        for subfile in self.files[&name].subfiles.iter().rev() {
+            let name = &subfile.filename;
+            if !self.files.contains_key(name)
+                && !self.missing.contains(name)
+                && self.queued.insert(name.to_string())
+            {
+                self.stack.push(name.to_string());
+            }
+        }
+        self.fresh.push(name);
+
+        self.emit_resolved(progress);
+        Ok(())
+    }
+
+    // every name counts once, missing files are as resolved as they will get
+    fn emit_resolved(&self, progress: &Progress) {
+        let resolved = self.files.len() + self.missing.len();
+        progress.emit(LoadEvent::FilesResolved {
+            resolved,
+            total: resolved + self.queued.len(),
+        });
+    }
+
+    pub fn colors(&self) -> &ColorTable {
+        &self.colors
+    }
+
+    // the files parsed since the last call
+    pub fn take_fresh(&mut self) -> Vec<LDrawFile> {
+        let files = &self.files;
+        self.fresh
+            .drain(..)
+            .filter_map(|name| files.get(&name).cloned())
+            .collect()
+    }
+
+    // the colour definitions found since the last call
+    pub fn take_fresh_colors(&mut self) -> Vec<LDrawColor> {
+        std::mem::take(&mut self.fresh_colors)
+    }
+
+    // what has been parsed so far
+    pub fn brick(&self) -> LDrawBrick {
+        LDrawBrick {
+            entry_file: self.entry_file.to_string(),
+            files: self.files.clone(),
+            colors: self.colors.clone(),
+        }
+    }
+
+    pub fn finish(self) -> LDrawBrick {
+        LDrawBrick {
+            entry_file: self.entry_file,
+            files: self.files,
+            colors: self.colors,
+        }
+    }
+}
+
+pub async fn fetch_bundle(
+    id: &str,
+    signal: Option<&AbortSignal>,
+    progress: &Progress,
+) -> Result<Bundle, LoadError> {
+    let file = format!("{}.zip", id);
+    let url = format!("http://localhost:3000/ldraw/bundle/{}", file);
+    let bytes = fetch_bytes(&url, &file, signal).await?;
+    progress.emit(LoadEvent::BytesFetched {
+        file: file.to_string(),
+        bytes: bytes.len(),
+    });
+    Bundle::read(&bytes).map_err(|error| error.in_file(&file))
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use web_sys::AbortSignal;
+
+use crate::{
+    errors::LoadError,
+    parser::{
+        part::{fetch_text, split_lines},
+        tokenizer::{tokenize_file, LDrawCommand},
+    },
+};
+
+pub const MAIN_COLOR: u32 = 16;
+pub const EDGE_COLOR: u32 = 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColorFinish {
+    Plain,
+    Chrome,
+    Pearlescent,
+    Rubber,
+    MatteMetallic,
+    Metal,
+    // GLITTER, SPECKLE, FABRIC, ... with their parameters dropped
+    Material(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LDrawColor {
+    pub name: String,
+    pub code: u32,
+    pub value: [u8; 3],
+    pub edge: [u8; 3],
+    pub alpha: u8,
+    pub luminance: u8,
+    pub finish: ColorFinish,
+}
+
+impl LDrawColor {
+    pub fn is_transparent(&self) -> bool {
+        self.alpha < 255
+    }
+
+    pub fn rgba(&self) -> [u8; 4] {
+        [self.value[0], self.value[1], self.value[2], self.alpha]
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ColorTable {
+    colors: HashMap<u32, LDrawColor>,
+}
+
+impl ColorTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, color: LDrawColor) {
+        self.colors.insert(color.code, color);
+    }
+
+    pub fn get(&self, code: u32) -> Option<&LDrawColor> {
+        self.colors.get(&code)
+    }
+
+    pub fn is_transparent(&self, code: u32) -> bool {
+        self.colors
+            .get(&code)
+            .map(|color| color.is_transparent())
+            .unwrap_or(false)
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    // sorted by code, so anything written from it comes out the same every time
+    pub fn colors(&self) -> Vec<&LDrawColor> {
+        let mut colors: Vec<&LDrawColor> = self.colors.values().collect();
+        colors.sort_by_key(|color| color.code);
+        colors
+    }
+
+    // always yields a colour, direct colours (0x2RRGGBB) included
+    pub fn resolve(&self, code: u32) -> LDrawColor {
+        if let Some(color) = self.colors.get(&code) {
+            return color.clone();
+        }
+
+        if code & 0xff00_0000 == 0x0200_0000 {
+            return LDrawColor {
+                name: format!("Direct_{:06X}", code & 0x00ff_ffff),
+                code,
+                value: [(code >> 16) as u8, (code >> 8) as u8, code as u8],
+                edge: [0, 0, 0],
+                alpha: 255,
+                luminance: 0,
+                finish: ColorFinish::Plain,
+            };
+        }
+
+        LDrawColor {
+            name: format!("Unknown_{}", code),
+            code,
+            value: [127, 127, 127],
+            edge: [51, 51, 51],
+            alpha: 255,
+            luminance: 0,
+            finish: ColorFinish::Plain,
+        }
+    }
+
+    // the colour code to use for a line inside a reference of the given colour
+    pub fn resolve_code(&self, code: u32, parent_code: u32) -> u32 {
+        match code {
+            MAIN_COLOR => parent_code,
+            EDGE_COLOR => {
+                let edge = self.resolve(parent_code).edge;
+                direct_color(edge)
+            }
+            _ => code,
+        }
+    }
+}
+
+pub const fn direct_color(rgb: [u8; 3]) -> u32 {
+    0x0200_0000 | (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32
+}
+
+fn tokenize_hex(token: &str) -> Option<[u8; 3]> {
+    let hex = token.strip_prefix('#')?;
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+// 0 !COLOUR name CODE x VALUE v EDGE e [ALPHA a] [LUMINANCE l] [ CHROME | PEARLESCENT | RUBBER | MATTE_METALLIC | METAL | MATERIAL params ]
+pub fn tokenize_colour(tokens: Vec<&str>) -> Option<LDrawColor> {
+    let mut color = LDrawColor {
+        name: tokens.first()?.to_string(),
+        code: 0,
+        value: [0, 0, 0],
+        edge: [0, 0, 0],
+        alpha: 255,
+        luminance: 0,
+        finish: ColorFinish::Plain,
+    };
+
+    let mut has_code = false;
+    let mut index = 1;
+    while index < tokens.len() {
+        match tokens[index] {
+            "CODE" => {
+                color.code = tokens.get(index + 1)?.parse().ok()?;
+                has_code = true;
+                index += 1;
+            }
+            "VALUE" => {
+                color.value = tokenize_hex(tokens.get(index + 1)?)?;
+                index += 1;
+            }
+            "EDGE" => {
+                // edges given as a colour code are rare, fall back to black for them
+                color.edge = tokenize_hex(tokens.get(index + 1)?).unwrap_or([0, 0, 0]);
+                index += 1;
+            }
+            "ALPHA" => {
+                color.alpha = tokens.get(index + 1)?.parse().ok()?;
+                index += 1;
+            }
+            "LUMINANCE" => {
+                color.luminance = tokens.get(index + 1)?.parse().ok()?;
+                index += 1;
+            }
+            "CHROME" => color.finish = ColorFinish::Chrome,
+            "PEARLESCENT" => color.finish = ColorFinish::Pearlescent,
+            "RUBBER" => color.finish = ColorFinish::Rubber,
+            "MATTE_METALLIC" => color.finish = ColorFinish::MatteMetallic,
+            "METAL" => color.finish = ColorFinish::Metal,
+            "MATERIAL" => {
+                color.finish = ColorFinish::Material(tokens.get(index + 1)?.to_string());
+                break;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+
+    if has_code {
+        Some(color)
+    } else {
+        None
+    }
+}
+
+pub async fn parse_ldconfig(signal: Option<&AbortSignal>) -> Result<ColorTable, LoadError> {
+    let url = "http://localhost:3000/ldraw/config/LDConfig.ldr";
+    let text = fetch_text(url, "LDConfig.ldr", signal).await?;
+
+    let mut table = ColorTable::new();
+    let tokens =
+        tokenize_file(split_lines(&text)).map_err(|error| error.in_file("LDConfig.ldr"))?;
+    for token in tokens {
+        if let Some(LDrawCommand::Colour(color)) = token {
+            table.insert(color);
+        }
+    }
+
+    Ok(table)
+}
@@ -1,10 +1,21 @@
 use std::collections::HashMap;
 
-use crate::parser::tokenizer::*;
+use crate::{
+    errors::{describe_js_error, LoadError, LoadErrorKind},
+    parser::{
+        bundle::fetch_bundle,
+        colors::{parse_ldconfig, ColorTable},
+        tokenizer::*,
+    },
+    progress::{LoadEvent, Progress},
+};
+use js_sys::{Promise, Uint8Array};
 use three_d::{Matrix3, Vector3};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{Request, RequestInit, RequestMode, Response};
+use web_sys::{
+    AbortSignal, Request, RequestInit, RequestMode, Response, Window, WorkerGlobalScope,
+};
 
 #[derive(Debug, Clone)]
 pub struct LDrawAuthor {
@@ -43,6 +54,10 @@ pub struct LDrawSubfile {
     pub transformation: Matrix3<f32>,
     pub translation: Vector3<f32>,
     pub filename: String,
+    // one based line of the reference in its file
+    pub line: usize,
+    // INVERTNEXT or a mirroring matrix, either flips the winding of the subfile
+    pub inverted: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -50,159 +65,241 @@ pub struct LDrawFile {
     pub name: String,
     pub title: String,
     pub author: LDrawAuthor,
+    pub ldraw_type: Option<LDrawType>,
     pub bfc_direction: BFCDirection,
     pub lines: Vec<LDrawContour>,
     pub optional_lines: Vec<LDrawOptionalContour>,
     pub triangles: Vec<LDrawTriangle>,
     pub subfiles: Vec<LDrawSubfile>,
+    // lines that were skipped while parsing
+    pub warnings: Vec<LoadError>,
+}
+
+impl LDrawFile {
+    // anything with an !LDRAW_ORG header is a part. Without one, a file that only draws is
+    // a part too, and a file that references others is a model, whatever its extension
+    pub fn is_part(&self) -> bool {
+        self.ldraw_type.is_some() || self.subfiles.is_empty()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct LDrawBrick {
     pub entry_file: String,
     pub files: HashMap<String, LDrawFile>,
+    pub colors: ColorTable,
 }
 
-pub async fn parse_part(id: &str) -> Result<LDrawBrick, JsValue> {
-    let files = get_bundle_lst(&format!("{}", id)).await?;
-    let mut file_map = HashMap::new();
+pub async fn parse_part(
+    id: &str,
+    signal: Option<&AbortSignal>,
+    progress: &Progress,
+) -> Result<LDrawBrick, LoadError> {
+    progress.emit(LoadEvent::FilesResolved {
+        resolved: 0,
+        total: 1,
+    });
+    let bundle = fetch_bundle(id, signal, progress).await?;
+    let colors = parse_ldconfig(signal).await?;
+    bundle.resolve(&format!("{}.dat", id), colors, progress)
+}
 
-    for file in files {
-        let lines = get_subfile(&file).await?;
-        let tokens = tokenize_file(lines).await?;
+// colour definitions found in the file are added to the colour table
+pub fn parse_file(
+    name: &str,
+    lines: Vec<String>,
+    colors: &mut ColorTable,
+) -> Result<LDrawFile, LoadError> {
+    let tokens = tokenize_file(lines).map_err(|error| error.in_file(name))?;
 
-        let mut file = LDrawFile {
+    let mut file = LDrawFile {
+        name: name.to_string(),
+        title: String::new(),
+        author: LDrawAuthor {
             name: String::new(),
-            title: String::new(),
-            author: LDrawAuthor {
-                name: String::new(),
-                username: None,
-            },
-            bfc_direction: BFCDirection::CW,
-            lines: Vec::new(),
-            optional_lines: Vec::new(),
-            triangles: Vec::new(),
-            subfiles: Vec::new(),
-        };
-
-        for token in &tokens {
-            match token {
-                Some(LDrawCommand::Name(name)) => file.name = name.to_string(),
-                Some(LDrawCommand::Title(title)) => file.title = title.to_string(),
-                Some(LDrawCommand::Author(name, username)) => {
-                    file.author.name = name.to_string();
-                    file.author.username = username.clone()
-                }
-                Some(LDrawCommand::BFCCertification(direction)) => {
-                    if direction.is_some() {
-                        file.bfc_direction = direction.clone().unwrap();
-                    }
+            username: None,
+        },
+        ldraw_type: None,
+        bfc_direction: BFCDirection::CW,
+        lines: Vec::new(),
+        optional_lines: Vec::new(),
+        triangles: Vec::new(),
+        subfiles: Vec::new(),
+        warnings: Vec::new(),
+    };
+
+    for token in &tokens {
+        match token {
+            Some(LDrawCommand::Name(name)) => file.name = name.to_string(),
+            Some(LDrawCommand::Title(title)) => file.title = title.to_string(),
+            Some(LDrawCommand::Author(name, username)) => {
+                file.author.name = name.to_string();
+                file.author.username = username.clone()
+            }
+            Some(LDrawCommand::Colour(color)) => colors.insert(color.clone()),
+            Some(LDrawCommand::Warning(line, message)) => file
+                .warnings
+                .push(LoadError::parse(*line, message.to_string()).in_file(name)),
+            Some(LDrawCommand::LDrawOrg(ldraw_type)) => file.ldraw_type = Some(ldraw_type.clone()),
+            Some(LDrawCommand::BFCCertification(direction)) => {
+                if direction.is_some() {
+                    file.bfc_direction = direction.clone().unwrap();
                 }
-                Some(LDrawCommand::Contour(color, x, y)) => file.lines.push(LDrawContour {
+            }
+            Some(LDrawCommand::Contour(color, x, y)) => file.lines.push(LDrawContour {
+                color: color.clone(),
+                x: x.clone(),
+                y: y.clone(),
+            }),
+            Some(LDrawCommand::OptionalContour(color, x, y, ox, oy)) => {
+                file.optional_lines.push(LDrawOptionalContour {
                     color: color.clone(),
                     x: x.clone(),
                     y: y.clone(),
-                }),
-                Some(LDrawCommand::OptionalContour(color, x, y, ox, oy)) => {
-                    file.optional_lines.push(LDrawOptionalContour {
-                        color: color.clone(),
-                        x: x.clone(),
-                        y: y.clone(),
-                        ox: ox.clone(),
-                        oy: oy.clone(),
-                    })
-                }
-                Some(LDrawCommand::Triangle(color, x, y, z)) => {
-                    file.triangles.push(LDrawTriangle {
-                        color: color.clone(),
-                        x: x.clone(),
-                        y: y.clone(),
-                        z: z.clone(),
-                    })
-                }
-                Some(LDrawCommand::Quadrilateral(color, x, y, z, w)) => {
-                    file.triangles.push(LDrawTriangle {
-                        color: color.clone(),
-                        x: x.clone(),
-                        y: y.clone(),
-                        z: z.clone(),
-                    });
-                    file.triangles.push(LDrawTriangle {
-                        color: color.clone(),
-                        x: z.clone(),
-                        y: w.clone(),
-                        z: x.clone(),
-                    })
-                }
-                Some(LDrawCommand::SubfileReference(
-                    color,
-                    translation,
-                    transformation,
-                    filename,
-                    invert_winding,
-                )) => {
-                    let bfc_direction = file.bfc_direction.clone();
-                    file.subfiles.push(LDrawSubfile {
-                        color: color.clone(),
-                        bfc_direction: if invert_winding.clone()
-                            && bfc_direction == BFCDirection::CW
-                        {
-                            BFCDirection::CCW
-                        } else if invert_winding.clone() && bfc_direction == BFCDirection::CCW {
-                            BFCDirection::CCW
-                        } else {
-                            bfc_direction
-                        },
-                        translation: translation.clone(),
-                        transformation: transformation.clone(),
-                        filename: filename.to_string(),
-                    })
-                }
-                _ => {}
+                    ox: ox.clone(),
+                    oy: oy.clone(),
+                })
+            }
+            Some(LDrawCommand::Triangle(color, x, y, z)) => file.triangles.push(LDrawTriangle {
+                color: color.clone(),
+                x: x.clone(),
+                y: y.clone(),
+                z: z.clone(),
+            }),
+            Some(LDrawCommand::Quadrilateral(color, x, y, z, w)) => {
+                file.triangles.push(LDrawTriangle {
+                    color: color.clone(),
+                    x: x.clone(),
+                    y: y.clone(),
+                    z: z.clone(),
+                });
+                file.triangles.push(LDrawTriangle {
+                    color: color.clone(),
+                    x: z.clone(),
+                    y: w.clone(),
+                    z: x.clone(),
+                })
             }
+            Some(LDrawCommand::SubfileReference(
+                color,
+                translation,
+                transformation,
+                filename,
+                invert_winding,
+                line,
+            )) => {
+                let bfc_direction = file.bfc_direction.clone();
+                file.subfiles.push(LDrawSubfile {
+                    color: color.clone(),
+                    bfc_direction: if *invert_winding {
+                        bfc_direction.flipped()
+                    } else {
+                        bfc_direction
+                    },
+                    translation: translation.clone(),
+                    transformation: transformation.clone(),
+                    filename: filename.to_string(),
+                    line: *line,
+                    inverted: *invert_winding,
+                })
+            }
+            _ => {}
         }
-
-        file_map.insert(file.name.to_string(), file.clone());
     }
 
-    Ok(LDrawBrick {
-        entry_file: format!("{}.dat", id),
-        files: file_map,
-    })
+    Ok(file)
 }
 
-async fn get_bundle_lst(id: &str) -> Result<Vec<String>, JsValue> {
-    let mut opts = RequestInit::new();
-    opts.method("GET");
-    opts.mode(RequestMode::Cors);
-
-    let url = format!("http://localhost:3000/ldraw/bundle/{}.lst", id);
-    let request = Request::new_with_str_and_init(&url, &opts)?;
+pub fn split_lines(text: &str) -> Vec<String> {
+    text.lines().map(|line| line.to_string()).collect()
+}
 
-    let window = web_sys::window().unwrap();
-    let response_value = JsFuture::from(window.fetch_with_request(&request)).await?;
-    let response: Response = response_value.dyn_into().unwrap();
+pub(crate) async fn fetch_text(
+    url: &str,
+    file: &str,
+    signal: Option<&AbortSignal>,
+) -> Result<String, LoadError> {
+    let response = fetch(url, file, signal).await?;
+    let body = response.text().map_err(|value| fetch_error(file, &value))?;
+    let text = read_body(body, file, signal).await?;
+    Ok(text.as_string().unwrap_or_default())
+}
 
-    let text = JsFuture::from(response.text()?).await?.as_string().unwrap();
-    let files = text.lines().map(|line| line.to_string()).collect();
+pub(crate) async fn fetch_bytes(
+    url: &str,
+    file: &str,
+    signal: Option<&AbortSignal>,
+) -> Result<Vec<u8>, LoadError> {
+    let response = fetch(url, file, signal).await?;
+    let body = response
+        .array_buffer()
+        .map_err(|value| fetch_error(file, &value))?;
+    let buffer = read_body(body, file, signal).await?;
+    Ok(Uint8Array::new(&buffer).to_vec())
+}
 
-    Ok(files)
+fn fetch_error(file: &str, value: &JsValue) -> LoadError {
+    LoadError::new(LoadErrorKind::Fetch, describe_js_error(value)).in_file(file)
 }
 
-async fn get_subfile(name: &str) -> Result<Vec<String>, JsValue> {
+async fn fetch(url: &str, file: &str, signal: Option<&AbortSignal>) -> Result<Response, LoadError> {
+    let cancelled = || signal.map(|signal| signal.aborted()).unwrap_or(false);
+
     let mut opts = RequestInit::new();
     opts.method("GET");
     opts.mode(RequestMode::Cors);
+    opts.signal(signal);
 
-    let url = format!("http://localhost:3000/ldraw/data/parts/{}", name);
-    let request = Request::new_with_str_and_init(&url, &opts)?;
+    let request =
+        Request::new_with_str_and_init(url, &opts).map_err(|value| fetch_error(file, &value))?;
 
-    let window = web_sys::window().unwrap();
-    let response_value = JsFuture::from(window.fetch_with_request(&request)).await?;
-    let response: Response = response_value.dyn_into().unwrap();
+    // parts are also loaded from the worker, which has no window
+    let global = js_sys::global();
+    let fetched = if let Some(window) = global.dyn_ref::<Window>() {
+        window.fetch_with_request(&request)
+    } else if let Some(scope) = global.dyn_ref::<WorkerGlobalScope>() {
+        scope.fetch_with_request(&request)
+    } else {
+        return Err(LoadError::new(
+            LoadErrorKind::Fetch,
+            "no window or worker to fetch with",
+        ));
+    };
+    let response_value = match JsFuture::from(fetched).await {
+        Ok(value) => value,
+        Err(_) if cancelled() => return Err(LoadError::cancelled().in_file(file)),
+        Err(value) => return Err(fetch_error(file, &value)),
+    };
+    let response: Response = response_value
+        .dyn_into()
+        .map_err(|value| fetch_error(file, &value))?;
 
-    let text = JsFuture::from(response.text()?).await?.as_string().unwrap();
-    let lines = text.lines().map(|line| line.to_string()).collect();
+    if response.status() == 404 {
+        return Err(
+            LoadError::new(LoadErrorKind::MissingFile, format!("{} was not found", url))
+                .in_file(file),
+        );
+    }
+    if !response.ok() {
+        return Err(LoadError::new(
+            LoadErrorKind::Fetch,
+            format!("{} answered with {}", url, response.status()),
+        )
+        .in_file(file));
+    }
+    Ok(response)
+}
 
-    Ok(lines)
+async fn read_body(
+    body: Promise,
+    file: &str,
+    signal: Option<&AbortSignal>,
+) -> Result<JsValue, LoadError> {
+    match JsFuture::from(body).await {
+        Ok(value) => Ok(value),
+        Err(_) if signal.map(|signal| signal.aborted()).unwrap_or(false) => {
+            Err(LoadError::cancelled().in_file(file))
+        }
+        Err(value) => Err(fetch_error(file, &value)),
+    }
 }
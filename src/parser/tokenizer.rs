@@ -1,7 +1,11 @@
 use chrono::NaiveDate;
 use std::str::FromStr;
 use three_d::{vec3, Matrix3, SquareMatrix, Vector3};
-use wasm_bindgen::prelude::*;
+
+use crate::{
+    errors::LoadError,
+    parser::colors::{tokenize_colour, LDrawColor},
+};
 
 #[derive(Debug, Clone)]
 pub struct Color {
@@ -22,7 +26,11 @@ pub enum LDrawCommand {
     Keywords(Vec<String>),
     History(NaiveDate, Option<String>, String),
     BFCCertification(Option<BFCDirection>),
-    SubfileReference(Color, Vector3<f32>, Matrix3<f32>, String, bool),
+    Colour(LDrawColor),
+    // a known meta command that could not be read, with its one based line number
+    Warning(usize, String),
+    // the last field is the one based line of the reference
+    SubfileReference(Color, Vector3<f32>, Matrix3<f32>, String, bool, usize),
     Contour(Color, Vector3<f32>, Vector3<f32>),
     Triangle(Color, Vector3<f32>, Vector3<f32>, Vector3<f32>),
     Quadrilateral(
@@ -41,12 +49,21 @@ pub enum LDrawCommand {
     ),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum BFCDirection {
     CW,
     CCW,
 }
 
+impl BFCDirection {
+    pub fn flipped(&self) -> Self {
+        match self {
+            BFCDirection::CW => BFCDirection::CCW,
+            BFCDirection::CCW => BFCDirection::CW,
+        }
+    }
+}
+
 impl FromStr for BFCDirection {
     type Err = ();
     fn from_str(input: &str) -> Result<BFCDirection, Self::Err> {
@@ -95,12 +112,13 @@ impl FromStr for LDrawType {
     }
 }
 
-pub async fn tokenize_file(lines: Vec<String>) -> Result<Vec<Option<LDrawCommand>>, JsValue> {
+pub fn tokenize_file(lines: Vec<String>) -> Result<Vec<Option<LDrawCommand>>, LoadError> {
     let mut parsed_lines: Vec<Option<LDrawCommand>> = Vec::new();
     let mut last_line: Option<LDrawCommand> = None;
 
     for (i, line) in lines.iter().enumerate() {
-        let parsed_line = tokenize_line(line.to_string(), i, last_line);
+        let parsed_line = tokenize_line(line.to_string(), i, last_line)
+            .map_err(|message| LoadError::parse(i + 1, message))?;
         last_line = parsed_line.clone();
         if parsed_line.is_some() {
             parsed_lines.push(parsed_line)
@@ -114,119 +132,161 @@ fn tokenize_line(
     line: String,
     index: usize,
     lastline: Option<LDrawCommand>,
-) -> Option<LDrawCommand> {
+) -> Result<Option<LDrawCommand>, String> {
     if line.trim().len() <= 1 {
-        return None;
+        return Ok(None);
     }
 
     let invert_next_line = matches!(lastline, Some(LDrawCommand::BFCInvertNext));
 
     let tokens: Vec<&str> = line.split_whitespace().collect();
     let tail = tokens.split_first().unwrap().1;
-    match tokens[0] {
-        "0" => Some(tokenize_meta(tail.to_vec(), index)),
-        "1" => Some(tokenize_subfile_reference(tail.to_vec(), invert_next_line)),
-        "2" => Some(tokenize_contour(tail.to_vec())),
-        "3" => Some(tokenize_triangle(tail.to_vec(), invert_next_line)),
-        "4" => Some(tokenize_quadrilateral(tail.to_vec(), invert_next_line)),
-        "5" => Some(tokenize_optional_contour(tail.to_vec())),
-        _ => None,
+    let command = match tokens[0] {
+        "0" => tokenize_meta(tail.to_vec(), index)
+            .unwrap_or_else(|message| LDrawCommand::Warning(index + 1, message)),
+        "1" => tokenize_subfile_reference(expect_tokens(tail, 14)?, invert_next_line, index + 1)?,
+        "2" => tokenize_contour(expect_tokens(tail, 7)?)?,
+        "3" => tokenize_triangle(expect_tokens(tail, 10)?, invert_next_line)?,
+        "4" => tokenize_quadrilateral(expect_tokens(tail, 13)?, invert_next_line)?,
+        "5" => tokenize_optional_contour(expect_tokens(tail, 13)?)?,
+        _ => return Ok(None),
+    };
+    Ok(Some(command))
+}
+
+fn expect_tokens<'a>(tokens: &[&'a str], count: usize) -> Result<Vec<&'a str>, String> {
+    if tokens.len() < count {
+        Err(format!(
+            "expected {} values but found {}",
+            count,
+            tokens.len()
+        ))
+    } else {
+        Ok(tokens.to_vec())
     }
 }
 
-fn tokenize_optional_contour(tokens: Vec<&str>) -> LDrawCommand {
-    let color = tokenize_color(tokens[0]);
-    let x = tokenize_vec3(tokens[1..4].to_vec());
-    let y = tokenize_vec3(tokens[4..7].to_vec());
-    let z = tokenize_vec3(tokens[7..10].to_vec());
-    let w = tokenize_vec3(tokens[10..13].to_vec());
+fn tokenize_optional_contour(tokens: Vec<&str>) -> Result<LDrawCommand, String> {
+    let color = tokenize_color(tokens[0])?;
+    let x = tokenize_vec3(tokens[1..4].to_vec())?;
+    let y = tokenize_vec3(tokens[4..7].to_vec())?;
+    let z = tokenize_vec3(tokens[7..10].to_vec())?;
+    let w = tokenize_vec3(tokens[10..13].to_vec())?;
 
-    LDrawCommand::OptionalContour(color, x, y, z, w)
+    Ok(LDrawCommand::OptionalContour(color, x, y, z, w))
 }
 
-fn tokenize_quadrilateral(tokens: Vec<&str>, invert_next_line: bool) -> LDrawCommand {
+fn tokenize_quadrilateral(
+    tokens: Vec<&str>,
+    invert_next_line: bool,
+) -> Result<LDrawCommand, String> {
     // invert if previous line says so
-    let color = tokenize_color(tokens[0]);
-    let x = tokenize_vec3(tokens[1..4].to_vec());
-    let y = tokenize_vec3(tokens[4..7].to_vec());
-    let z = tokenize_vec3(tokens[7..10].to_vec());
-    let w = tokenize_vec3(tokens[10..13].to_vec());
+    let color = tokenize_color(tokens[0])?;
+    let x = tokenize_vec3(tokens[1..4].to_vec())?;
+    let y = tokenize_vec3(tokens[4..7].to_vec())?;
+    let z = tokenize_vec3(tokens[7..10].to_vec())?;
+    let w = tokenize_vec3(tokens[10..13].to_vec())?;
 
     if invert_next_line {
-        LDrawCommand::Quadrilateral(color, x, y, z, w)
+        Ok(LDrawCommand::Quadrilateral(color, x, y, z, w))
     } else {
-        LDrawCommand::Quadrilateral(color, x, w, z, y)
+        Ok(LDrawCommand::Quadrilateral(color, x, w, z, y))
     }
 }
 
-fn tokenize_triangle(tokens: Vec<&str>, invert_next_line: bool) -> LDrawCommand {
+fn tokenize_triangle(tokens: Vec<&str>, invert_next_line: bool) -> Result<LDrawCommand, String> {
     // invert if previous line says so
-    let color = tokenize_color(tokens[0]);
-    let x = tokenize_vec3(tokens[1..4].to_vec());
-    let y = tokenize_vec3(tokens[4..7].to_vec());
-    let z = tokenize_vec3(tokens[7..10].to_vec());
+    let color = tokenize_color(tokens[0])?;
+    let x = tokenize_vec3(tokens[1..4].to_vec())?;
+    let y = tokenize_vec3(tokens[4..7].to_vec())?;
+    let z = tokenize_vec3(tokens[7..10].to_vec())?;
 
     if invert_next_line {
-        LDrawCommand::Triangle(color, x, z, y)
+        Ok(LDrawCommand::Triangle(color, x, z, y))
     } else {
-        LDrawCommand::Triangle(color, x, y, z)
+        Ok(LDrawCommand::Triangle(color, x, y, z))
     }
 }
 
-fn tokenize_contour(tokens: Vec<&str>) -> LDrawCommand {
-    let color = tokenize_color(tokens[0]);
-    let x = tokenize_vec3(tokens[1..4].to_vec());
-    let y = tokenize_vec3(tokens[4..7].to_vec());
+fn tokenize_contour(tokens: Vec<&str>) -> Result<LDrawCommand, String> {
+    let color = tokenize_color(tokens[0])?;
+    let x = tokenize_vec3(tokens[1..4].to_vec())?;
+    let y = tokenize_vec3(tokens[4..7].to_vec())?;
 
-    LDrawCommand::Contour(color, x, y)
+    Ok(LDrawCommand::Contour(color, x, y))
 }
 
-fn tokenize_subfile_reference(tokens: Vec<&str>, invert_next_line: bool) -> LDrawCommand {
+fn tokenize_subfile_reference(
+    tokens: Vec<&str>,
+    invert_next_line: bool,
+    line: usize,
+) -> Result<LDrawCommand, String> {
     // invert if previous line says so or if matrix determinant is negative
 
-    let color = tokenize_color(tokens[0]);
-    let file = sanitize_file_name(tokens[13]);
+    let color = tokenize_color(tokens[0])?;
+    // file names may contain spaces
+    let file = sanitize_file_name(&tokens[13..].join(" "));
 
-    let translation = tokenize_vec3(tokens[1..4].to_vec());
-    let transformation = tokenize_mat3(tokens[4..13].to_vec());
+    let translation = tokenize_vec3(tokens[1..4].to_vec())?;
+    let transformation = tokenize_mat3(tokens[4..13].to_vec())?;
 
-    LDrawCommand::SubfileReference(
+    Ok(LDrawCommand::SubfileReference(
         color,
         translation,
         transformation,
         file,
         invert_next_line ^ (transformation.determinant() < 0.0),
-    )
+        line,
+    ))
 }
 
-fn tokenize_vec3(tokens: Vec<&str>) -> Vector3<f32> {
-    let tokens: Vec<f32> = tokens.iter().map(|token| token.parse().unwrap()).collect();
-    vec3(tokens[0], tokens[1], tokens[2])
+fn tokenize_f32s(tokens: Vec<&str>) -> Result<Vec<f32>, String> {
+    tokens
+        .iter()
+        .map(|token| {
+            token
+                .parse()
+                .map_err(|_| format!("'{}' is not a number", token))
+        })
+        .collect()
 }
 
-fn tokenize_mat3(tokens: Vec<&str>) -> Matrix3<f32> {
-    let tokens: Vec<f32> = tokens.iter().map(|token| token.parse().unwrap()).collect();
-    Matrix3::new(
+fn tokenize_vec3(tokens: Vec<&str>) -> Result<Vector3<f32>, String> {
+    let tokens = tokenize_f32s(tokens)?;
+    Ok(vec3(tokens[0], tokens[1], tokens[2]))
+}
+
+fn tokenize_mat3(tokens: Vec<&str>) -> Result<Matrix3<f32>, String> {
+    let tokens = tokenize_f32s(tokens)?;
+    Ok(Matrix3::new(
         tokens[0], tokens[1], tokens[2], tokens[3], tokens[4], tokens[5], tokens[6], tokens[7],
         tokens[8],
-    )
+    ))
 }
 
-fn tokenize_color(token: &str) -> Color {
-    Color {
-        value: token.parse().unwrap(),
-    }
+fn tokenize_color(token: &str) -> Result<Color, String> {
+    // direct colours are also written as hex, 0x2RRGGBB
+    let value = match token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => token.parse().ok(),
+    };
+    value
+        .map(|value| Color { value })
+        .ok_or_else(|| format!("'{}' is not a colour code", token))
 }
 
 fn sanitize_file_name(token: &str) -> String {
     token.replace("\\", "/")
 }
 
-fn tokenize_license(tokens: Vec<&str>) -> LDrawCommand {
-    LDrawCommand::License(
-        tokens[0..tokens.len() - 3].join(" "),
-        tokens.last().unwrap().to_string(),
-    )
+fn tokenize_license(tokens: Vec<&str>) -> Option<LDrawCommand> {
+    Some(LDrawCommand::License(
+        tokens[0..tokens.len().checked_sub(3)?].join(" "),
+        tokens.last()?.to_string(),
+    ))
 }
 
 fn tokenize_user_name(token: &str) -> Option<String> {
@@ -237,54 +297,69 @@ fn tokenize_user_name(token: &str) -> Option<String> {
     }
 }
 
-fn tokenize_author(tokens: Vec<&str>) -> LDrawCommand {
-    let user_name_option = tokens.split_last().unwrap().0;
+fn tokenize_author(tokens: Vec<&str>) -> Option<LDrawCommand> {
+    let (user_name_option, real_name) = tokens.split_last()?;
     let user_name = tokenize_user_name(user_name_option);
 
     let real_name = if user_name.is_none() {
         tokens.join(" ")
     } else {
-        tokens.split_last().unwrap().1.join(" ")
+        real_name.join(" ")
     };
-    LDrawCommand::Author(real_name, user_name)
+    Some(LDrawCommand::Author(real_name, user_name))
 }
 
-fn tokenize_history(tokens: Vec<&str>) -> LDrawCommand {
-    let date = NaiveDate::parse_from_str(tokens[0], "%Y-%m-%d");
-    let user_name = tokenize_user_name(tokens[1]);
+fn tokenize_history(tokens: Vec<&str>) -> Option<LDrawCommand> {
+    let date = NaiveDate::parse_from_str(tokens.first()?, "%Y-%m-%d").ok()?;
+    let user_name = tokenize_user_name(tokens.get(1)?);
     let text = tokens.split_at(2).1.join(" ");
-    LDrawCommand::History(date.unwrap(), user_name, text)
+    Some(LDrawCommand::History(date, user_name, text))
 }
 
 // 0 BFC ( CERTIFY ( CCW | CW ) | NOCERTIFY )
-fn tokenize_bfc_certification(tokens: Vec<&str>) -> LDrawCommand {
-    match tokens[0] {
-        "CERTIFY" => LDrawCommand::BFCCertification(BFCDirection::from_str(tokens[1]).ok()),
-        "NOCERTIFY" => LDrawCommand::BFCCertification(None),
-        "INVERTNEXT" => LDrawCommand::BFCInvertNext,
-        _ => LDrawCommand::Comment,
+fn tokenize_bfc_certification(tokens: Vec<&str>) -> Option<LDrawCommand> {
+    match *tokens.first()? {
+        "CERTIFY" => {
+            Some(LDrawCommand::BFCCertification(tokens.get(1).and_then(
+                |direction| BFCDirection::from_str(direction).ok(),
+            )))
+        }
+        "NOCERTIFY" => Some(LDrawCommand::BFCCertification(None)),
+        "INVERTNEXT" => Some(LDrawCommand::BFCInvertNext),
+        _ => None,
     }
 }
 
-fn tokenize_meta(tokens: Vec<&str>, line_index: usize) -> LDrawCommand {
+// malformed meta commands are reported instead of failing the file
+fn tokenize_meta(tokens: Vec<&str>, line_index: usize) -> Result<LDrawCommand, String> {
     if line_index == 0 {
-        LDrawCommand::Title(tokens.join(" "))
-    } else {
-        let tail = tokens.split_first().unwrap().1;
-        match tokens[0] {
-            "Name:" => LDrawCommand::Name(sanitize_file_name(&tail.join(" "))),
-            "Author:" => tokenize_author(tail.to_vec()),
-            "!LICENSE" => tokenize_license(tail.to_vec()),
-            "!LDRAW_ORG" => LDrawCommand::LDrawOrg(LDrawType::from_str(tail[0]).unwrap()),
-            "!CATEGORY" => LDrawCommand::Category(tail[0].to_string()),
-            "!KEYWORDS" => LDrawCommand::Keywords(
-                tail.iter()
-                    .map(|s| s.to_string().replace(",", ""))
-                    .collect(),
-            ),
-            "!HISTORY" => tokenize_history(tail.to_vec()),
-            "BFC" => tokenize_bfc_certification(tail.to_vec()),
-            _ => LDrawCommand::Comment,
-        }
+        return Ok(LDrawCommand::Title(tokens.join(" ")));
     }
+
+    let (command, tail) = match tokens.split_first() {
+        Some(split) => split,
+        None => return Ok(LDrawCommand::Comment),
+    };
+    let parsed = match *command {
+        "Name:" => Some(LDrawCommand::Name(sanitize_file_name(&tail.join(" ")))),
+        "Author:" => tokenize_author(tail.to_vec()),
+        "!LICENSE" => tokenize_license(tail.to_vec()),
+        "!LDRAW_ORG" => tail
+            .first()
+            .and_then(|ldraw_type| LDrawType::from_str(ldraw_type).ok())
+            .map(LDrawCommand::LDrawOrg),
+        "!CATEGORY" => tail
+            .first()
+            .map(|category| LDrawCommand::Category(category.to_string())),
+        "!KEYWORDS" => Some(LDrawCommand::Keywords(
+            tail.iter()
+                .map(|s| s.to_string().replace(",", ""))
+                .collect(),
+        )),
+        "!HISTORY" => tokenize_history(tail.to_vec()),
+        "BFC" => tokenize_bfc_certification(tail.to_vec()),
+        "!COLOUR" => tokenize_colour(tail.to_vec()).map(LDrawCommand::Colour),
+        _ => return Ok(LDrawCommand::Comment),
+    };
+    parsed.ok_or_else(|| format!("malformed {} command", command))
 }
@@ -0,0 +1,437 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ops::Mul;
+use std::rc::Rc;
+
+use js_sys::Function;
+use three_d::{InnerSpace, Matrix4, SquareMatrix, Vector3};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    bounds::Aabb,
+    coordinates::OutputFrame,
+    parser::{
+        colors::MAIN_COLOR,
+        part::{LDrawBrick, LDrawFile},
+    },
+    scene::{part_instances, references, PartInstance},
+};
+
+const BVH_LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    // not necessarily normalized, distances are measured in multiples of it
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Vector3<f32>, direction: Vector3<f32>) -> Self {
+        Self { origin, direction }
+    }
+
+    pub fn at(&self, distance: f32) -> Vector3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Ray {
+        Ray {
+            origin: matrix.mul(self.origin.extend(1.0)).truncate(),
+            direction: matrix.mul(self.direction.extend(0.0)).truncate(),
+        }
+    }
+
+    // Möller-Trumbore, triangles are hit from both sides
+    pub fn intersect_triangle(&self, triangle: &[Vector3<f32>; 3]) -> Option<f32> {
+        let edge1 = triangle[1] - triangle[0];
+        let edge2 = triangle[2] - triangle[0];
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let inverse = 1.0 / determinant;
+        let t = self.origin - triangle[0];
+        let u = t.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = t.cross(edge1);
+        let v = self.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge2.dot(q) * inverse;
+        if distance > 0.0 {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    // slab test, the distance where the ray enters the box or 0 when it starts inside
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = f32::INFINITY;
+        for axis in 0..3 {
+            let inverse = 1.0 / self.direction[axis];
+            let mut t0 = (aabb.min[axis] - self.origin[axis]) * inverse;
+            let mut t1 = (aabb.max[axis] - self.origin[axis]) * inverse;
+            if inverse < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN from 0 * inf keeps the previous bounds
+            near = if t0 > near { t0 } else { near };
+            far = if t1 < far { t1 } else { far };
+            if far < near {
+                return None;
+            }
+        }
+        Some(near)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BvhNode {
+    aabb: Aabb,
+    // range into the triangles of a leaf
+    first: usize,
+    count: usize,
+    children: Option<(usize, usize)>,
+}
+
+// bounding volume hierarchy over the triangles of one part in its own space
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<[Vector3<f32>; 3]>,
+}
+
+impl Bvh {
+    pub fn new(triangles: Vec<[Vector3<f32>; 3]>) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            triangles,
+        };
+        if !bvh.triangles.is_empty() {
+            bvh.build(0, bvh.triangles.len());
+        }
+        bvh
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        self.nodes
+            .first()
+            .map(|node| node.aabb)
+            .unwrap_or_else(Aabb::empty)
+    }
+
+    fn build(&mut self, first: usize, count: usize) -> usize {
+        let triangles = &mut self.triangles[first..first + count];
+        let aabb = Aabb::from_points(triangles.iter().flat_map(|triangle| triangle.iter()));
+
+        let index = self.nodes.len();
+        self.nodes.push(BvhNode {
+            aabb,
+            first,
+            count,
+            children: None,
+        });
+        if count <= BVH_LEAF_SIZE {
+            return index;
+        }
+
+        // median split along the longest axis of the centroids
+        let centroid =
+            |triangle: &[Vector3<f32>; 3]| (triangle[0] + triangle[1] + triangle[2]) / 3.0;
+        let centroids =
+            Aabb::from_points(triangles.iter().map(centroid).collect::<Vec<_>>().iter());
+        let size = centroids.size();
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+        triangles.sort_by(|a, b| {
+            centroid(a)[axis]
+                .partial_cmp(&centroid(b)[axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let half = count / 2;
+        let left = self.build(first, half);
+        let right = self.build(first + half, count - half);
+        self.nodes[index].children = Some((left, right));
+        index
+    }
+
+    // the nearest hit along the ray
+    pub fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let mut nearest: Option<f32> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            match ray.intersect_aabb(&node.aabb) {
+                Some(entry) if nearest.map(|nearest| entry < nearest).unwrap_or(true) => {}
+                _ => continue,
+            }
+
+            match node.children {
+                Some((left, right)) => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+                None => {
+                    for triangle in self.triangles[node.first..node.first + node.count].iter() {
+                        if let Some(distance) = ray.intersect_triangle(triangle) {
+                            if nearest.map(|nearest| distance < nearest).unwrap_or(true) {
+                                nearest = Some(distance);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        nearest
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PickHit {
+    // index into the part instances of the brick
+    pub instance: usize,
+    pub part: PartInstance,
+    // in the output frame
+    pub point: Vector3<f32>,
+    pub distance: f32,
+}
+
+// ray casting against every part instance of a brick
+#[derive(Debug, Clone)]
+pub struct Picker {
+    parts: Vec<PartInstance>,
+    // LDraw part space to output frame and back
+    to_world: Vec<Matrix4<f32>>,
+    to_part: Vec<Matrix4<f32>>,
+    world_bounds: Vec<Aabb>,
+    bvhs: HashMap<String, Bvh>,
+}
+
+impl Picker {
+    pub fn new(brick: &LDrawBrick, frame: &OutputFrame) -> Self {
+        Self::build(brick, frame, HashMap::new())
+    }
+
+    // after files arrived, only the parts in `changed` are read again
+    pub fn update(&mut self, brick: &LDrawBrick, frame: &OutputFrame, changed: &HashSet<String>) {
+        let mut bvhs = std::mem::take(&mut self.bvhs);
+        bvhs.retain(|filename, _| !changed.contains(filename));
+        *self = Self::build(brick, frame, bvhs);
+    }
+
+    fn build(brick: &LDrawBrick, frame: &OutputFrame, mut bvhs: HashMap<String, Bvh>) -> Self {
+        let parts = part_instances(brick);
+        for part in parts.iter() {
+            if !bvhs.contains_key(&part.filename) {
+                let mut triangles = Vec::new();
+                if let Some(file) = brick.files.get(&part.filename) {
+                    collect_triangles(brick, file, Matrix4::identity(), &mut triangles);
+                }
+                bvhs.insert(part.filename.to_string(), Bvh::new(triangles));
+            }
+        }
+
+        let to_world: Vec<Matrix4<f32>> = parts
+            .iter()
+            .map(|part| frame.matrix().mul(part.transformation))
+            .collect();
+        let to_part = to_world
+            .iter()
+            .map(|matrix| matrix.invert().unwrap_or_else(Matrix4::identity))
+            .collect();
+        let world_bounds = parts
+            .iter()
+            .zip(to_world.iter())
+            .map(|(part, matrix)| bvhs[&part.filename].aabb().transform(matrix))
+            .collect();
+
+        Self {
+            parts,
+            to_world,
+            to_part,
+            world_bounds,
+            bvhs,
+        }
+    }
+
+    pub fn parts(&self) -> &[PartInstance] {
+        &self.parts
+    }
+
+    // the ray is given in the output frame
+    pub fn cast(&self, ray: &Ray) -> Option<PickHit> {
+        let mut candidates: Vec<(usize, f32)> = self
+            .world_bounds
+            .iter()
+            .enumerate()
+            .filter_map(|(index, aabb)| ray.intersect_aabb(aabb).map(|entry| (index, entry)))
+            .collect();
+        candidates.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut nearest: Option<(usize, f32)> = None;
+        for (index, entry) in candidates {
+            if nearest
+                .map(|(_, distance)| entry > distance)
+                .unwrap_or(false)
+            {
+                break;
+            }
+
+            // the direction is not normalized, so distances stay comparable between parts
+            let local = ray.transform(&self.to_part[index]);
+            if let Some(distance) = self.bvhs[&self.parts[index].filename].intersect(&local) {
+                if nearest
+                    .map(|(_, nearest)| distance < nearest)
+                    .unwrap_or(true)
+                {
+                    nearest = Some((index, distance));
+                }
+            }
+        }
+
+        nearest.map(|(index, distance)| PickHit {
+            instance: index,
+            part: self.parts[index].clone(),
+            point: ray.at(distance),
+            distance,
+        })
+    }
+
+    pub fn world_transformation(&self, instance: usize) -> Option<&Matrix4<f32>> {
+        self.to_world.get(instance)
+    }
+}
+
+fn collect_triangles(
+    brick: &LDrawBrick,
+    file: &LDrawFile,
+    matrix: Matrix4<f32>,
+    triangles: &mut Vec<[Vector3<f32>; 3]>,
+) {
+    let point = |point: &Vector3<f32>| matrix.mul(point.extend(1.0)).truncate();
+    for triangle in file.triangles.iter() {
+        triangles.push([point(&triangle.x), point(&triangle.y), point(&triangle.z)]);
+    }
+    for reference in references(brick, file, MAIN_COLOR, &file.bfc_direction) {
+        collect_triangles(
+            brick,
+            reference.file,
+            matrix.mul(reference.matrix()),
+            triangles,
+        );
+    }
+}
+
+// what the JS click and hover callbacks receive
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PickResult {
+    pub instance: usize,
+    filename: String,
+    pub color: u32,
+    color_name: String,
+    source_file: Option<String>,
+    pub source_line: Option<usize>,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub distance: f32,
+}
+
+impl PickResult {
+    pub fn new(hit: &PickHit, brick: &LDrawBrick) -> Self {
+        Self {
+            instance: hit.instance,
+            filename: hit.part.filename.to_string(),
+            color: hit.part.color,
+            color_name: brick.colors.resolve(hit.part.color).name,
+            source_file: hit.part.source.as_ref().map(|(file, _)| file.to_string()),
+            source_line: hit.part.source.as_ref().map(|(_, line)| *line),
+            x: hit.point.x,
+            y: hit.point.y,
+            z: hit.point.z,
+            distance: hit.distance,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl PickResult {
+    #[wasm_bindgen(getter)]
+    pub fn filename(&self) -> String {
+        self.filename.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn color_name(&self) -> String {
+        self.color_name.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn source_file(&self) -> Option<String> {
+        self.source_file.clone()
+    }
+}
+
+// the click and hover callbacks of a window, shared between its handle and the renderer
+#[derive(Debug, Clone, Default)]
+pub struct PickListeners {
+    listeners: Rc<RefCell<(Option<Function>, Option<Function>)>>,
+}
+
+impl PickListeners {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_click(&self, listener: Option<Function>) {
+        self.listeners.borrow_mut().0 = listener;
+    }
+
+    pub fn set_hover(&self, listener: Option<Function>) {
+        self.listeners.borrow_mut().1 = listener;
+    }
+
+    pub fn wants_hover(&self) -> bool {
+        self.listeners.borrow().1.is_some()
+    }
+
+    pub fn click(&self, result: Option<PickResult>) {
+        let listener = self.listeners.borrow().0.clone();
+        call(listener, result);
+    }
+
+    pub fn hover(&self, result: Option<PickResult>) {
+        let listener = self.listeners.borrow().1.clone();
+        call(listener, result);
+    }
+}
+
+fn call(listener: Option<Function>, result: Option<PickResult>) {
+    if let Some(listener) = listener {
+        let value = result.map(JsValue::from).unwrap_or(JsValue::NULL);
+        if let Err(error) = listener.call1(&JsValue::NULL, &value) {
+            log::warn!("pick listener threw {:?}", error);
+        }
+    }
+}
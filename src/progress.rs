@@ -0,0 +1,150 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use js_sys::{Function, Object, Reflect};
+use wasm_bindgen::prelude::*;
+
+use crate::errors::LoadError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildStage {
+    Bounds,
+    Scene,
+    Meshes,
+    Edges,
+    Shadows,
+    Picking,
+}
+
+impl BuildStage {
+    const ALL: [BuildStage; 6] = [
+        BuildStage::Bounds,
+        BuildStage::Scene,
+        BuildStage::Meshes,
+        BuildStage::Edges,
+        BuildStage::Shadows,
+        BuildStage::Picking,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|stage| stage.as_str() == name)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildStage::Bounds => "bounds",
+            BuildStage::Scene => "scene",
+            BuildStage::Meshes => "meshes",
+            BuildStage::Edges => "edges",
+            BuildStage::Shadows => "shadows",
+            BuildStage::Picking => "picking",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadEvent {
+    FilesResolved { resolved: usize, total: usize },
+    // bytes is the size of file, the one download that just finished
+    BytesFetched { file: String, bytes: usize },
+    Warning(LoadError),
+    Stage(BuildStage),
+    FirstFrame,
+    Error(LoadError),
+}
+
+impl LoadEvent {
+    // { type, ...fields } for the JS listeners, warnings and errors carry the fields of
+    // the load error
+    pub fn to_js(&self) -> JsValue {
+        let object: Object = match self {
+            LoadEvent::Warning(error) | LoadEvent::Error(error) => error.to_js().into(),
+            _ => Object::new(),
+        };
+        let set = |key: &str, value: JsValue| {
+            Reflect::set(&object, &JsValue::from_str(key), &value).unwrap();
+        };
+
+        match self {
+            LoadEvent::FilesResolved { resolved, total } => {
+                set("type", JsValue::from_str("files"));
+                set("resolved", JsValue::from_f64(*resolved as f64));
+                set("total", JsValue::from_f64(*total as f64));
+            }
+            LoadEvent::BytesFetched { file, bytes } => {
+                set("type", JsValue::from_str("bytes"));
+                set("file", JsValue::from_str(file));
+                set("bytes", JsValue::from_f64(*bytes as f64));
+            }
+            LoadEvent::Warning(_) => set("type", JsValue::from_str("warning")),
+            LoadEvent::Stage(stage) => {
+                set("type", JsValue::from_str("stage"));
+                set("stage", JsValue::from_str(stage.as_str()));
+            }
+            LoadEvent::FirstFrame => set("type", JsValue::from_str("first_frame")),
+            LoadEvent::Error(_) => set("type", JsValue::from_str("error")),
+        }
+        object.into()
+    }
+
+    pub fn from_js(value: &JsValue) -> Option<Self> {
+        let field = |key: &str| Reflect::get(value, &JsValue::from_str(key)).ok();
+        let count = |key: &str| Some(field(key)?.as_f64()? as usize);
+
+        Some(match field("type")?.as_string()?.as_str() {
+            "files" => LoadEvent::FilesResolved {
+                resolved: count("resolved")?,
+                total: count("total")?,
+            },
+            "bytes" => LoadEvent::BytesFetched {
+                file: field("file")?.as_string()?,
+                bytes: count("bytes")?,
+            },
+            "warning" => LoadEvent::Warning(LoadError::from_js(value)?),
+            "stage" => LoadEvent::Stage(BuildStage::from_name(&field("stage")?.as_string()?)?),
+            "first_frame" => LoadEvent::FirstFrame,
+            "error" => LoadEvent::Error(LoadError::from_js(value)?),
+            _ => return None,
+        })
+    }
+}
+
+// the JS listeners of one window, shared between the loader, the renderer and the handle
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    listeners: Rc<RefCell<Vec<Function>>>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_listener(&self, listener: Function) {
+        self.listeners.borrow_mut().push(listener);
+    }
+
+    pub fn remove_listener(&self, listener: &Function) {
+        self.listeners
+            .borrow_mut()
+            .retain(|existing| existing != listener);
+    }
+
+    pub fn emit(&self, event: LoadEvent) {
+        if self.listeners.borrow().is_empty() {
+            return;
+        }
+
+        let value = event.to_js();
+        // listeners may add or remove listeners while being called
+        let listeners = self.listeners.borrow().clone();
+        for listener in listeners.iter() {
+            if let Err(error) = listener.call1(&JsValue::NULL, &value) {
+                log::warn!("load listener threw {:?}", error);
+            }
+        }
+    }
+}
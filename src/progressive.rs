@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+use std::ops::Mul;
+
+use js_sys::{Date, Promise};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::AbortSignal;
+use winit::event_loop::EventLoopProxy;
+
+use crate::{
+    bounds::{Aabb, ModelBounds},
+    coordinates::OutputFrame,
+    errors::LoadError,
+    events::RenderingUserEvent,
+    parser::{
+        bundle::{fetch_bundle, Bundle, Resolver},
+        colors::{parse_ldconfig, LDrawColor},
+        part::{LDrawBrick, LDrawFile},
+    },
+    progress::{LoadEvent, Progress},
+    scene::{pending_parts, placeholder_box, Scene},
+};
+
+// how long the loader parses before the page gets to draw
+const PARSE_BUDGET_MS: f64 = 12.0;
+// every update uploads the parts that completed since the last one, spacing them out
+// keeps the number of draw calls down
+const UPDATE_INTERVAL_MS: f64 = 250.0;
+
+// what a window starts from, parts waiting for pending files are drawn as placeholders
+pub struct LoadedBrick {
+    pub brick: LDrawBrick,
+    // built elsewhere, by the worker
+    pub scene: Option<Scene>,
+    pub pending: HashSet<String>,
+}
+
+impl LoadedBrick {
+    pub fn complete(brick: LDrawBrick, scene: Option<Scene>) -> Self {
+        Self {
+            brick,
+            scene,
+            pending: HashSet::new(),
+        }
+    }
+}
+
+// the files parsed and the colours defined since the last update
+#[derive(Debug, Clone)]
+pub struct BrickUpdate {
+    pub files: Vec<LDrawFile>,
+    pub colors: Vec<LDrawColor>,
+    pub pending: HashSet<String>,
+}
+
+impl BrickUpdate {
+    pub fn apply(self, brick: &mut LDrawBrick) -> HashSet<String> {
+        for file in self.files {
+            brick.files.insert(file.name.to_string(), file);
+        }
+        for color in self.colors {
+            brick.colors.insert(color);
+        }
+        self.pending
+    }
+}
+
+// the model bounds with a placeholder box for every part that has not arrived, the
+// camera is framed on these so it does not have to move once the geometry is there
+pub fn loading_bounds(brick: &LDrawBrick, pending: &HashSet<String>, frame: OutputFrame) -> Aabb {
+    let matrix = frame.matrix();
+    pending_parts(brick, pending).iter().fold(
+        ModelBounds::compute(brick, frame).framed_aabb(),
+        |bounds, part| bounds.union(&placeholder_box().transform(&matrix.mul(part.transformation))),
+    )
+}
+
+// parsing is spread over frames and parts show up as they complete, the bundle itself is
+// still fetched as one zip before the first frame
+pub struct ProgressiveLoad {
+    bundle: Bundle,
+    resolver: Resolver,
+}
+
+impl ProgressiveLoad {
+    // fetches the bundle and parses only the entry file, that is enough to place the parts
+    pub async fn start(
+        brick_id: &str,
+        signal: &AbortSignal,
+        progress: &Progress,
+    ) -> Result<Self, LoadError> {
+        progress.emit(LoadEvent::FilesResolved {
+            resolved: 0,
+            total: 1,
+        });
+        let bundle = fetch_bundle(brick_id, Some(signal), progress).await?;
+        let colors = parse_ldconfig(Some(signal)).await?;
+        let mut resolver = Resolver::new(&format!("{}.dat", brick_id), colors);
+        resolver.step(&bundle, progress)?;
+        // the window starts with these
+        resolver.take_fresh();
+        resolver.take_fresh_colors();
+        Ok(Self { bundle, resolver })
+    }
+
+    pub fn loaded(&self) -> LoadedBrick {
+        LoadedBrick {
+            brick: self.resolver.brick(),
+            scene: None,
+            pending: self.resolver.pending(),
+        }
+    }
+
+    // parses the remaining files between frames and sends them to window id, resolves to
+    // the complete brick
+    pub async fn run(
+        mut self,
+        id: usize,
+        proxy: &EventLoopProxy<RenderingUserEvent<()>>,
+        signal: &AbortSignal,
+        progress: &Progress,
+    ) -> Result<LDrawBrick, LoadError> {
+        let mut updated_at = Date::now();
+        while !self.resolver.is_done() {
+            let started = Date::now();
+            while !self.resolver.is_done() && Date::now() - started < PARSE_BUDGET_MS {
+                self.resolver.step(&self.bundle, progress)?;
+            }
+            if self.resolver.is_done() || Date::now() - updated_at >= UPDATE_INTERVAL_MS {
+                let update = BrickUpdate {
+                    files: self.resolver.take_fresh(),
+                    colors: self.resolver.take_fresh_colors(),
+                    pending: self.resolver.pending(),
+                };
+                // the window may be gone already, the loop notices the signal below
+                proxy
+                    .send_event(RenderingUserEvent::InternalUpdateBrick(id, update))
+                    .ok();
+                updated_at = Date::now();
+            }
+
+            yield_to_page().await;
+            if signal.aborted() {
+                return Err(LoadError::cancelled());
+            }
+        }
+        Ok(self.resolver.finish())
+    }
+}
+
+async fn yield_to_page() {
+    let promise = Promise::new(&mut |resolve, _| {
+        let scheduled = web_sys::window()
+            .map(|window| window.set_timeout_with_callback(&resolve).is_ok())
+            .unwrap_or(false);
+        if !scheduled {
+            resolve.call0(&JsValue::NULL).ok();
+        }
+    });
+    JsFuture::from(promise).await.ok();
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    camera::Projection,
+    parser::colors::{ColorTable, LDrawColor},
+};
+
+// the key light of a preset casts shadows unless the quality turns them off, Flat has no
+// key light and so no shadows
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightingPreset {
+    // a key and a fill light
+    Studio,
+    // mostly ambient with a weak key light
+    Soft,
+    // ambient only, useful for instructions style renders
+    Flat,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    Low,
+    Medium,
+    High,
+}
+
+impl Quality {
+    pub fn shadow_map_size(&self) -> Option<u32> {
+        match self {
+            Quality::Low => None,
+            Quality::Medium => Some(1024),
+            Quality::High => Some(2048),
+        }
+    }
+
+    pub fn edge_subdivisions(&self) -> u32 {
+        match self {
+            Quality::Low => 4,
+            Quality::Medium => 8,
+            Quality::High => 16,
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderProps {
+    background: [u8; 4],
+    pub show_edges: bool,
+    color_overrides: HashMap<u32, [u8; 4]>,
+    pub lighting: LightingPreset,
+    pub projection: Projection,
+    pub quality: Quality,
+}
+
+impl Default for RenderProps {
+    fn default() -> Self {
+        Self {
+            background: [204, 204, 204, 255],
+            show_edges: false,
+            color_overrides: HashMap::new(),
+            lighting: LightingPreset::Studio,
+            projection: Projection::Perspective,
+            quality: Quality::Medium,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl RenderProps {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_background(&mut self, r: u8, g: u8, b: u8, a: u8) {
+        self.background = [r, g, b, a];
+    }
+
+    pub fn set_color_override(&mut self, code: u32, r: u8, g: u8, b: u8, a: u8) {
+        self.color_overrides.insert(code, [r, g, b, a]);
+    }
+
+    pub fn remove_color_override(&mut self, code: u32) {
+        self.color_overrides.remove(&code);
+    }
+
+    pub fn clear_color_overrides(&mut self) {
+        self.color_overrides.clear();
+    }
+}
+
+impl RenderProps {
+    pub fn background(&self) -> [u8; 4] {
+        self.background
+    }
+
+    // the colour with its value and alpha replaced by an override, the finish is kept
+    pub fn resolve_color(&self, colors: &ColorTable, code: u32) -> LDrawColor {
+        let mut color = colors.resolve(code);
+        if let Some([r, g, b, a]) = self.color_overrides.get(&code) {
+            color.value = [*r, *g, *b];
+            color.alpha = *a;
+        }
+        color
+    }
+
+    pub fn diff(&self, other: &RenderProps) -> PropsDiff {
+        let mut colors: Vec<u32> = self
+            .color_overrides
+            .keys()
+            .chain(other.color_overrides.keys())
+            .filter(|code| self.color_overrides.get(code) != other.color_overrides.get(code))
+            .copied()
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+
+        PropsDiff {
+            background: self.background != other.background,
+            edges: self.show_edges != other.show_edges,
+            colors,
+            lighting: self.lighting != other.lighting,
+            projection: self.projection != other.projection,
+            quality: self.quality != other.quality,
+        }
+    }
+}
+
+// what changed between two sets of props, so only those parts get rebuilt
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PropsDiff {
+    pub background: bool,
+    pub edges: bool,
+    // colour codes whose override was added, changed or removed
+    pub colors: Vec<u32>,
+    pub lighting: bool,
+    pub projection: bool,
+    pub quality: bool,
+}
+
+impl PropsDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == PropsDiff::default()
+    }
+}
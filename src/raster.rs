@@ -0,0 +1,367 @@
+use std::cmp::Ordering;
+use std::ops::Mul;
+
+use three_d::{InnerSpace, Matrix4, Vector3, Vector4};
+
+use crate::{
+    bounds::ModelBounds,
+    camera::{CameraSetup, ViewPreset},
+    capture::{encode_png, MAX_CAPTURE_SIZE},
+    parser::{colors::ColorTable, part::LDrawBrick},
+    props::RenderProps,
+    scene::{Scene, SceneOptions},
+};
+
+const AMBIENT: f32 = 0.35;
+// lines are drawn slightly in front of the surfaces they lie on
+const LINE_DEPTH_BIAS: f32 = 1e-4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RasterTriangle {
+    pub positions: [Vector3<f32>; 3],
+    pub color: [u8; 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RasterLine {
+    pub positions: [Vector3<f32>; 2],
+    pub color: [u8; 4],
+}
+
+// the scene flattened into coloured triangles and lines in the output frame
+#[derive(Debug, Clone, Default)]
+pub struct RasterScene {
+    pub triangles: Vec<RasterTriangle>,
+    pub lines: Vec<RasterLine>,
+}
+
+impl RasterScene {
+    pub fn new(scene: &Scene, colors: &ColorTable, props: &RenderProps) -> Self {
+        let color = |code: u32| props.resolve_color(colors, code).rgba();
+        let mut raster = Self::default();
+
+        for instance in scene.instances.iter() {
+            let point = |position: &Vector3<f32>| {
+                instance.transformation.mul(position.extend(1.0)).truncate()
+            };
+            let mesh = &scene.meshes[instance.mesh];
+            for group in mesh.groups.iter() {
+                let color = color(group.color);
+                for triangle in group.positions.chunks_exact(3) {
+                    raster.triangles.push(RasterTriangle {
+                        positions: [
+                            point(&triangle[0]),
+                            point(&triangle[1]),
+                            point(&triangle[2]),
+                        ],
+                        color,
+                    });
+                }
+            }
+            if props.show_edges {
+                for edges in mesh.edges.iter() {
+                    let color = color(edges.color);
+                    for line in edges.positions.chunks_exact(2) {
+                        raster.lines.push(RasterLine {
+                            positions: [point(&line[0]), point(&line[1])],
+                            color,
+                        });
+                    }
+                }
+            }
+        }
+
+        // already in world space
+        for group in scene.transparent.iter() {
+            let color = color(group.color);
+            for triangle in group.positions.chunks_exact(3) {
+                raster.triangles.push(RasterTriangle {
+                    positions: [triangle[0], triangle[1], triangle[2]],
+                    color,
+                });
+            }
+        }
+
+        raster
+    }
+}
+
+// a z-buffered software renderer, everything runs on the CPU and the output only
+// depends on the input
+pub struct Rasterizer {
+    width: u32,
+    height: u32,
+    color: Vec<[u8; 4]>,
+    depth: Vec<f32>,
+}
+
+impl Rasterizer {
+    // limited like captures, the buffers take 20 bytes a pixel
+    pub fn new(width: u32, height: u32) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err("can't rasterize an empty image".to_string());
+        }
+        let size = (width as usize)
+            .checked_mul(height as usize)
+            .filter(|_| width.max(height) <= MAX_CAPTURE_SIZE)
+            .ok_or_else(|| {
+                format!(
+                    "can't rasterize images larger than {0}x{0}",
+                    MAX_CAPTURE_SIZE
+                )
+            })?;
+        Ok(Self {
+            width,
+            height,
+            color: vec![[0, 0, 0, 0]; size],
+            depth: vec![f32::INFINITY; size],
+        })
+    }
+
+    pub fn clear(&mut self, color: [u8; 4]) {
+        self.color.iter_mut().for_each(|pixel| *pixel = color);
+        self.depth
+            .iter_mut()
+            .for_each(|depth| *depth = f32::INFINITY);
+    }
+
+    // rows from top to bottom, RGBA
+    pub fn pixels(&self) -> &[[u8; 4]] {
+        &self.color
+    }
+
+    // depth in 0..1 of the nearest opaque surface, infinite where there is none
+    pub fn depth(&self, x: u32, y: u32) -> f32 {
+        if x >= self.width || y >= self.height {
+            return f32::INFINITY;
+        }
+        self.depth[(y * self.width + x) as usize]
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, String> {
+        encode_png(&self.color, self.width, self.height)
+    }
+
+    pub fn draw(&mut self, scene: &RasterScene, camera: &CameraSetup) {
+        let aspect = self.width as f32 / self.height.max(1) as f32;
+        let view_projection = camera.view_projection(aspect);
+        // a light over the shoulder of the viewer, so every render is lit the same way
+        let right = camera.direction().cross(camera.up).normalize();
+        let light = (camera.direction() + camera.up * 0.6 - right * 0.3).normalize();
+
+        let (opaque, mut transparent): (Vec<&RasterTriangle>, Vec<&RasterTriangle>) = scene
+            .triangles
+            .iter()
+            .partition(|triangle| triangle.color[3] == 255);
+
+        for triangle in opaque {
+            self.draw_triangle(triangle, &view_projection, light, true);
+        }
+        for line in scene.lines.iter() {
+            self.draw_line(line, &view_projection);
+        }
+
+        // back to front and blended without writing depth
+        let distance = |triangle: &RasterTriangle| {
+            let center =
+                (triangle.positions[0] + triangle.positions[1] + triangle.positions[2]) / 3.0;
+            (center - camera.position).magnitude2()
+        };
+        transparent.sort_by(|a, b| {
+            distance(b)
+                .partial_cmp(&distance(a))
+                .unwrap_or(Ordering::Equal)
+        });
+        for triangle in transparent {
+            self.draw_triangle(triangle, &view_projection, light, false);
+        }
+    }
+
+    // clip space to pixel coordinates and depth in 0..1
+    fn to_screen(&self, clip: Vector4<f32>) -> Vector3<f32> {
+        let ndc = clip.truncate() / clip.w;
+        Vector3::new(
+            (ndc.x + 1.0) * 0.5 * self.width as f32,
+            (1.0 - ndc.y) * 0.5 * self.height as f32,
+            (ndc.z + 1.0) * 0.5,
+        )
+    }
+
+    fn draw_triangle(
+        &mut self,
+        triangle: &RasterTriangle,
+        view_projection: &Matrix4<f32>,
+        light: Vector3<f32>,
+        write_depth: bool,
+    ) {
+        let [a, b, c] = triangle.positions;
+        let normal = (b - a).cross(c - a);
+        if normal.magnitude2() == 0.0 {
+            return;
+        }
+        // LDraw windings can't be trusted, so both sides are lit
+        let shade = AMBIENT + (1.0 - AMBIENT) * normal.normalize().dot(light).abs();
+        let [r, g, b, alpha] = triangle.color;
+        let shaded = [
+            (r as f32 * shade).round() as u8,
+            (g as f32 * shade).round() as u8,
+            (b as f32 * shade).round() as u8,
+            alpha,
+        ];
+
+        let clip: Vec<Vector4<f32>> = triangle
+            .positions
+            .iter()
+            .map(|position| view_projection.mul(position.extend(1.0)))
+            .collect();
+        let polygon = clip_near(&clip);
+        if polygon.len() < 3 {
+            return;
+        }
+        let screen: Vec<Vector3<f32>> = polygon.iter().map(|clip| self.to_screen(*clip)).collect();
+        for i in 1..screen.len() - 1 {
+            self.fill([screen[0], screen[i], screen[i + 1]], shaded, write_depth);
+        }
+    }
+
+    fn fill(&mut self, points: [Vector3<f32>; 3], color: [u8; 4], write_depth: bool) {
+        let [p0, p1, p2] = points;
+        let area = edge(p0, p1, p2);
+        if area == 0.0 {
+            return;
+        }
+
+        let min_x = p0.x.min(p1.x).min(p2.x).floor().max(0.0) as u32;
+        let min_y = p0.y.min(p1.y).min(p2.y).floor().max(0.0) as u32;
+        let max_x = (p0.x.max(p1.x).max(p2.x).ceil() as u32).min(self.width);
+        let max_y = (p0.y.max(p1.y).max(p2.y).ceil() as u32).min(self.height);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                // sampled at the pixel centre
+                let p = Vector3::new(x as f32 + 0.5, y as f32 + 0.5, 0.0);
+                let w0 = edge(p1, p2, p) / area;
+                let w1 = edge(p2, p0, p) / area;
+                let w2 = edge(p0, p1, p) / area;
+                if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                    continue;
+                }
+
+                let depth = w0 * p0.z + w1 * p1.z + w2 * p2.z;
+                let index = (y * self.width + x) as usize;
+                if !(0.0..=1.0).contains(&depth) || depth >= self.depth[index] {
+                    continue;
+                }
+                if write_depth {
+                    self.depth[index] = depth;
+                    self.color[index] = color;
+                } else {
+                    self.color[index] = blend(self.color[index], color);
+                }
+            }
+        }
+    }
+
+    fn draw_line(&mut self, line: &RasterLine, view_projection: &Matrix4<f32>) {
+        let clip: Vec<Vector4<f32>> = line
+            .positions
+            .iter()
+            .map(|position| view_projection.mul(position.extend(1.0)))
+            .collect();
+        let clipped = clip_near(&clip);
+        if clipped.len() < 2 {
+            return;
+        }
+        let (from, to) = (self.to_screen(clipped[0]), self.to_screen(clipped[1]));
+
+        let steps = (to.x - from.x)
+            .abs()
+            .max((to.y - from.y).abs())
+            .ceil()
+            .max(1.0) as u32;
+        for step in 0..=steps {
+            let point = from + (to - from) * (step as f32 / steps as f32);
+            if point.x < 0.0 || point.y < 0.0 {
+                continue;
+            }
+            let (x, y) = (point.x as u32, point.y as u32);
+            if x >= self.width || y >= self.height {
+                continue;
+            }
+
+            let index = (y * self.width + x) as usize;
+            let depth = point.z - LINE_DEPTH_BIAS;
+            if depth <= self.depth[index] {
+                self.depth[index] = depth;
+                self.color[index] = line.color;
+            }
+        }
+    }
+}
+
+fn edge(a: Vector3<f32>, b: Vector3<f32>, p: Vector3<f32>) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+fn blend(below: [u8; 4], above: [u8; 4]) -> [u8; 4] {
+    let alpha = above[3] as f32 / 255.0;
+    let mix =
+        |below: u8, above: u8| (below as f32 * (1.0 - alpha) + above as f32 * alpha).round() as u8;
+    [
+        mix(below[0], above[0]),
+        mix(below[1], above[1]),
+        mix(below[2], above[2]),
+        below[3].max(above[3]),
+    ]
+}
+
+// cuts a polygon (or a line) in clip space at the near plane, z >= -w
+fn clip_near(points: &[Vector4<f32>]) -> Vec<Vector4<f32>> {
+    let inside = |point: &Vector4<f32>| point.z + point.w;
+    let mut clipped = Vec::with_capacity(points.len() + 1);
+    // a line is not closed, so only its one segment is checked
+    let segments = if points.len() == 2 { 1 } else { points.len() };
+
+    if points.len() == 2 && inside(&points[0]) >= 0.0 {
+        clipped.push(points[0]);
+    }
+    for i in 0..segments {
+        let (current, next) = (points[i], points[(i + 1) % points.len()]);
+        let (d0, d1) = (inside(&current), inside(&next));
+        if points.len() > 2 && d0 >= 0.0 {
+            clipped.push(current);
+        }
+        if (d0 >= 0.0) != (d1 >= 0.0) {
+            clipped.push(current + (next - current) * (d0 / (d0 - d1)));
+        }
+        if points.len() == 2 && d1 >= 0.0 {
+            clipped.push(next);
+        }
+    }
+    clipped
+}
+
+// a PNG of the brick from one of the view presets, without touching the GPU
+pub fn render_thumbnail(
+    brick: &LDrawBrick,
+    width: u32,
+    height: u32,
+    preset: ViewPreset,
+    props: &RenderProps,
+) -> Result<Vec<u8>, String> {
+    let options = SceneOptions::default();
+    let scene = Scene::build(brick, &options);
+    let bounds = ModelBounds::compute(brick, options.frame).framed_aabb();
+    let camera = CameraSetup::preset(
+        &bounds,
+        preset,
+        props.projection,
+        &options.frame,
+        width as f32 / height.max(1) as f32,
+    );
+
+    let mut rasterizer = Rasterizer::new(width, height)?;
+    rasterizer.clear(props.background());
+    rasterizer.draw(&RasterScene::new(&scene, &brick.colors, props), &camera);
+    rasterizer.to_png()
+}
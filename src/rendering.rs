@@ -1,185 +1,1383 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::ops::Mul;
+use std::rc::Rc;
 
 use three_d::{
-    degrees, vec3, AmbientLight, Camera, ClearState, Color, CpuMaterial, CpuMesh, Cull,
-    DirectionalLight, Event, FrameOutput, Gm, Matrix, Matrix3, Matrix4, Mesh, OrbitControl,
-    PhysicalMaterial, Vector3, Viewport, Window,
+    degrees, vec3, AmbientLight, Blend, Camera, ClearState, Color, ColorMaterial, Context,
+    CpuMaterial, CpuMesh, Cull, DepthTest, DepthTexture2D, DirectionalLight, Event, FrameInput,
+    FrameOutput, Geometry, Gm, InnerSpace, InstancedMesh, Instances, Interpolation, Light, Matrix4,
+    Mesh, MouseButton, Object, OrbitControl, PhysicalMaterial, Quaternion, RenderStates,
+    RenderTarget, Texture2D, Vector3, Viewport, Window, Wrapping, WriteMask,
 };
+use web_sys::HtmlCanvasElement;
 
 use crate::{
-    events::RenderingUserEvent,
-    parser::part::LDrawFile,
-    parser::{part::LDrawBrick, tokenizer::BFCDirection},
+    bounds::Aabb,
+    camera::{CameraSetup, ViewPreset},
+    capture::{downsample, encode_png, CaptureRequest},
+    coordinates::OutputFrame,
+    debug::{ResourceCounter, FRAME_GEOMETRIES, LIVE_WINDOWS},
+    events::{RenderingUserEvent, RenderingWindow},
+    materials::MaterialParams,
+    parser::{colors::ColorTable, part::LDrawBrick},
+    picking::{PickListeners, PickResult, Picker, Ray},
+    progress::{BuildStage, LoadEvent, Progress},
+    progressive::{loading_bounds, BrickUpdate, LoadedBrick},
+    props::{LightingPreset, Quality, RenderProps},
+    scene::{Scene, SceneMesh, SceneOptions, TransparencySort},
+    selection::{Selection, SelectionStyle, UnselectedStyle},
+    smoothing::extrude,
 };
 
-fn get_vertices(
-    file: &LDrawFile,
-    brick: &LDrawBrick,
-    matrix: Matrix4<f32>,
-    winding: &BFCDirection,
-) -> Vec<Vector3<f32>> {
-    let mut vertices = Vec::new();
-    for triangle in file.triangles.iter() {
-        if matches!(winding, &BFCDirection::CCW) {
-            vertices.push(matrix.mul(triangle.x.extend(1.0)).truncate());
-            vertices.push(matrix.mul(triangle.y.extend(1.0)).truncate());
-            vertices.push(matrix.mul(triangle.z.extend(1.0)).truncate());
-        } else {
-            vertices.push(matrix.mul(triangle.x.extend(1.0)).truncate());
-            vertices.push(matrix.mul(triangle.z.extend(1.0)).truncate());
-            vertices.push(matrix.mul(triangle.y.extend(1.0)).truncate());
+fn cpu_mesh(positions: &[Vector3<f32>]) -> CpuMesh {
+    let mut cpu_mesh = CpuMesh {
+        positions: three_d::Positions::F32(positions.to_vec()),
+        indices: three_d::Indices::None,
+        normals: None,
+        tangents: None,
+        uvs: None,
+        colors: None,
+    };
+    cpu_mesh.compute_normals();
+    cpu_mesh
+}
+
+fn transparent_material(context: &Context, cpu_material: &CpuMaterial) -> PhysicalMaterial {
+    let mut material = PhysicalMaterial::new_transparent(context, cpu_material);
+    // sorted back to front, so blend without writing depth
+    material.render_states = RenderStates {
+        write_mask: WriteMask::COLOR,
+        blend: Blend::TRANSPARENCY,
+        cull: Cull::None,
+        ..Default::default()
+    };
+    material
+}
+
+struct SortedInstances {
+    color: u32,
+    mesh: Gm<InstancedMesh, PhysicalMaterial>,
+    transformations: Vec<Matrix4<f32>>,
+    // centre of the mesh in its own space
+    center: Vector3<f32>,
+}
+
+impl SortedInstances {
+    fn distance2(&self, transformation: &Matrix4<f32>, eye: Vector3<f32>) -> f32 {
+        (transformation.mul(self.center.extend(1.0)).truncate() - eye).magnitude2()
+    }
+
+    fn farthest(&self, eye: Vector3<f32>) -> f32 {
+        self.transformations
+            .iter()
+            .map(|transformation| self.distance2(transformation, eye))
+            .fold(0.0, f32::max)
+    }
+
+    fn sort(&mut self, eye: Vector3<f32>) {
+        let mut transformations = std::mem::take(&mut self.transformations);
+        transformations.sort_by(|a, b| {
+            self.distance2(b, eye)
+                .partial_cmp(&self.distance2(a, eye))
+                .unwrap_or(Ordering::Equal)
+        });
+        self.transformations = transformations;
+
+        self.mesh.set_instances(&Instances {
+            transformations: self.transformations.clone(),
+            ..Default::default()
+        });
+    }
+}
+
+// the merged triangles are sorted again only once the eye has turned this many degrees
+// around them or changed its distance by this fraction, every sort uploads the mesh
+const RESORT_ANGLE: f32 = 5.0;
+const RESORT_DISTANCE: f32 = 0.1;
+
+struct SortedTriangles {
+    context: Context,
+    positions: Vec<Vector3<f32>>,
+    // one colour code per triangle and the resolved colour per vertex
+    codes: Vec<u32>,
+    colors: Vec<Color>,
+    // the surface of each triangle as an index into materials, albedo and alpha come
+    // from the vertex colours
+    surfaces: Vec<usize>,
+    materials: Vec<MaterialParams>,
+    center: Vector3<f32>,
+    sorted_for: Option<Vector3<f32>>,
+    // back to front, a mesh for every run of triangles with the same surface
+    meshes: Vec<Gm<Mesh, PhysicalMaterial>>,
+}
+
+impl SortedTriangles {
+    fn new(context: &Context, scene: &Scene, colors: &ColorTable, props: &RenderProps) -> Self {
+        let mut triangles = Self {
+            context: context.clone(),
+            positions: Vec::new(),
+            codes: Vec::new(),
+            colors: Vec::new(),
+            surfaces: Vec::new(),
+            materials: Vec::new(),
+            center: vec3(0.0, 0.0, 0.0),
+            sorted_for: None,
+            meshes: Vec::new(),
+        };
+        for group in scene.transparent.iter() {
+            triangles.positions.extend_from_slice(&group.positions);
+            triangles
+                .codes
+                .extend(std::iter::repeat(group.color).take(group.triangle_count()));
         }
+        if !triangles.positions.is_empty() {
+            let sum = triangles
+                .positions
+                .iter()
+                .fold(vec3(0.0, 0.0, 0.0), |sum, position| sum + position);
+            triangles.center = sum / triangles.positions.len() as f32;
+        }
+        triangles.recolor(colors, props);
+        triangles
     }
 
-    for subfile in file.subfiles.iter() {
-        let new_matrix = matrix
-            .mul(Matrix4::from_translation(subfile.translation))
-            .mul(Matrix4::from(subfile.transformation).transpose());
+    fn recolor(&mut self, colors: &ColorTable, props: &RenderProps) {
+        self.colors.clear();
+        self.surfaces.clear();
+        self.materials.clear();
+        for code in self.codes.iter() {
+            let color = props.resolve_color(colors, *code);
+            let [r, g, b, a] = color.rgba();
+            self.colors
+                .extend(std::iter::repeat(Color { r, g, b, a }).take(3));
 
-        let mut subfile_vertices = get_vertices(
-            brick.files.get(&subfile.filename).unwrap(),
-            brick,
-            new_matrix,
-            &subfile.bfc_direction,
-        );
-        vertices.append(&mut subfile_vertices)
+            let surface = MaterialParams {
+                albedo: [255, 255, 255, 255],
+                ..MaterialParams::from_color(&color)
+            };
+            let index = match self.materials.iter().position(|other| *other == surface) {
+                Some(index) => index,
+                None => {
+                    self.materials.push(surface);
+                    self.materials.len() - 1
+                }
+            };
+            self.surfaces.push(index);
+        }
+        // rebuilt on the next sort
+        self.sorted_for = None;
     }
 
-    vertices
+    // the triangles of another scene join these and are sorted along with them
+    fn append(&mut self, other: SortedTriangles) {
+        if other.positions.is_empty() {
+            return;
+        }
+        let (count, added) = (self.positions.len() as f32, other.positions.len() as f32);
+        self.center = (self.center * count + other.center * added) / (count + added);
+        self.positions.extend(other.positions);
+        self.codes.extend(other.codes);
+        self.colors.extend(other.colors);
+        for surface in other.surfaces {
+            let material = other.materials[surface];
+            let index = match self.materials.iter().position(|other| *other == material) {
+                Some(index) => index,
+                None => {
+                    self.materials.push(material);
+                    self.materials.len() - 1
+                }
+            };
+            self.surfaces.push(index);
+        }
+        self.sorted_for = None;
+    }
+
+    fn needs_sort(&self, eye: Vector3<f32>) -> bool {
+        let sorted_for = match self.sorted_for {
+            Some(sorted_for) => sorted_for,
+            None => return true,
+        };
+        let (before, now) = (sorted_for - self.center, eye - self.center);
+        let (before_distance, distance) = (before.magnitude(), now.magnitude());
+        if before_distance == 0.0 || distance == 0.0 {
+            return before != now;
+        }
+        let turned =
+            before.dot(now) / (before_distance * distance) < RESORT_ANGLE.to_radians().cos();
+        let moved = (distance - before_distance).abs() > RESORT_DISTANCE * before_distance;
+        turned || moved
+    }
+
+    fn sort(&mut self, eye: Vector3<f32>) {
+        if self.positions.is_empty() || !self.needs_sort(eye) {
+            return;
+        }
+        self.sorted_for = Some(eye);
+
+        let centroid = |triangle: usize| {
+            (self.positions[triangle * 3]
+                + self.positions[triangle * 3 + 1]
+                + self.positions[triangle * 3 + 2])
+                / 3.0
+        };
+        let mut order: Vec<(usize, f32)> = (0..self.positions.len() / 3)
+            .map(|triangle| (triangle, (centroid(triangle) - eye).magnitude2()))
+            .collect();
+        order.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+
+        self.meshes.clear();
+        let mut start = 0;
+        while start < order.len() {
+            let surface = self.surfaces[order[start].0];
+            let end = order[start..]
+                .iter()
+                .position(|(triangle, _)| self.surfaces[*triangle] != surface)
+                .map_or(order.len(), |length| start + length);
+
+            let mut positions = Vec::with_capacity((end - start) * 3);
+            let mut colors = Vec::with_capacity((end - start) * 3);
+            for (triangle, _) in order[start..end].iter() {
+                positions.extend_from_slice(&self.positions[triangle * 3..triangle * 3 + 3]);
+                colors.extend_from_slice(&self.colors[triangle * 3..triangle * 3 + 3]);
+            }
+            let mut cpu_mesh = CpuMesh {
+                positions: three_d::Positions::F32(positions),
+                indices: three_d::Indices::None,
+                normals: None,
+                tangents: None,
+                uvs: None,
+                colors: Some(colors),
+            };
+            cpu_mesh.compute_normals();
+
+            self.meshes.push(Gm::new(
+                Mesh::new(&self.context, &cpu_mesh),
+                transparent_material(&self.context, &self.materials[surface].to_cpu_material()),
+            ));
+            start = end;
+        }
+    }
 }
 
-fn generate_brick_mesh(brick: LDrawBrick) -> CpuMesh {
-    let entry_file = brick.files.get(&brick.entry_file).unwrap();
-    let vertices: Vec<Vector3<f32>> = get_vertices(
-        entry_file,
-        &brick,
-        Matrix4::new(
-            1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
-        ),
-        &entry_file.bfc_direction,
-    )
-    .iter()
-    .map(|vertex| Matrix3::new(1.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 0.0, -1.0).mul(vertex))
-    .collect();
+enum TransparentObjects {
+    PerInstance(Vec<SortedInstances>),
+    PerTriangle(SortedTriangles),
+}
 
-    CpuMesh {
-        positions: three_d::Positions::F32(vertices),
-        indices: three_d::Indices::None,
-        normals: None,
-        tangents: None,
-        uvs: None,
-        colors: None,
+struct SceneObjects {
+    meshes: Vec<(u32, Gm<Mesh, PhysicalMaterial>)>,
+    instanced_meshes: Vec<(u32, Gm<InstancedMesh, PhysicalMaterial>)>,
+    transparent: TransparentObjects,
+    sorted_for: Option<Vector3<f32>>,
+}
+
+impl SceneObjects {
+    fn new(
+        context: &Context,
+        scene: &Scene,
+        colors: &ColorTable,
+        props: &RenderProps,
+        sort: TransparencySort,
+    ) -> Self {
+        let mut meshes = Vec::new();
+        let mut instanced_meshes = Vec::new();
+        let mut sorted_instances = Vec::new();
+
+        for (index, scene_mesh) in scene.meshes.iter().enumerate() {
+            let transformations: Vec<Matrix4<f32>> = scene
+                .instances_of(index)
+                .map(|instance| instance.transformation)
+                .collect();
+            if transformations.is_empty() {
+                continue;
+            }
+
+            for group in scene_mesh.groups.iter() {
+                let cpu_mesh = cpu_mesh(&group.positions);
+
+                let cpu_material =
+                    MaterialParams::from_color(&props.resolve_color(colors, group.color))
+                        .to_cpu_material();
+
+                if group.transparent {
+                    sorted_instances.push(SortedInstances {
+                        color: group.color,
+                        mesh: Gm::new(
+                            InstancedMesh::new(
+                                context,
+                                &Instances {
+                                    transformations: transformations.clone(),
+                                    ..Default::default()
+                                },
+                                &cpu_mesh,
+                            ),
+                            transparent_material(context, &cpu_material),
+                        ),
+                        transformations: transformations.clone(),
+                        center: group
+                            .positions
+                            .iter()
+                            .fold(vec3(0.0, 0.0, 0.0), |sum, position| sum + position)
+                            / group.positions.len().max(1) as f32,
+                    });
+                    continue;
+                }
+
+                let material = PhysicalMaterial::new_opaque(context, &cpu_material);
+                if transformations.len() == 1 {
+                    let mut mesh = Gm::new(Mesh::new(context, &cpu_mesh), material);
+                    mesh.set_transformation(transformations[0]);
+                    meshes.push((group.color, mesh));
+                } else {
+                    instanced_meshes.push((
+                        group.color,
+                        Gm::new(
+                            InstancedMesh::new(
+                                context,
+                                &Instances {
+                                    transformations: transformations.clone(),
+                                    ..Default::default()
+                                },
+                                &cpu_mesh,
+                            ),
+                            material,
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let transparent = match sort {
+            TransparencySort::PerInstance => TransparentObjects::PerInstance(sorted_instances),
+            TransparencySort::PerTriangle => {
+                TransparentObjects::PerTriangle(SortedTriangles::new(context, scene, colors, props))
+            }
+        };
+
+        Self {
+            meshes,
+            instanced_meshes,
+            transparent,
+            sorted_for: None,
+        }
+    }
+
+    // the objects of another scene, built with the same sort, are drawn with these
+    fn extend(&mut self, other: SceneObjects) {
+        self.meshes.extend(other.meshes);
+        self.instanced_meshes.extend(other.instanced_meshes);
+        match (&mut self.transparent, other.transparent) {
+            (TransparentObjects::PerInstance(sorted), TransparentObjects::PerInstance(other)) => {
+                sorted.extend(other)
+            }
+            (TransparentObjects::PerTriangle(sorted), TransparentObjects::PerTriangle(other)) => {
+                sorted.append(other)
+            }
+            _ => {}
+        }
+        self.sorted_for = None;
+    }
+
+    // swaps the materials of the given colour codes, the geometry stays as it is. A group
+    // keeps being drawn opaque or transparent even if an override changes its alpha
+    fn recolor(
+        &mut self,
+        context: &Context,
+        colors: &ColorTable,
+        props: &RenderProps,
+        codes: &[u32],
+    ) {
+        let cpu_material = |code: u32| {
+            MaterialParams::from_color(&props.resolve_color(colors, code)).to_cpu_material()
+        };
+
+        for (code, mesh) in self.meshes.iter_mut() {
+            if codes.contains(code) {
+                mesh.material = PhysicalMaterial::new_opaque(context, &cpu_material(*code));
+            }
+        }
+        for (code, mesh) in self.instanced_meshes.iter_mut() {
+            if codes.contains(code) {
+                mesh.material = PhysicalMaterial::new_opaque(context, &cpu_material(*code));
+            }
+        }
+
+        match &mut self.transparent {
+            TransparentObjects::PerInstance(sorted) => {
+                for instances in sorted.iter_mut() {
+                    if codes.contains(&instances.color) {
+                        instances.mesh.material =
+                            transparent_material(context, &cpu_material(instances.color));
+                    }
+                }
+            }
+            TransparentObjects::PerTriangle(sorted) => {
+                if sorted.codes.iter().any(|code| codes.contains(code)) {
+                    sorted.recolor(colors, props);
+                    self.sorted_for = None;
+                }
+            }
+        }
+    }
+
+    fn objects(&self) -> Vec<&dyn Object> {
+        self.meshes
+            .iter()
+            .map(|(_, mesh)| mesh as &dyn Object)
+            .chain(
+                self.instanced_meshes
+                    .iter()
+                    .map(|(_, mesh)| mesh as &dyn Object),
+            )
+            .collect()
+    }
+
+    // back to front, call sort_transparent first
+    fn transparent_objects(&self) -> Vec<&dyn Object> {
+        match &self.transparent {
+            TransparentObjects::PerInstance(sorted) => sorted
+                .iter()
+                .map(|instances| &instances.mesh as &dyn Object)
+                .collect(),
+            TransparentObjects::PerTriangle(sorted) => sorted
+                .meshes
+                .iter()
+                .map(|mesh| mesh as &dyn Object)
+                .collect(),
+        }
+    }
+
+    fn sort_transparent(&mut self, eye: Vector3<f32>) {
+        if self.sorted_for == Some(eye) {
+            return;
+        }
+        self.sorted_for = Some(eye);
+
+        match &mut self.transparent {
+            TransparentObjects::PerInstance(sorted) => {
+                for instances in sorted.iter_mut() {
+                    instances.sort(eye);
+                }
+                sorted.sort_by(|a, b| {
+                    b.farthest(eye)
+                        .partial_cmp(&a.farthest(eye))
+                        .unwrap_or(Ordering::Equal)
+                });
+            }
+            TransparentObjects::PerTriangle(sorted) => sorted.sort(eye),
+        }
+    }
+
+    fn geometry_count(&self) -> usize {
+        let transparent = match &self.transparent {
+            TransparentObjects::PerInstance(sorted) => sorted.len(),
+            TransparentObjects::PerTriangle(sorted) => sorted.meshes.len(),
+        };
+        self.meshes.len() + self.instanced_meshes.len() + transparent
+    }
+
+    // transparent geometry does not cast shadows
+    fn geometries(&self) -> Vec<&dyn Geometry> {
+        self.meshes
+            .iter()
+            .map(|(_, mesh)| mesh as &dyn Geometry)
+            .chain(
+                self.instanced_meshes
+                    .iter()
+                    .map(|(_, mesh)| mesh as &dyn Geometry),
+            )
+            .collect()
+    }
+
+    // opaque and transparent, for passes that draw everything with one material
+    fn all_geometries(&self) -> Vec<&dyn Geometry> {
+        let mut geometries = self.geometries();
+        match &self.transparent {
+            TransparentObjects::PerInstance(sorted) => geometries.extend(
+                sorted
+                    .iter()
+                    .map(|instances| &instances.mesh as &dyn Geometry),
+            ),
+            TransparentObjects::PerTriangle(sorted) => {
+                geometries.extend(sorted.meshes.iter().map(|mesh| mesh as &dyn Geometry))
+            }
+        }
+        geometries
     }
 }
 
-// should take some kind of properties as struct
-pub fn render_brick(
-    window: Window,
-    brick: LDrawBrick,
-) -> Box<
-    dyn FnMut(
-        &winit::event::Event<RenderingUserEvent<()>>,
-        &winit::event_loop::EventLoopWindowTarget<RenderingUserEvent<()>>,
-        &mut winit::event_loop::ControlFlow,
-    ),
-> {
-    let context = window.gl();
-
-    let mut camera = Camera::new_perspective(
-        window.viewport(),
-        vec3(60.00, 50.0, 60.0), // camera position
-        vec3(0.0, 0.0, 0.0),     // camera target
-        vec3(0.0, 1.0, 0.0),     // camera up
-        degrees(45.0),
-        0.1,
-        1000.0,
+// boxes for the parts that wait for files, small enough to be built again with every update
+struct Placeholders {
+    objects: SceneObjects,
+    edges: Vec<Gm<InstancedMesh, ColorMaterial>>,
+}
+
+impl Placeholders {
+    fn new(
+        context: &Context,
+        brick: &LDrawBrick,
+        pending: &HashSet<String>,
+        options: &SceneOptions,
+        props: &RenderProps,
+    ) -> Self {
+        let scene = Scene::placeholders(brick, options, pending);
+        let edges = if props.show_edges {
+            edge_objects(context, &scene, &brick.colors, props, &options.frame)
+        } else {
+            Vec::new()
+        };
+        Self {
+            objects: SceneObjects::new(
+                context,
+                &scene,
+                &brick.colors,
+                props,
+                options.transparency_sort,
+            ),
+            edges,
+        }
+    }
+}
+
+// what throws shadows, the placeholders too
+fn shadow_casters<'a>(
+    objects: &'a SceneObjects,
+    placeholders: &'a Placeholders,
+) -> Vec<&'a dyn Geometry> {
+    let mut geometries = objects.geometries();
+    geometries.extend(placeholders.objects.geometries());
+    geometries
+}
+
+// a press and release further apart than this many pixels is a drag, not a click
+const CLICK_TOLERANCE: f32 = 4.0;
+
+// radius of the edge cylinders in LDU
+const EDGE_RADIUS: f32 = 0.25;
+
+fn edge_transformation(from: Vector3<f32>, to: Vector3<f32>, radius: f32) -> Matrix4<f32> {
+    Matrix4::from_translation(from)
+        * Matrix4::from(Quaternion::from_arc(
+            vec3(1.0, 0.0, 0.0),
+            (to - from).normalize(),
+            None,
+        ))
+        * Matrix4::from_nonuniform_scale((to - from).magnitude(), radius, radius)
+}
+
+// type 2 lines drawn as one instanced cylinder per colour
+fn edge_objects(
+    context: &Context,
+    scene: &Scene,
+    colors: &ColorTable,
+    props: &RenderProps,
+    frame: &OutputFrame,
+) -> Vec<Gm<InstancedMesh, ColorMaterial>> {
+    let radius = frame.length(EDGE_RADIUS);
+    let mut transformations: HashMap<u32, Vec<Matrix4<f32>>> = HashMap::new();
+    for instance in scene.instances.iter() {
+        for edges in scene.meshes[instance.mesh].edges.iter() {
+            let target = transformations.entry(edges.color).or_default();
+            for line in edges.positions.chunks_exact(2) {
+                let from = instance.transformation.mul(line[0].extend(1.0)).truncate();
+                let to = instance.transformation.mul(line[1].extend(1.0)).truncate();
+                if from != to {
+                    target.push(edge_transformation(from, to, radius));
+                }
+            }
+        }
+    }
+
+    let cylinder = CpuMesh::cylinder(props.quality.edge_subdivisions());
+    transformations
+        .into_iter()
+        .map(|(code, transformations)| {
+            let [r, g, b, a] = props.resolve_color(colors, code).rgba();
+            Gm::new(
+                InstancedMesh::new(
+                    context,
+                    &Instances {
+                        transformations,
+                        ..Default::default()
+                    },
+                    &cylinder,
+                ),
+                ColorMaterial::new_opaque(
+                    context,
+                    &CpuMaterial {
+                        albedo: Color { r, g, b, a },
+                        ..Default::default()
+                    },
+                ),
+            )
+        })
+        .collect()
+}
+
+// how far the outline reaches past the silhouette of a selected part, in LDU
+const OUTLINE_WIDTH: f32 = 1.5;
+
+const HIGHLIGHT_ALPHA: u8 = 110;
+const DIM_ALPHA: u8 = 170;
+const GHOST_ALPHA: u8 = 36;
+
+// a flat colour drawn over what is already on screen without writing depth
+fn overlay_material(context: &Context, [r, g, b, a]: [u8; 4], cull: Cull) -> ColorMaterial {
+    let mut material = ColorMaterial::new_transparent(
+        context,
+        &CpuMaterial {
+            albedo: Color { r, g, b, a },
+            ..Default::default()
+        },
     );
-    let mut control = OrbitControl::new(vec3(0.0, 0.0, 0.0), 1.0, 1000.0);
+    material.render_states = RenderStates {
+        write_mask: WriteMask::COLOR,
+        depth_test: DepthTest::LessOrEqual,
+        blend: Blend::TRANSPARENCY,
+        cull,
+    };
+    material
+}
 
-    let mut light0 = DirectionalLight::new(&context, 0.5, Color::WHITE, &vec3(0.0, -0.5, -0.5));
-    let light1 = DirectionalLight::new(&context, 0.5, Color::WHITE, &vec3(0.0, 0.5, 0.5));
-    let amb_light = AmbientLight::new(&context, 0.5, Color::WHITE);
+// the selected part instances drawn a second time on top of the scene. Only these are
+// rebuilt when the selection changes, the scene meshes stay as they are
+struct SelectionObjects {
+    // parts baked in their own space, kept between selections
+    parts: HashMap<(String, u32), Option<SceneMesh>>,
+    meshes: Vec<Gm<InstancedMesh, PhysicalMaterial>>,
+    // the selected parts grown past their silhouette, only for SelectionStyle::Outline
+    hulls: Vec<InstancedMesh>,
+}
 
-    // positions array contains all UNIQUE vectors
-    // triangles use indices
+impl SelectionObjects {
+    fn new() -> Self {
+        Self {
+            parts: HashMap::new(),
+            meshes: Vec::new(),
+            hulls: Vec::new(),
+        }
+    }
 
-    // mesh builder function that builds the mesh recursively - for a beginning ignore duplicate portions and ignore colors
+    fn update(
+        &mut self,
+        context: &Context,
+        selection: &Selection,
+        picker: &Picker,
+        brick: &LDrawBrick,
+        props: &RenderProps,
+    ) {
+        self.meshes.clear();
+        self.hulls.clear();
 
-    let mut brick_tri_mesh = generate_brick_mesh(brick);
+        let mut transformations: HashMap<(String, u32), Vec<Matrix4<f32>>> = HashMap::new();
+        for instance in selection.instances() {
+            let part = &picker.parts()[instance];
+            if let Some(transformation) = picker.world_transformation(instance) {
+                transformations
+                    .entry((part.filename.to_string(), part.color))
+                    .or_default()
+                    .push(*transformation);
+            }
+        }
 
-    brick_tri_mesh.compute_normals();
+        for (key, transformations) in transformations {
+            let mesh = self
+                .parts
+                .entry(key.clone())
+                .or_insert_with(|| Scene::part_mesh(brick, &key.0, key.1));
+            let mesh = match mesh {
+                Some(mesh) => mesh,
+                None => continue,
+            };
 
-    let mut brick_mesh = Gm::new(
-        Mesh::new(&context, &brick_tri_mesh),
-        PhysicalMaterial::new(
-            &context,
-            &CpuMaterial {
-                albedo: Color {
-                    r: 0,
-                    g: 0,
-                    b: 255,
-                    a: 255,
-                },
-                ..Default::default()
+            for group in mesh.groups.iter() {
+                let cpu_material =
+                    MaterialParams::from_color(&props.resolve_color(&brick.colors, group.color))
+                        .to_cpu_material();
+                let mut material = if group.transparent {
+                    transparent_material(context, &cpu_material)
+                } else {
+                    PhysicalMaterial::new_opaque(context, &cpu_material)
+                };
+                // drawn where the scene already put the same surface
+                material.render_states.depth_test = DepthTest::LessOrEqual;
+
+                self.meshes.push(Gm::new(
+                    InstancedMesh::new(
+                        context,
+                        &Instances {
+                            transformations: transformations.clone(),
+                            ..Default::default()
+                        },
+                        &cpu_mesh(&group.positions),
+                    ),
+                    material,
+                ));
+            }
+
+            if selection.style == SelectionStyle::Outline {
+                let triangles: Vec<[Vector3<f32>; 3]> = mesh
+                    .groups
+                    .iter()
+                    .flat_map(|group| group.positions.chunks_exact(3))
+                    .map(|corners| [corners[0], corners[1], corners[2]])
+                    .collect();
+                if triangles.is_empty() {
+                    continue;
+                }
+
+                let hull: Vec<Vector3<f32>> = extrude(&triangles, OUTLINE_WIDTH)
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                self.hulls.push(InstancedMesh::new(
+                    context,
+                    &Instances {
+                        transformations,
+                        ..Default::default()
+                    },
+                    &cpu_mesh(&hull),
+                ));
+            }
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.meshes.len() + self.hulls.len()
+    }
+
+    fn geometries(&self) -> Vec<&dyn Geometry> {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh as &dyn Geometry)
+            .collect()
+    }
+}
+
+struct Lights {
+    directional: Vec<DirectionalLight>,
+    ambient: AmbientLight,
+    // whether the key light has a shadow map, none until generate_shadow_map is called
+    shadowed: bool,
+}
+
+impl Lights {
+    fn new(context: &Context, preset: LightingPreset, frame: &OutputFrame) -> Self {
+        let key = frame.direction(vec3(0.0, 0.5, 0.5));
+        let fill = frame.direction(vec3(0.0, -0.5, -0.5));
+        match preset {
+            LightingPreset::Studio => Self {
+                directional: vec![
+                    DirectionalLight::new(context, 0.5, Color::WHITE, &key),
+                    DirectionalLight::new(context, 0.5, Color::WHITE, &fill),
+                ],
+                ambient: AmbientLight::new(context, 0.5, Color::WHITE),
+                shadowed: false,
             },
-        ),
-    );
+            LightingPreset::Soft => Self {
+                directional: vec![DirectionalLight::new(context, 0.3, Color::WHITE, &key)],
+                ambient: AmbientLight::new(context, 0.8, Color::WHITE),
+                shadowed: false,
+            },
+            LightingPreset::Flat => Self {
+                directional: Vec::new(),
+                ambient: AmbientLight::new(context, 1.0, Color::WHITE),
+                shadowed: false,
+            },
+        }
+    }
 
-    light0.generate_shadow_map(1024, brick_mesh.into_iter());
+    // only the key light casts shadows, every preset that has one gets them unless the
+    // quality has no shadow map size
+    fn generate_shadow_map(&mut self, quality: Quality, geometries: Vec<&dyn Geometry>) {
+        self.shadowed = false;
+        if let Some(key) = self.directional.first_mut() {
+            match quality.shadow_map_size() {
+                Some(size) => {
+                    key.generate_shadow_map(size, geometries);
+                    self.shadowed = true;
+                }
+                None => key.clear_shadow_map(),
+            }
+        }
+    }
+
+    fn shadow_maps(&self) -> usize {
+        self.shadowed as usize
+    }
 
-    // brick_mesh.material.render_states.cull = Cull::Back;
+    fn all(&self) -> Vec<&dyn Light> {
+        self.directional
+            .iter()
+            .map(|light| light as &dyn Light)
+            .chain(std::iter::once(&self.ambient as &dyn Light))
+            .collect()
+    }
+}
 
-    let mut red: u8 = 0;
+// everything a window owns on the GPU, dropped as a whole when the window is disposed
+struct WindowState {
+    context: Context,
+    brick: LDrawBrick,
+    // files that have not arrived yet
+    pending: HashSet<String>,
+    options: SceneOptions,
+    frame: OutputFrame,
+    bounds: Aabb,
+    props: RenderProps,
+    // the parts that are complete, extended as files arrive
+    scene: Scene,
+    scene_objects: SceneObjects,
+    edges: Vec<Gm<InstancedMesh, ColorMaterial>>,
+    placeholders: Placeholders,
+    lights: Lights,
+    camera: Camera,
+    camera_setup: CameraSetup,
+    control: OrbitControl,
+    progress: Progress,
+    rendered: bool,
+    picker: Picker,
+    picks: PickListeners,
+    pressed_at: Option<(f32, f32)>,
+    hovered: Option<usize>,
+    selection: Selection,
+    selection_objects: SelectionObjects,
+    // drawn after the next frame
+    captures: Vec<CaptureRequest>,
+    _live: ResourceCounter,
+    geometries: ResourceCounter,
+}
+
+impl WindowState {
+    fn new(
+        window: &Window,
+        loaded: LoadedBrick,
+        options: SceneOptions,
+        props: RenderProps,
+        progress: Progress,
+        picks: PickListeners,
+    ) -> Self {
+        let context = window.gl();
+        let frame = options.frame;
+        let LoadedBrick {
+            brick,
+            scene,
+            pending,
+        } = loaded;
+
+        progress.emit(LoadEvent::Stage(BuildStage::Bounds));
+        let bounds = loading_bounds(&brick, &pending, frame);
+        let camera_setup = CameraSetup::preset(
+            &bounds,
+            ViewPreset::LDraw,
+            props.projection,
+            &frame,
+            window.viewport().aspect(),
+        );
+        let mut camera = Camera::new_perspective(
+            window.viewport(),
+            camera_setup.position,
+            camera_setup.target,
+            camera_setup.up,
+            degrees(camera_setup.field_of_view),
+            camera_setup.z_near,
+            camera_setup.z_far,
+        );
+        camera_setup.apply(&mut camera);
+        let control = camera_setup.orbit_control();
+
+        let scene = scene.unwrap_or_else(|| {
+            progress.emit(LoadEvent::Stage(BuildStage::Scene));
+            Scene::build_loaded(&brick, &options, &pending)
+        });
+
+        progress.emit(LoadEvent::Stage(BuildStage::Meshes));
+        let scene_objects = SceneObjects::new(
+            &context,
+            &scene,
+            &brick.colors,
+            &props,
+            options.transparency_sort,
+        );
+        let edges = if props.show_edges {
+            progress.emit(LoadEvent::Stage(BuildStage::Edges));
+            edge_objects(&context, &scene, &brick.colors, &props, &frame)
+        } else {
+            Vec::new()
+        };
+
+        let placeholders = Placeholders::new(&context, &brick, &pending, &options, &props);
+
+        progress.emit(LoadEvent::Stage(BuildStage::Shadows));
+        let mut lights = Lights::new(&context, props.lighting, &frame);
+        lights.generate_shadow_map(props.quality, shadow_casters(&scene_objects, &placeholders));
+
+        progress.emit(LoadEvent::Stage(BuildStage::Picking));
+        let picker = Picker::new(&brick, &frame);
+
+        let mut state = Self {
+            context,
+            brick,
+            pending,
+            options,
+            frame,
+            bounds,
+            props,
+            scene,
+            scene_objects,
+            edges,
+            placeholders,
+            lights,
+            camera,
+            camera_setup,
+            control,
+            progress,
+            rendered: false,
+            picker,
+            picks,
+            pressed_at: None,
+            hovered: None,
+            selection: Selection::new(),
+            selection_objects: SelectionObjects::new(),
+            captures: Vec::new(),
+            _live: ResourceCounter::new(&LIVE_WINDOWS, 1),
+            geometries: ResourceCounter::new(&FRAME_GEOMETRIES, 0),
+        };
+        state.count_geometries();
+        state
+    }
+
+    fn count_geometries(&mut self) {
+        let count = self.scene_objects.geometry_count()
+            + self.edges.len()
+            + self.placeholders.objects.geometry_count()
+            + self.placeholders.edges.len()
+            + self.lights.shadow_maps()
+            + self.selection_objects.count();
+        self.geometries.set(count);
+    }
+
+    // swaps the placeholders of the parts that are complete now for their geometry, only
+    // that is uploaded. The camera stays where it is
+    fn update_brick(&mut self, update: BrickUpdate) {
+        let defined: Vec<u32> = update.colors.iter().map(|color| color.code).collect();
+        let before = std::mem::replace(&mut self.pending, update.apply(&mut self.brick));
+        if !defined.is_empty() {
+            self.scene_objects
+                .recolor(&self.context, &self.brick.colors, &self.props, &defined);
+        }
+
+        let arrived = Scene::build_arrived(&self.brick, &self.options, &before, &self.pending);
+        self.scene_objects.extend(SceneObjects::new(
+            &self.context,
+            &arrived,
+            &self.brick.colors,
+            &self.props,
+            self.options.transparency_sort,
+        ));
+        if self.props.show_edges {
+            self.edges.extend(edge_objects(
+                &self.context,
+                &arrived,
+                &self.brick.colors,
+                &self.props,
+                &self.frame,
+            ));
+        }
+        let changed: HashSet<String> = arrived
+            .meshes
+            .iter()
+            .map(|mesh| mesh.key.filename.to_string())
+            .collect();
+        self.scene.extend(arrived);
+        self.placeholders = Placeholders::new(
+            &self.context,
+            &self.brick,
+            &self.pending,
+            &self.options,
+            &self.props,
+        );
+        self.picker.update(&self.brick, &self.frame, &changed);
+
+        // these take the whole model, so they wait until it is complete
+        if self.pending.is_empty() {
+            self.lights.generate_shadow_map(
+                self.props.quality,
+                shadow_casters(&self.scene_objects, &self.placeholders),
+            );
+            self.bounds = loading_bounds(&self.brick, &self.pending, self.frame);
+        }
+        if !self.selection.is_empty() {
+            self.update_selection();
+        }
+        self.count_geometries();
+    }
+
+    fn pick_at(&self, position: (f32, f32)) -> Option<PickResult> {
+        let ray = Ray::new(
+            self.camera.position_at_pixel(position),
+            self.camera.view_direction_at_pixel(position),
+        );
+        self.picker
+            .cast(&ray)
+            .map(|hit| PickResult::new(&hit, &self.brick))
+    }
+
+    fn update_selection(&mut self) {
+        self.selection_objects.update(
+            &self.context,
+            &self.selection,
+            &self.picker,
+            &self.brick,
+            &self.props,
+        );
+    }
 
-    let inner_callback: Box<
-        dyn FnMut(
-            &winit::event::Event<RenderingUserEvent<()>>,
-            &winit::event_loop::EventLoopWindowTarget<RenderingUserEvent<()>>,
-            &mut winit::event_loop::ControlFlow,
-        ),
-    > = Box::new(
-        window.get_render_loop::<RenderingUserEvent<()>, _>(move |mut frame_input| {
-            for event in frame_input.events.iter_mut() {
-                match event {
-                    Event::UserEvent(RenderingUserEvent::InternalUpdateProps(value)) => {
-                        red = *value
+    fn hover(&mut self, result: Option<PickResult>) {
+        let hovered = result.as_ref().map(|result| result.instance);
+        if hovered != self.hovered {
+            self.hovered = hovered;
+            self.picks.hover(result);
+        }
+    }
+
+    fn handle_event(&mut self, event: &Event<RenderingUserEvent<()>>, viewport: Viewport) {
+        match event {
+            Event::MousePress {
+                button: MouseButton::Left,
+                position,
+                ..
+            } => self.pressed_at = Some(*position),
+            Event::MouseRelease {
+                button: MouseButton::Left,
+                position,
+                ..
+            } => {
+                if let Some(pressed_at) = self.pressed_at.take() {
+                    let moved = (position.0 - pressed_at.0).hypot(position.1 - pressed_at.1);
+                    if moved <= CLICK_TOLERANCE {
+                        self.picks.click(self.pick_at(*position));
                     }
-                    _ => {}
                 }
             }
+            Event::MouseMotion {
+                button: None,
+                position,
+                ..
+            } if self.picks.wants_hover() => {
+                let result = self.pick_at(*position);
+                self.hover(result);
+            }
+            Event::MouseLeave => self.hover(None),
+            Event::UserEvent(RenderingUserEvent::InternalUpdateProps(_, new_props)) => {
+                let diff = self.props.diff(new_props);
+                self.props = new_props.clone();
 
-            let viewport = Viewport {
-                x: 0,
-                y: 0,
-                width: frame_input.viewport.width,
-                height: frame_input.viewport.height,
-            };
-            camera.set_viewport(viewport);
-
-            // Camera control must be after the gui update.
-            control.handle_events(&mut camera, &mut frame_input.events);
+                if diff.projection {
+                    self.camera_setup.sync(&self.camera);
+                    self.camera_setup = self.camera_setup.with_projection(self.props.projection);
+                    self.camera_setup.apply(&mut self.camera);
+                }
+                if !diff.colors.is_empty() {
+                    self.scene_objects.recolor(
+                        &self.context,
+                        &self.brick.colors,
+                        &self.props,
+                        &diff.colors,
+                    );
+                }
+                if diff.edges || diff.quality || !diff.colors.is_empty() {
+                    self.edges = if self.props.show_edges {
+                        edge_objects(
+                            &self.context,
+                            &self.scene,
+                            &self.brick.colors,
+                            &self.props,
+                            &self.frame,
+                        )
+                    } else {
+                        Vec::new()
+                    };
+                    self.placeholders = Placeholders::new(
+                        &self.context,
+                        &self.brick,
+                        &self.pending,
+                        &self.options,
+                        &self.props,
+                    );
+                }
+                if !diff.colors.is_empty() && !self.selection.is_empty() {
+                    self.update_selection();
+                }
+                if diff.lighting {
+                    self.lights = Lights::new(&self.context, self.props.lighting, &self.frame);
+                }
+                if diff.lighting || diff.quality {
+                    self.lights.generate_shadow_map(
+                        self.props.quality,
+                        shadow_casters(&self.scene_objects, &self.placeholders),
+                    );
+                }
+            }
+            Event::UserEvent(RenderingUserEvent::InternalSetView(_, preset)) => {
+                self.camera_setup = CameraSetup::preset(
+                    &self.bounds,
+                    *preset,
+                    self.camera_setup.projection,
+                    &self.frame,
+                    viewport.aspect(),
+                );
+                self.camera_setup.apply(&mut self.camera);
+                self.control = self.camera_setup.orbit_control();
+            }
+            Event::UserEvent(RenderingUserEvent::InternalSelection(_, command)) => {
+                let changed = self
+                    .selection
+                    .apply(command, self.picker.parts(), &self.brick);
+                if changed {
+                    self.update_selection();
+                }
+            }
+            Event::UserEvent(RenderingUserEvent::InternalCapture(_, request)) => {
+                self.captures.push(request.clone());
+            }
+            Event::UserEvent(RenderingUserEvent::InternalUpdateBrick(_, update)) => {
+                self.update_brick(update.clone());
+            }
+            Event::UserEvent(RenderingUserEvent::InternalFitToBounds(_)) => {
+                self.camera_setup.sync(&self.camera);
+                self.camera_setup = CameraSetup::fit(
+                    &self.bounds,
+                    self.camera_setup.direction(),
+                    self.camera_setup.up,
+                    self.camera_setup.projection,
+                    viewport.aspect(),
+                );
+                self.camera_setup.apply(&mut self.camera);
+                self.control = self.camera_setup.orbit_control();
+            }
+            _ => {}
+        }
+    }
 
-            brick_mesh.material.albedo = Color {
-                r: red,
-                g: 128,
-                b: 128,
-                a: 255,
-            };
+    // the scene and the placeholders, opaque and transparent
+    fn all_geometries(&self) -> Vec<&dyn Geometry> {
+        let mut geometries = self.scene_objects.all_geometries();
+        geometries.extend(self.placeholders.objects.all_geometries());
+        geometries
+    }
 
-            // Then, based on whether or not we render the instanced brick_meshs, collect the renderable
-            // objects.
+    // the scene and the selection on top of it, cleared to the given colour first
+    fn draw(&self, target: &RenderTarget, camera: &Camera, clear: [u8; 4]) {
+        let lights = self.lights.all();
+        let [r, g, b, a] = clear;
+        target.clear(ClearState::color_and_depth(
+            r as f32 / 255.0,
+            g as f32 / 255.0,
+            b as f32 / 255.0,
+            a as f32 / 255.0,
+            1.0,
+        ));
 
-            frame_input
-                .screen()
-                .clear(ClearState::color_and_depth(0.8, 0.8, 0.8, 1.0, 1.0))
-                .render(
-                    &camera,
-                    brick_mesh.into_iter(),
-                    &[&light0, &light1, &amb_light],
+        let selected = &self.selection_objects;
+        let unselected = if self.selection.is_empty() {
+            UnselectedStyle::Normal
+        } else {
+            self.selection.unselected
+        };
+        if unselected == UnselectedStyle::Ghost {
+            // the selection first, so the shells around it don't cover it
+            target
+                .render(camera, &selected.meshes, &lights)
+                .render_with_material(
+                    &overlay_material(&self.context, [128, 128, 128, GHOST_ALPHA], Cull::None),
+                    camera,
+                    self.all_geometries(),
+                    &lights,
                 );
+        } else {
+            target
+                .render(camera, self.scene_objects.objects(), &lights)
+                .render(camera, self.placeholders.objects.objects(), &lights)
+                .render(camera, &self.edges, &lights)
+                .render(camera, &self.placeholders.edges, &lights)
+                .render(camera, self.scene_objects.transparent_objects(), &lights);
+            if unselected == UnselectedStyle::Dim {
+                let dim = self.props.background();
+                let mut geometries = self.all_geometries();
+                geometries.extend(
+                    self.edges
+                        .iter()
+                        .chain(self.placeholders.edges.iter())
+                        .map(|edges| edges as &dyn Geometry),
+                );
+                target
+                    .render_with_material(
+                        &overlay_material(
+                            &self.context,
+                            [dim[0], dim[1], dim[2], DIM_ALPHA],
+                            Cull::None,
+                        ),
+                        camera,
+                        geometries,
+                        &lights,
+                    )
+                    .render(camera, &selected.meshes, &lights);
+            }
+        }
 
-            FrameOutput::default()
-        }),
-    );
-    inner_callback
+        if !self.selection.is_empty() {
+            let [r, g, b] = self.selection.color;
+            match self.selection.style {
+                SelectionStyle::Highlight => {
+                    target.render_with_material(
+                        &overlay_material(&self.context, [r, g, b, HIGHLIGHT_ALPHA], Cull::None),
+                        camera,
+                        selected.geometries(),
+                        &lights,
+                    );
+                }
+                SelectionStyle::Outline => {
+                    // the hulls are the parts pushed out along their normals, drawing the
+                    // parts again over them leaves only the rim past the silhouette
+                    target
+                        .render_with_material(
+                            &overlay_material(&self.context, [r, g, b, 255], Cull::None),
+                            camera,
+                            &selected.hulls,
+                            &lights,
+                        )
+                        .render(camera, &selected.meshes, &lights);
+                }
+            }
+        }
+    }
+
+    fn capture(&self, request: &CaptureRequest) -> Result<Vec<u8>, String> {
+        let samples = request.options.samples(request.width, request.height);
+        let (width, height) = (request.width * samples, request.height * samples);
+
+        let mut color = Texture2D::new_empty::<[u8; 4]>(
+            &self.context,
+            width,
+            height,
+            Interpolation::Nearest,
+            Interpolation::Nearest,
+            None,
+            Wrapping::ClampToEdge,
+            Wrapping::ClampToEdge,
+        );
+        let mut depth = DepthTexture2D::new::<f32>(
+            &self.context,
+            width,
+            height,
+            Wrapping::ClampToEdge,
+            Wrapping::ClampToEdge,
+        );
+
+        // same view, only the aspect ratio follows the requested size
+        let mut camera = self.camera.clone();
+        camera.set_viewport(Viewport {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        });
+        let clear = if request.options.transparent {
+            [0, 0, 0, 0]
+        } else {
+            self.props.background()
+        };
+
+        let target = RenderTarget::new(color.as_color_target(None), depth.as_depth_target());
+        self.draw(&target, &camera, clear);
+        let pixels = target.read_color::<[u8; 4]>();
+        encode_png(
+            &downsample(&pixels, width, height, samples),
+            request.width,
+            request.height,
+        )
+    }
+
+    fn render(&mut self, mut frame_input: FrameInput<RenderingUserEvent<()>>) -> FrameOutput {
+        let viewport = Viewport {
+            x: 0,
+            y: 0,
+            width: frame_input.viewport.width,
+            height: frame_input.viewport.height,
+        };
+        self.camera.set_viewport(viewport);
+
+        for event in frame_input.events.iter() {
+            self.handle_event(event, viewport);
+        }
+
+        // Camera control must be after the gui update.
+        self.control
+            .handle_events(&mut self.camera, &mut frame_input.events);
+
+        self.scene_objects.sort_transparent(*self.camera.position());
+        self.count_geometries();
+
+        let screen = frame_input.screen();
+        self.draw(&screen, &self.camera, self.props.background());
+        for request in std::mem::take(&mut self.captures) {
+            match self.capture(&request) {
+                Ok(png) => request.resolve(&png),
+                Err(message) => request.reject(&message),
+            }
+        }
+
+        if !self.rendered {
+            self.rendered = true;
+            self.progress.emit(LoadEvent::FirstFrame);
+        }
+
+        FrameOutput::default()
+    }
+}
+
+pub fn render_brick(
+    window: Window,
+    canvas: HtmlCanvasElement,
+    loaded: LoadedBrick,
+    options: SceneOptions,
+    props: RenderProps,
+    progress: Progress,
+    picks: PickListeners,
+) -> RenderingWindow<()> {
+    let window_id = window.winit_window().id();
+    let state = Rc::new(RefCell::new(Some(WindowState::new(
+        &window, loaded, options, props, progress, picks,
+    ))));
+
+    let render_state = state.clone();
+    let handler = Box::new(window.get_render_loop::<RenderingUserEvent<()>, _>(
+        move |frame_input| match render_state.borrow_mut().as_mut() {
+            Some(state) => state.render(frame_input),
+            None => FrameOutput {
+                wait_next_event: true,
+                ..Default::default()
+            },
+        },
+    ));
+
+    // runs before the handler, and with it the three-d window and its listeners, is dropped
+    let teardown = Box::new(move || {
+        if let Some(state) = state.borrow_mut().take() {
+            for request in state.captures.iter() {
+                request.reject("the window was deleted");
+            }
+        }
+        // resizing the canvas clears whatever was drawn last
+        canvas.set_width(canvas.width());
+    });
+
+    RenderingWindow {
+        window_id,
+        handler,
+        teardown,
+    }
 }
@@ -0,0 +1,838 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Mul;
+
+use three_d::{Matrix, Matrix4, SquareMatrix, Vector3};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    bounds::Aabb,
+    coordinates::OutputFrame,
+    parser::{
+        colors::{direct_color, MAIN_COLOR},
+        part::{LDrawBrick, LDrawFile, LDrawSubfile},
+        tokenizer::BFCDirection,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MeshKey {
+    pub filename: String,
+    pub color: u32,
+    pub winding: BFCDirection,
+}
+
+#[derive(Debug, Clone)]
+pub struct SceneGroup {
+    pub color: u32,
+    pub transparent: bool,
+    // three vertices per triangle
+    pub positions: Vec<Vector3<f32>>,
+}
+
+impl SceneGroup {
+    pub fn triangle_count(&self) -> usize {
+        self.positions.len() / 3
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SceneEdges {
+    pub color: u32,
+    // two vertices per line
+    pub positions: Vec<Vector3<f32>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SceneMesh {
+    pub key: MeshKey,
+    // one group per resolved colour
+    pub groups: Vec<SceneGroup>,
+    pub edges: Vec<SceneEdges>,
+}
+
+impl SceneMesh {
+    fn group_mut(&mut self, color: u32, transparent: bool) -> &mut SceneGroup {
+        let index = match self.groups.iter().position(|group| group.color == color) {
+            Some(index) => index,
+            None => {
+                self.groups.push(SceneGroup {
+                    color,
+                    transparent,
+                    positions: Vec::new(),
+                });
+                self.groups.len() - 1
+            }
+        };
+        &mut self.groups[index]
+    }
+
+    fn edges_mut(&mut self, color: u32) -> &mut SceneEdges {
+        let index = match self.edges.iter().position(|edges| edges.color == color) {
+            Some(index) => index,
+            None => {
+                self.edges.push(SceneEdges {
+                    color,
+                    positions: Vec::new(),
+                });
+                self.edges.len() - 1
+            }
+        };
+        &mut self.edges[index]
+    }
+
+    pub fn opaque_groups(&self) -> impl Iterator<Item = &SceneGroup> {
+        self.groups.iter().filter(|group| !group.transparent)
+    }
+
+    pub fn transparent_groups(&self) -> impl Iterator<Item = &SceneGroup> {
+        self.groups.iter().filter(|group| group.transparent)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.groups.iter().map(|group| group.triangle_count()).sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SceneInstance {
+    pub mesh: usize,
+    pub transformation: Matrix4<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartInstance {
+    pub filename: String,
+    pub color: u32,
+    // the file and line that placed the part, none for a part loaded on its own
+    pub source: Option<(String, usize)>,
+    // in LDraw space, relative to the entry file
+    pub transformation: Matrix4<f32>,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransparencySort {
+    // transparent meshes stay instanced and their instances are sorted
+    PerInstance = 0,
+    // all transparent triangles are merged in world space and sorted individually
+    PerTriangle = 1,
+}
+
+#[derive(Debug, Clone)]
+pub struct SceneOptions {
+    // a (file, colour) pair that is referenced at least this often is kept as a
+    // separate mesh and drawn instanced, everything else is baked into its parent
+    pub instance_threshold: usize,
+    pub frame: OutputFrame,
+    pub transparency_sort: TransparencySort,
+}
+
+impl Default for SceneOptions {
+    fn default() -> Self {
+        Self {
+            instance_threshold: 8,
+            frame: OutputFrame::default(),
+            transparency_sort: TransparencySort::PerInstance,
+        }
+    }
+}
+
+// stands in for a part until all of its files have arrived, a 1x1 brick in light bluish
+// grey with dark edges
+pub const PLACEHOLDER_MIN: [f32; 3] = [-10.0, -4.0, -10.0];
+pub const PLACEHOLDER_MAX: [f32; 3] = [10.0, 24.0, 10.0];
+pub const PLACEHOLDER_COLOR: u32 = direct_color([0xa0, 0xa5, 0xa9]);
+pub const PLACEHOLDER_EDGE_COLOR: u32 = direct_color([0x33, 0x33, 0x33]);
+
+pub fn placeholder_box() -> Aabb {
+    Aabb {
+        min: PLACEHOLDER_MIN.into(),
+        max: PLACEHOLDER_MAX.into(),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Scene {
+    pub meshes: Vec<SceneMesh>,
+    pub instances: Vec<SceneInstance>,
+    // world space transparent triangles, only filled for TransparencySort::PerTriangle
+    pub transparent: Vec<SceneGroup>,
+}
+
+impl Scene {
+    pub fn build(brick: &LDrawBrick, options: &SceneOptions) -> Scene {
+        Self::build_partial(brick, options, &HashSet::new())
+    }
+
+    // while files are still arriving, parts that wait for any of the pending files are
+    // drawn as placeholder boxes instead of half their geometry
+    pub fn build_partial(
+        brick: &LDrawBrick,
+        options: &SceneOptions,
+        pending: &HashSet<String>,
+    ) -> Scene {
+        let mut scene = Self::build_loaded(brick, options, pending);
+        scene.extend(Self::placeholders(brick, options, pending));
+        scene
+    }
+
+    // the parts that are complete, without placeholders for the others
+    pub fn build_loaded(
+        brick: &LDrawBrick,
+        options: &SceneOptions,
+        pending: &HashSet<String>,
+    ) -> Scene {
+        let entry_file = brick.files.get(&brick.entry_file).unwrap();
+
+        let mut builder = SceneBuilder {
+            brick,
+            options,
+            counts: HashMap::new(),
+            lookup: HashMap::new(),
+            scene: Scene::default(),
+            descend: true,
+            waiting: Waiting::new(pending),
+        };
+        builder.count(entry_file, MAIN_COLOR, &entry_file.bfc_direction);
+
+        let root = builder.add_mesh(MeshKey {
+            filename: brick.entry_file.to_string(),
+            color: MAIN_COLOR,
+            winding: entry_file.bfc_direction.clone(),
+        });
+        let waiting_entry =
+            entry_file.is_part() && builder.waiting.contains(brick, &brick.entry_file);
+        if !waiting_entry {
+            builder.bake(
+                entry_file,
+                MAIN_COLOR,
+                Matrix4::identity(),
+                &entry_file.bfc_direction,
+                root,
+                true,
+            );
+        }
+        builder.scene.instances.insert(
+            0,
+            SceneInstance {
+                mesh: root,
+                transformation: Matrix4::identity(),
+            },
+        );
+
+        builder.scene.framed(options)
+    }
+
+    // a placeholder box for every part that waits for one of the pending files
+    pub fn placeholders(
+        brick: &LDrawBrick,
+        options: &SceneOptions,
+        pending: &HashSet<String>,
+    ) -> Scene {
+        let mut scene = Scene::default();
+        if !pending.is_empty() {
+            scene.add_placeholders(&pending_parts(brick, pending));
+        }
+        scene.framed(options)
+    }
+
+    // the parts that waited for one of the files in `before` and are complete with only
+    // `after` pending, one instanced mesh per (file, colour) pair. A scene built with
+    // `before` pending is up to date again once it is extended with these
+    pub fn build_arrived(
+        brick: &LDrawBrick,
+        options: &SceneOptions,
+        before: &HashSet<String>,
+        after: &HashSet<String>,
+    ) -> Scene {
+        let entry_file = brick.files.get(&brick.entry_file).unwrap();
+        let mut builder = SceneBuilder {
+            brick,
+            options,
+            counts: HashMap::new(),
+            lookup: HashMap::new(),
+            scene: Scene::default(),
+            descend: true,
+            waiting: Waiting::new(after),
+        };
+        let mut waited = Waiting::new(before);
+
+        if entry_file.is_part() {
+            let entry = &brick.entry_file;
+            if waited.contains(brick, entry) && !builder.waiting.contains(brick, entry) {
+                let key = MeshKey {
+                    filename: entry.to_string(),
+                    color: MAIN_COLOR,
+                    winding: entry_file.bfc_direction.clone(),
+                };
+                let mesh = builder.instanced_mesh(&key, entry_file);
+                builder.scene.instances.push(SceneInstance {
+                    mesh,
+                    transformation: Matrix4::identity(),
+                });
+            }
+        } else {
+            builder.arrive(
+                entry_file,
+                MAIN_COLOR,
+                Matrix4::identity(),
+                &entry_file.bfc_direction,
+                &mut waited,
+                false,
+            );
+        }
+
+        builder.scene.framed(options)
+    }
+
+    // the meshes and instances of a scene built with the same options are added to these
+    pub fn extend(&mut self, other: Scene) {
+        let offset = self.meshes.len();
+        self.meshes.extend(other.meshes);
+        self.instances
+            .extend(other.instances.into_iter().map(|instance| SceneInstance {
+                mesh: instance.mesh + offset,
+                ..instance
+            }));
+        for group in other.transparent {
+            match self
+                .transparent
+                .iter_mut()
+                .find(|existing| existing.color == group.color)
+            {
+                Some(existing) => existing.positions.extend(group.positions),
+                None => self.transparent.push(group),
+            }
+        }
+    }
+
+    // a scene built in LDraw space, like the one stored in a baked brick, placed in the
+    // output frame and sorted the way the options ask
+    pub fn framed(mut self, options: &SceneOptions) -> Scene {
+        let root_transformation = options.frame.matrix();
+        for instance in self.instances.iter_mut() {
+            instance.transformation = root_transformation.mul(instance.transformation);
+        }
+        if options.transparency_sort == TransparencySort::PerTriangle {
+            self.merge_transparent();
+        }
+        self
+    }
+
+    // a single part baked on its own in LDraw part space, nothing is instanced
+    pub fn part_mesh(brick: &LDrawBrick, filename: &str, color: u32) -> Option<SceneMesh> {
+        Self::file_mesh(brick, filename, color, true)
+    }
+
+    // only the geometry of the file itself, without its subfile references
+    pub fn local_mesh(brick: &LDrawBrick, filename: &str, color: u32) -> Option<SceneMesh> {
+        Self::file_mesh(brick, filename, color, false)
+    }
+
+    fn file_mesh(
+        brick: &LDrawBrick,
+        filename: &str,
+        color: u32,
+        descend: bool,
+    ) -> Option<SceneMesh> {
+        let file = brick.files.get(filename)?;
+        let options = SceneOptions::default();
+        let pending = HashSet::new();
+        let mut builder = SceneBuilder {
+            brick,
+            options: &options,
+            counts: HashMap::new(),
+            lookup: HashMap::new(),
+            scene: Scene::default(),
+            descend,
+            waiting: Waiting::new(&pending),
+        };
+        let mesh = builder.add_mesh(MeshKey {
+            filename: filename.to_string(),
+            color,
+            winding: file.bfc_direction.clone(),
+        });
+        builder.bake(
+            file,
+            color,
+            Matrix4::identity(),
+            &file.bfc_direction,
+            mesh,
+            false,
+        );
+        builder.scene.meshes.pop()
+    }
+
+    // the axis aligned box around every instance, in the output frame
+    pub fn aabb(&self) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for instance in self.instances.iter() {
+            for group in self.meshes[instance.mesh].groups.iter() {
+                for position in group.positions.iter() {
+                    aabb.extend(&instance.transformation.mul(position.extend(1.0)).truncate());
+                }
+            }
+        }
+        for group in self.transparent.iter() {
+            for position in group.positions.iter() {
+                aabb.extend(position);
+            }
+        }
+        aabb
+    }
+
+    fn add_placeholders(&mut self, parts: &[PartInstance]) {
+        if parts.is_empty() {
+            return;
+        }
+
+        let corners = placeholder_box().corners();
+        // corners are numbered by their bits, x is 1, y is 2 and z is 4
+        const FACES: [[usize; 4]; 6] = [
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+        ];
+        const EDGES: [[usize; 2]; 12] = [
+            [0, 1],
+            [2, 3],
+            [4, 5],
+            [6, 7],
+            [0, 2],
+            [1, 3],
+            [4, 6],
+            [5, 7],
+            [0, 4],
+            [1, 5],
+            [2, 6],
+            [3, 7],
+        ];
+        let mut positions = Vec::new();
+        for [a, b, c, d] in FACES {
+            positions.extend([corners[a], corners[b], corners[c]]);
+            positions.extend([corners[a], corners[c], corners[d]]);
+        }
+
+        let mesh = self.meshes.len();
+        self.meshes.push(SceneMesh {
+            key: MeshKey {
+                filename: String::new(),
+                color: PLACEHOLDER_COLOR,
+                winding: BFCDirection::CCW,
+            },
+            groups: vec![SceneGroup {
+                color: PLACEHOLDER_COLOR,
+                transparent: false,
+                positions,
+            }],
+            edges: vec![SceneEdges {
+                color: PLACEHOLDER_EDGE_COLOR,
+                positions: EDGES
+                    .iter()
+                    .flat_map(|[a, b]| [corners[*a], corners[*b]])
+                    .collect(),
+            }],
+        });
+        for part in parts {
+            self.instances.push(SceneInstance {
+                mesh,
+                transformation: part.transformation,
+            });
+        }
+    }
+
+    fn merge_transparent(&mut self) {
+        let mut merged = SceneMesh {
+            key: MeshKey {
+                filename: String::new(),
+                color: MAIN_COLOR,
+                winding: BFCDirection::CCW,
+            },
+            groups: Vec::new(),
+            edges: Vec::new(),
+        };
+
+        for instance in self.instances.iter() {
+            for group in self.meshes[instance.mesh].transparent_groups() {
+                merged.group_mut(group.color, true).positions.extend(
+                    group.positions.iter().map(|position| {
+                        instance.transformation.mul(position.extend(1.0)).truncate()
+                    }),
+                );
+            }
+        }
+        for mesh in self.meshes.iter_mut() {
+            mesh.groups.retain(|group| !group.transparent);
+        }
+
+        self.transparent = merged.groups;
+    }
+
+    pub fn instances_of(&self, mesh: usize) -> impl Iterator<Item = &SceneInstance> {
+        self.instances
+            .iter()
+            .filter(move |instance| instance.mesh == mesh)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        let transparent: usize = self
+            .transparent
+            .iter()
+            .map(|group| group.triangle_count())
+            .sum();
+        self.instances
+            .iter()
+            .map(|instance| self.meshes[instance.mesh].triangle_count())
+            .sum::<usize>()
+            + transparent
+    }
+}
+
+pub fn part_instances(brick: &LDrawBrick) -> Vec<PartInstance> {
+    let entry_file = brick.files.get(&brick.entry_file).unwrap();
+    let mut parts = Vec::new();
+
+    if entry_file.is_part() {
+        parts.push(PartInstance {
+            filename: brick.entry_file.to_string(),
+            color: MAIN_COLOR,
+            source: None,
+            transformation: Matrix4::identity(),
+        });
+    } else {
+        collect_part_instances(
+            brick,
+            entry_file,
+            MAIN_COLOR,
+            Matrix4::identity(),
+            &mut parts,
+        );
+    }
+
+    parts
+}
+
+fn collect_part_instances(
+    brick: &LDrawBrick,
+    file: &LDrawFile,
+    color: u32,
+    matrix: Matrix4<f32>,
+    parts: &mut Vec<PartInstance>,
+) {
+    for subfile in file.subfiles.iter() {
+        let transformation = matrix.mul(subfile_matrix(subfile));
+        let subfile_color = brick.colors.resolve_code(subfile.color.value, color);
+
+        match brick.files.get(&subfile.filename) {
+            Some(child) if !child.is_part() => {
+                collect_part_instances(brick, child, subfile_color, transformation, parts)
+            }
+            _ => parts.push(PartInstance {
+                filename: subfile.filename.to_string(),
+                color: subfile_color,
+                source: Some((file.name.to_string(), subfile.line)),
+                transformation,
+            }),
+        }
+    }
+}
+
+// the part instances that still wait for one of the pending files
+pub fn pending_parts(brick: &LDrawBrick, pending: &HashSet<String>) -> Vec<PartInstance> {
+    let mut waiting = Waiting::new(pending);
+    part_instances(brick)
+        .into_iter()
+        .filter(|part| waiting.contains(brick, &part.filename))
+        .collect()
+}
+
+// whether a file or anything it references is still pending, remembered per file
+struct Waiting<'a> {
+    pending: &'a HashSet<String>,
+    files: HashMap<String, bool>,
+}
+
+impl<'a> Waiting<'a> {
+    fn new(pending: &'a HashSet<String>) -> Self {
+        Self {
+            pending,
+            files: HashMap::new(),
+        }
+    }
+
+    fn contains(&mut self, brick: &LDrawBrick, name: &str) -> bool {
+        if self.pending.is_empty() {
+            return false;
+        }
+        if let Some(waiting) = self.files.get(name) {
+            return *waiting;
+        }
+
+        let waiting = self.pending.contains(name)
+            || brick.files.get(name).is_some_and(|file| {
+                file.subfiles
+                    .iter()
+                    .any(|subfile| self.contains(brick, &subfile.filename))
+            });
+        self.files.insert(name.to_string(), waiting);
+        waiting
+    }
+}
+
+// a subfile is wound the way it declares itself, flipped once for every INVERTNEXT or
+// mirroring matrix between it and the file the scene starts from. `winding` is what the
+// referencing file is wound with
+pub fn subfile_winding(
+    file: &LDrawFile,
+    winding: &BFCDirection,
+    subfile: &LDrawSubfile,
+    child: &LDrawFile,
+) -> BFCDirection {
+    if (*winding != file.bfc_direction) ^ subfile.inverted {
+        child.bfc_direction.flipped()
+    } else {
+        child.bfc_direction.clone()
+    }
+}
+
+// a subfile reference that resolved, with the colour and winding the referenced file
+// is drawn with
+pub struct Reference<'a> {
+    pub subfile: &'a LDrawSubfile,
+    pub file: &'a LDrawFile,
+    pub color: u32,
+    pub winding: BFCDirection,
+}
+
+impl<'a> Reference<'a> {
+    // relative to the referencing file
+    pub fn matrix(&self) -> Matrix4<f32> {
+        subfile_matrix(self.subfile)
+    }
+}
+
+// the subfiles of a file drawn in `color` and wound with `winding`, every walk down the
+// subfile tree takes its steps through here. Missing files are left out, the loader
+// reported them already
+pub fn references<'a>(
+    brick: &'a LDrawBrick,
+    file: &'a LDrawFile,
+    color: u32,
+    winding: &'a BFCDirection,
+) -> impl Iterator<Item = Reference<'a>> + 'a {
+    file.subfiles.iter().filter_map(move |subfile| {
+        let child = brick.files.get(&subfile.filename)?;
+        Some(Reference {
+            subfile,
+            file: child,
+            color: brick.colors.resolve_code(subfile.color.value, color),
+            winding: subfile_winding(file, winding, subfile, child),
+        })
+    })
+}
+
+pub fn subfile_matrix(subfile: &LDrawSubfile) -> Matrix4<f32> {
+    Matrix4::from_translation(subfile.translation)
+        .mul(Matrix4::from(subfile.transformation).transpose())
+}
+
+struct SceneBuilder<'a> {
+    brick: &'a LDrawBrick,
+    options: &'a SceneOptions,
+    counts: HashMap<MeshKey, usize>,
+    lookup: HashMap<MeshKey, usize>,
+    scene: Scene,
+    // false leaves out everything that is referenced by type 1 lines
+    descend: bool,
+    waiting: Waiting<'a>,
+}
+
+impl<'a> SceneBuilder<'a> {
+    // counts how often each (file, colour) pair shows up in the fully expanded brick
+    fn count(&mut self, file: &LDrawFile, color: u32, winding: &BFCDirection) {
+        for reference in references(self.brick, file, color, winding) {
+            self.count(reference.file, reference.color, &reference.winding);
+            let key = MeshKey {
+                filename: reference.subfile.filename.to_string(),
+                color: reference.color,
+                winding: reference.winding,
+            };
+            *self.counts.entry(key).or_insert(0) += 1;
+        }
+    }
+
+    fn add_mesh(&mut self, key: MeshKey) -> usize {
+        let index = self.scene.meshes.len();
+        self.scene.meshes.push(SceneMesh {
+            key: key.clone(),
+            groups: Vec::new(),
+            edges: Vec::new(),
+        });
+        self.lookup.insert(key, index);
+        index
+    }
+
+    fn instanced_mesh(&mut self, key: &MeshKey, file: &LDrawFile) -> usize {
+        if let Some(index) = self.lookup.get(key) {
+            return *index;
+        }
+
+        let index = self.add_mesh(key.clone());
+        // nested references are baked into the instanced mesh
+        self.bake(
+            file,
+            key.color,
+            Matrix4::identity(),
+            &key.winding,
+            index,
+            false,
+        );
+        index
+    }
+
+    // walks the model files of the brick for the parts that arrived, see build_arrived.
+    // Below a model file that was pending itself everything is new
+    fn arrive(
+        &mut self,
+        file: &LDrawFile,
+        color: u32,
+        matrix: Matrix4<f32>,
+        winding: &BFCDirection,
+        waited: &mut Waiting,
+        new: bool,
+    ) {
+        let brick = self.brick;
+        for reference in references(brick, file, color, winding) {
+            let name = &reference.subfile.filename;
+            let transformation = matrix.mul(reference.matrix());
+            let key = MeshKey {
+                filename: name.to_string(),
+                color: reference.color,
+                winding: reference.winding,
+            };
+
+            if !reference.file.is_part() {
+                let new = new || waited.pending.contains(name);
+                let draws =
+                    !reference.file.triangles.is_empty() || !reference.file.lines.is_empty();
+                if new && draws {
+                    // the lines and triangles of the model file itself
+                    let mesh = match self.lookup.get(&key) {
+                        Some(mesh) => *mesh,
+                        None => {
+                            let mesh = self.add_mesh(key.clone());
+                            let descend = std::mem::replace(&mut self.descend, false);
+                            self.bake(
+                                reference.file,
+                                key.color,
+                                Matrix4::identity(),
+                                &key.winding,
+                                mesh,
+                                false,
+                            );
+                            self.descend = descend;
+                            mesh
+                        }
+                    };
+                    self.scene.instances.push(SceneInstance {
+                        mesh,
+                        transformation,
+                    });
+                }
+                self.arrive(
+                    reference.file,
+                    key.color,
+                    transformation,
+                    &key.winding,
+                    waited,
+                    new,
+                );
+            } else if (new || waited.contains(brick, name)) && !self.waiting.contains(brick, name) {
+                let mesh = self.instanced_mesh(&key, reference.file);
+                self.scene.instances.push(SceneInstance {
+                    mesh,
+                    transformation,
+                });
+            }
+        }
+    }
+
+    fn bake(
+        &mut self,
+        file: &LDrawFile,
+        color: u32,
+        matrix: Matrix4<f32>,
+        winding: &BFCDirection,
+        target: usize,
+        allow_instancing: bool,
+    ) {
+        let brick = self.brick;
+        let mesh = &mut self.scene.meshes[target];
+        for triangle in file.triangles.iter() {
+            let (y, z) = if matches!(winding, &BFCDirection::CCW) {
+                (triangle.y, triangle.z)
+            } else {
+                (triangle.z, triangle.y)
+            };
+            let triangle_color = brick.colors.resolve_code(triangle.color.value, color);
+            let transparent = brick.colors.is_transparent(triangle_color);
+            let group = mesh.group_mut(triangle_color, transparent);
+            group
+                .positions
+                .push(matrix.mul(triangle.x.extend(1.0)).truncate());
+            group.positions.push(matrix.mul(y.extend(1.0)).truncate());
+            group.positions.push(matrix.mul(z.extend(1.0)).truncate());
+        }
+
+        for line in file.lines.iter() {
+            let line_color = brick.colors.resolve_code(line.color.value, color);
+            let edges = mesh.edges_mut(line_color);
+            edges
+                .positions
+                .push(matrix.mul(line.x.extend(1.0)).truncate());
+            edges
+                .positions
+                .push(matrix.mul(line.y.extend(1.0)).truncate());
+        }
+
+        if !self.descend {
+            return;
+        }
+
+        for reference in references(brick, file, color, winding) {
+            // a model that waits for some of its parts still draws the others, the
+            // placeholders go where the waiting parts are
+            if reference.file.is_part() && self.waiting.contains(brick, &reference.subfile.filename)
+            {
+                continue;
+            }
+            let transformation = matrix.mul(reference.matrix());
+            let key = MeshKey {
+                filename: reference.subfile.filename.to_string(),
+                color: reference.color,
+                winding: reference.winding,
+            };
+
+            let occurrences = *self.counts.get(&key).unwrap_or(&0);
+            if allow_instancing && occurrences >= self.options.instance_threshold {
+                let mesh = self.instanced_mesh(&key, reference.file);
+                self.scene.instances.push(SceneInstance {
+                    mesh,
+                    transformation,
+                });
+            } else {
+                self.bake(
+                    reference.file,
+                    key.color,
+                    transformation,
+                    &key.winding,
+                    target,
+                    allow_instancing,
+                );
+            }
+        }
+    }
+}
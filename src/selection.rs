@@ -0,0 +1,178 @@
+use std::collections::BTreeSet;
+
+use js_sys::{Function, Object, Reflect};
+use wasm_bindgen::prelude::*;
+
+use crate::{parser::part::LDrawBrick, scene::PartInstance};
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionStyle {
+    // selected parts are tinted with the selection colour
+    Highlight,
+    // a rim in the selection colour is drawn around the silhouette of selected parts
+    Outline,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnselectedStyle {
+    Normal,
+    // faded towards the background colour
+    Dim,
+    // drawn as faint see-through shells
+    Ghost,
+}
+
+#[derive(Debug, Clone)]
+pub enum SelectionCommand {
+    Select(Vec<usize>),
+    Deselect(Vec<usize>),
+    Clear,
+    // called with a part description for every instance, selects where it returns true
+    SelectWhere(Function),
+    SetStyle(SelectionStyle, UnselectedStyle, [u8; 3]),
+}
+
+// the selected part instances of a window, by index into the part instances of its brick
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    instances: BTreeSet<usize>,
+    pub style: SelectionStyle,
+    pub unselected: UnselectedStyle,
+    pub color: [u8; 3],
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Self {
+            instances: BTreeSet::new(),
+            style: SelectionStyle::Outline,
+            unselected: UnselectedStyle::Normal,
+            color: [255, 160, 0],
+        }
+    }
+}
+
+impl Selection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn contains(&self, instance: usize) -> bool {
+        self.instances.contains(&instance)
+    }
+
+    pub fn instances(&self) -> impl Iterator<Item = usize> + '_ {
+        self.instances.iter().copied()
+    }
+
+    // each returns whether anything changed
+    pub fn select(&mut self, instances: &[usize], parts: &[PartInstance]) -> bool {
+        let mut changed = false;
+        for instance in instances.iter() {
+            if *instance < parts.len() {
+                changed |= self.instances.insert(*instance);
+            } else {
+                log::warn!("can't select unknown part instance {}", instance);
+            }
+        }
+        changed
+    }
+
+    pub fn deselect(&mut self, instances: &[usize]) -> bool {
+        let mut changed = false;
+        for instance in instances.iter() {
+            changed |= self.instances.remove(instance);
+        }
+        changed
+    }
+
+    pub fn clear(&mut self) -> bool {
+        let changed = !self.instances.is_empty();
+        self.instances.clear();
+        changed
+    }
+
+    pub fn select_where(
+        &mut self,
+        parts: &[PartInstance],
+        predicate: impl Fn(usize, &PartInstance) -> bool,
+    ) -> bool {
+        let mut changed = false;
+        for (instance, part) in parts.iter().enumerate() {
+            if predicate(instance, part) {
+                changed |= self.instances.insert(instance);
+            }
+        }
+        changed
+    }
+
+    pub fn apply(
+        &mut self,
+        command: &SelectionCommand,
+        parts: &[PartInstance],
+        brick: &LDrawBrick,
+    ) -> bool {
+        match command {
+            SelectionCommand::Select(instances) => self.select(instances, parts),
+            SelectionCommand::Deselect(instances) => self.deselect(instances),
+            SelectionCommand::Clear => self.clear(),
+            SelectionCommand::SelectWhere(predicate) => {
+                self.select_where(parts, |instance, part| {
+                    match predicate.call1(&JsValue::NULL, &part_info(instance, part, brick)) {
+                        Ok(selected) => selected.is_truthy(),
+                        Err(error) => {
+                            log::warn!("selection predicate threw {:?}", error);
+                            false
+                        }
+                    }
+                })
+            }
+            SelectionCommand::SetStyle(style, unselected, color) => {
+                let changed =
+                    (self.style, self.unselected, self.color) != (*style, *unselected, *color);
+                self.style = *style;
+                self.unselected = *unselected;
+                self.color = *color;
+                changed
+            }
+        }
+    }
+}
+
+// { instance, filename, color, color_name, source_file, source_line } for JS predicates
+fn part_info(instance: usize, part: &PartInstance, brick: &LDrawBrick) -> JsValue {
+    let object = Object::new();
+    let fields = [
+        ("instance", JsValue::from_f64(instance as f64)),
+        ("filename", JsValue::from_str(&part.filename)),
+        ("color", JsValue::from_f64(part.color as f64)),
+        (
+            "color_name",
+            JsValue::from_str(&brick.colors.resolve(part.color).name),
+        ),
+        (
+            "source_file",
+            part.source
+                .as_ref()
+                .map(|(file, _)| JsValue::from_str(file))
+                .unwrap_or(JsValue::NULL),
+        ),
+        (
+            "source_line",
+            part.source
+                .as_ref()
+                .map(|(_, line)| JsValue::from_f64(*line as f64))
+                .unwrap_or(JsValue::NULL),
+        ),
+    ];
+    for (key, value) in fields.iter() {
+        Reflect::set(&object, &JsValue::from_str(key), value).unwrap();
+    }
+    object.into()
+}
@@ -0,0 +1,212 @@
+use std::collections::{HashMap, HashSet};
+
+use three_d::{vec3, InnerSpace, Vector3};
+
+// faces meeting at a sharper angle than this keep separate normals
+pub const CREASE_ANGLE: f32 = 60.0;
+// positions closer than this many LDU are the same vertex
+const WELD_TOLERANCE: f32 = 1e-3;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SmoothedMesh {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    // a (position, normal) index pair per corner, in the order of the input triangles
+    pub corners: Vec<[(u32, u32); 3]>,
+}
+
+// shared vertices and normals for a triangle soup. Normals are averaged over faces
+// around a vertex unless an LDraw edge line runs between them or they meet at more
+// than the crease angle, that is where LDraw parts are meant to look sharp
+pub fn smooth(triangles: &[[Vector3<f32>; 3]], hard_edges: &[[Vector3<f32>; 2]]) -> SmoothedMesh {
+    let mut mesh = SmoothedMesh::default();
+    let mut lookup: HashMap<[i64; 3], u32> = HashMap::new();
+    let key = |position: &Vector3<f32>| {
+        [position.x, position.y, position.z].map(|value| (value / WELD_TOLERANCE).round() as i64)
+    };
+
+    let welded: Vec<[u32; 3]> = triangles
+        .iter()
+        .map(|triangle| {
+            triangle.map(|position| {
+                *lookup.entry(key(&position)).or_insert_with(|| {
+                    mesh.positions.push(position);
+                    mesh.positions.len() as u32 - 1
+                })
+            })
+        })
+        .collect();
+
+    let hard: HashSet<(u32, u32)> = hard_edges
+        .iter()
+        .filter_map(|[from, to]| {
+            let from = *lookup.get(&key(from))?;
+            let to = *lookup.get(&key(to))?;
+            Some((from.min(to), from.max(to)))
+        })
+        .collect();
+
+    // area weighted, so slivers barely count
+    let face_normals: Vec<Vector3<f32>> = triangles
+        .iter()
+        .map(|[a, b, c]| (b - a).cross(c - a))
+        .collect();
+
+    let mut edges: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+    for (index, triangle) in welded.iter().enumerate() {
+        for corner in 0..3 {
+            let (from, to) = (triangle[corner], triangle[(corner + 1) % 3]);
+            edges
+                .entry((from.min(to), from.max(to)))
+                .or_default()
+                .push(index);
+        }
+    }
+
+    // corners around a vertex are joined across every smooth edge
+    let mut sets = DisjointSets::new(welded.len() * 3);
+    let crease = CREASE_ANGLE.to_radians().cos();
+    for (edge, faces) in edges.iter() {
+        if hard.contains(edge) {
+            continue;
+        }
+        for (i, first) in faces.iter().enumerate() {
+            for second in faces[i + 1..].iter() {
+                let (a, b) = (face_normals[*first], face_normals[*second]);
+                if a.magnitude2() == 0.0 || b.magnitude2() == 0.0 {
+                    continue;
+                }
+                // LDraw windings can't be trusted, so a flipped neighbour is still smooth
+                if a.normalize().dot(b.normalize()).abs() < crease {
+                    continue;
+                }
+                for vertex in [edge.0, edge.1] {
+                    sets.union(
+                        corner(&welded, *first, vertex),
+                        corner(&welded, *second, vertex),
+                    );
+                }
+            }
+        }
+    }
+
+    let mut sums: HashMap<usize, Vector3<f32>> = HashMap::new();
+    for (index, reference) in face_normals.iter().enumerate() {
+        let reference = *reference;
+        for corner in 0..3 {
+            let root = sets.find(index * 3 + corner);
+            let sum = sums.entry(root).or_insert_with(|| vec3(0.0, 0.0, 0.0));
+            // flipped faces are turned towards the first face of the set
+            *sum += if sum.dot(reference) < 0.0 {
+                -reference
+            } else {
+                reference
+            };
+        }
+    }
+
+    let mut normal_indices: HashMap<usize, u32> = HashMap::new();
+    for (index, triangle) in welded.iter().enumerate() {
+        let mut corners = [(0, 0); 3];
+        for (corner, position) in triangle.iter().enumerate() {
+            let root = sets.find(index * 3 + corner);
+            let normal = *normal_indices.entry(root).or_insert_with(|| {
+                let sum = sums[&root];
+                mesh.normals.push(if sum.magnitude2() > 0.0 {
+                    sum.normalize()
+                } else {
+                    vec3(0.0, 1.0, 0.0)
+                });
+                mesh.normals.len() as u32 - 1
+            });
+            corners[corner] = (*position, normal);
+        }
+        mesh.corners.push(corners);
+    }
+
+    mesh
+}
+
+// the triangles moved out along their vertex normals, welded so the shell stays closed
+// over sharp corners. Unlike a scale about the centre this follows hollows and concave
+// outlines, so a shell drawn behind the mesh only shows past its silhouette
+pub fn extrude(triangles: &[[Vector3<f32>; 3]], distance: f32) -> Vec<[Vector3<f32>; 3]> {
+    let mut lookup: HashMap<[i64; 3], usize> = HashMap::new();
+    let mut sums: Vec<Vector3<f32>> = Vec::new();
+    let key = |position: &Vector3<f32>| {
+        [position.x, position.y, position.z].map(|value| (value / WELD_TOLERANCE).round() as i64)
+    };
+
+    let welded: Vec<[usize; 3]> = triangles
+        .iter()
+        .map(|triangle| {
+            triangle.map(|position| {
+                *lookup.entry(key(&position)).or_insert_with(|| {
+                    sums.push(vec3(0.0, 0.0, 0.0));
+                    sums.len() - 1
+                })
+            })
+        })
+        .collect();
+
+    // area weighted like smooth, but across every edge, the shell must not split
+    for ([a, b, c], vertices) in triangles.iter().zip(welded.iter()) {
+        let normal = (b - a).cross(c - a);
+        for vertex in vertices.iter() {
+            sums[*vertex] += normal;
+        }
+    }
+    let offsets: Vec<Vector3<f32>> = sums
+        .iter()
+        .map(|sum| {
+            if sum.magnitude2() > 0.0 {
+                sum.normalize() * distance
+            } else {
+                vec3(0.0, 0.0, 0.0)
+            }
+        })
+        .collect();
+
+    triangles
+        .iter()
+        .zip(welded.iter())
+        .map(|(triangle, vertices)| {
+            [0, 1, 2].map(|corner| triangle[corner] + offsets[vertices[corner]])
+        })
+        .collect()
+}
+
+fn corner(triangles: &[[u32; 3]], triangle: usize, vertex: u32) -> usize {
+    let position = triangles[triangle]
+        .iter()
+        .position(|other| *other == vertex)
+        .unwrap();
+    triangle * 3 + position
+}
+
+struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(count: usize) -> Self {
+        Self {
+            parents: (0..count).collect(),
+        }
+    }
+
+    fn find(&mut self, mut item: usize) -> usize {
+        while self.parents[item] != item {
+            self.parents[item] = self.parents[self.parents[item]];
+            item = self.parents[item];
+        }
+        item
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parents[b] = a;
+        }
+    }
+}
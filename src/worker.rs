@@ -0,0 +1,784 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::rc::Rc;
+
+use js_sys::{Array, Float32Array, Function, Object, Promise, Reflect, Uint32Array, Uint8Array};
+use serde_json::{json, Value};
+use three_d::{vec3, Matrix4};
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{AbortController, AbortSignal, MessageEvent, Worker};
+
+use crate::{
+    baked,
+    coordinates::{CoordinateSystem, OutputFrame, Units},
+    errors::{describe_js_error, LoadError, LoadErrorKind},
+    fetch_brick,
+    parser::{part::LDrawBrick, tokenizer::BFCDirection},
+    progress::{BuildStage, LoadEvent, Progress},
+    scene::{
+        MeshKey, Scene, SceneEdges, SceneGroup, SceneInstance, SceneMesh, SceneOptions,
+        TransparencySort,
+    },
+};
+
+// what the page asks of the worker
+#[derive(Debug, Clone)]
+pub enum WorkerRequest {
+    Load {
+        job: u32,
+        brick_id: String,
+        options: SceneOptions,
+        prefer_baked: bool,
+    },
+    Cancel {
+        job: u32,
+    },
+}
+
+// what the worker answers, every load ends with exactly one Loaded or Failed
+#[derive(Debug, Clone)]
+pub enum WorkerResponse {
+    Progress {
+        job: u32,
+        event: LoadEvent,
+    },
+    Loaded {
+        job: u32,
+        // the brick as baked::encode writes it, the page still needs it for picking
+        brick: Vec<u8>,
+        scene: ScenePayload,
+    },
+    Failed {
+        job: u32,
+        error: LoadError,
+    },
+}
+
+// a run of vertices in ScenePayload::vertices
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadRange {
+    pub color: u32,
+    pub transparent: bool,
+    pub start: usize,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadMesh {
+    pub key: MeshKey,
+    pub groups: Vec<PayloadRange>,
+    pub edges: Vec<PayloadRange>,
+}
+
+// a built scene as a few flat buffers that can be transferred instead of copied
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScenePayload {
+    pub meshes: Vec<PayloadMesh>,
+    pub transparent: Vec<PayloadRange>,
+    // the mesh of each instance and its matrix, 16 floats column by column
+    pub instances: Vec<u32>,
+    pub matrices: Vec<f32>,
+    // three floats per vertex
+    pub vertices: Vec<f32>,
+}
+
+impl ScenePayload {
+    pub fn from_scene(scene: &Scene) -> Self {
+        let mut payload = Self::default();
+        let mut push = |color: u32, transparent: bool, positions: &[three_d::Vector3<f32>]| {
+            let start = payload.vertices.len() / 3;
+            for position in positions {
+                payload
+                    .vertices
+                    .extend_from_slice(&[position.x, position.y, position.z]);
+            }
+            PayloadRange {
+                color,
+                transparent,
+                start,
+                count: positions.len(),
+            }
+        };
+
+        let meshes: Vec<PayloadMesh> = scene
+            .meshes
+            .iter()
+            .map(|mesh| PayloadMesh {
+                key: mesh.key.clone(),
+                groups: mesh
+                    .groups
+                    .iter()
+                    .map(|group| push(group.color, group.transparent, &group.positions))
+                    .collect(),
+                edges: mesh
+                    .edges
+                    .iter()
+                    .map(|edges| push(edges.color, false, &edges.positions))
+                    .collect(),
+            })
+            .collect();
+        let transparent: Vec<PayloadRange> = scene
+            .transparent
+            .iter()
+            .map(|group| push(group.color, group.transparent, &group.positions))
+            .collect();
+
+        payload.meshes = meshes;
+        payload.transparent = transparent;
+        for instance in scene.instances.iter() {
+            payload.instances.push(instance.mesh as u32);
+            let matrix: &[f32; 16] = instance.transformation.as_ref();
+            payload.matrices.extend_from_slice(matrix);
+        }
+        payload
+    }
+
+    pub fn into_scene(self) -> Scene {
+        let positions = |range: &PayloadRange| {
+            self.vertices[range.start * 3..(range.start + range.count) * 3]
+                .chunks_exact(3)
+                .map(|xyz| vec3(xyz[0], xyz[1], xyz[2]))
+                .collect::<Vec<_>>()
+        };
+        let group = |range: &PayloadRange| SceneGroup {
+            color: range.color,
+            transparent: range.transparent,
+            positions: positions(range),
+        };
+
+        Scene {
+            meshes: self
+                .meshes
+                .iter()
+                .map(|mesh| SceneMesh {
+                    key: mesh.key.clone(),
+                    groups: mesh.groups.iter().map(group).collect(),
+                    edges: mesh
+                        .edges
+                        .iter()
+                        .map(|range| SceneEdges {
+                            color: range.color,
+                            positions: positions(range),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            instances: self
+                .instances
+                .iter()
+                .zip(self.matrices.chunks_exact(16))
+                .map(|(mesh, matrix)| {
+                    let matrix: &[f32; 16] = matrix.try_into().unwrap();
+                    let matrix: &Matrix4<f32> = matrix.into();
+                    SceneInstance {
+                        mesh: *mesh as usize,
+                        transformation: *matrix,
+                    }
+                })
+                .collect(),
+            transparent: self.transparent.iter().map(group).collect(),
+        }
+    }
+
+    // the ranges and keys as JSON, the buffers stay typed arrays
+    fn layout(&self) -> Value {
+        let range = |range: &PayloadRange| {
+            json!([range.color, range.transparent, range.start, range.count])
+        };
+        json!({
+            "meshes": self.meshes.iter().map(|mesh| json!({
+                "file": mesh.key.filename,
+                "color": mesh.key.color,
+                "ccw": mesh.key.winding == BFCDirection::CCW,
+                "groups": mesh.groups.iter().map(range).collect::<Vec<_>>(),
+                "edges": mesh.edges.iter().map(range).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+            "transparent": self.transparent.iter().map(range).collect::<Vec<_>>(),
+        })
+    }
+
+    fn from_layout(
+        layout: &Value,
+        instances: Vec<u32>,
+        matrices: Vec<f32>,
+        vertices: Vec<f32>,
+    ) -> Option<Self> {
+        let ranges = |value: &Value| -> Option<Vec<PayloadRange>> {
+            value
+                .as_array()?
+                .iter()
+                .map(|range| {
+                    Some(PayloadRange {
+                        color: range[0].as_u64()? as u32,
+                        transparent: range[1].as_bool()?,
+                        start: range[2].as_u64()? as usize,
+                        count: range[3].as_u64()? as usize,
+                    })
+                })
+                .collect()
+        };
+
+        let meshes = layout["meshes"]
+            .as_array()?
+            .iter()
+            .map(|mesh| {
+                Some(PayloadMesh {
+                    key: MeshKey {
+                        filename: mesh["file"].as_str()?.to_string(),
+                        color: mesh["color"].as_u64()? as u32,
+                        winding: if mesh["ccw"].as_bool()? {
+                            BFCDirection::CCW
+                        } else {
+                            BFCDirection::CW
+                        },
+                    },
+                    groups: ranges(&mesh["groups"])?,
+                    edges: ranges(&mesh["edges"])?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let payload = Self {
+            meshes,
+            transparent: ranges(&layout["transparent"])?,
+            instances,
+            matrices,
+            vertices,
+        };
+        payload.fits().then_some(payload)
+    }
+
+    // a range past the end would panic once the scene is rebuilt
+    pub fn fits(&self) -> bool {
+        let vertex_count = self.vertices.len() / 3;
+        let ranges_fit = self
+            .meshes
+            .iter()
+            .flat_map(|mesh| mesh.groups.iter().chain(mesh.edges.iter()))
+            .chain(self.transparent.iter())
+            .all(|range| {
+                range
+                    .start
+                    .checked_add(range.count)
+                    .is_some_and(|end| end <= vertex_count)
+            });
+        let instances_fit = self.instances.len() * 16 == self.matrices.len()
+            && self
+                .instances
+                .iter()
+                .all(|mesh| (*mesh as usize) < self.meshes.len());
+        ranges_fit && instances_fit
+    }
+}
+
+// the options go over as the discriminants of their enums and are looked up by them,
+// so the order of these does not matter
+const SYSTEMS: [CoordinateSystem; 3] = [
+    CoordinateSystem::LDraw,
+    CoordinateSystem::YUp,
+    CoordinateSystem::ZUp,
+];
+const UNITS: [Units; 4] = [Units::LDU, Units::Millimetres, Units::Metres, Units::Studs];
+const SORTS: [TransparencySort; 2] = [TransparencySort::PerInstance, TransparencySort::PerTriangle];
+
+fn by_discriminant<T: Copy>(values: &[T], code: u32, discriminant: fn(T) -> u32) -> Option<T> {
+    values
+        .iter()
+        .copied()
+        .find(|value| discriminant(*value) == code)
+}
+
+fn message_error(message: &str) -> LoadError {
+    LoadError::new(
+        LoadErrorKind::Fetch,
+        format!("unexpected worker message: {}", message),
+    )
+}
+
+fn set(object: &Object, key: &str, value: impl Into<JsValue>) {
+    Reflect::set(object, &JsValue::from_str(key), &value.into()).unwrap();
+}
+
+fn get(object: &JsValue, key: &str) -> Option<JsValue> {
+    Reflect::get(object, &JsValue::from_str(key))
+        .ok()
+        .filter(|value| !value.is_undefined())
+}
+
+impl WorkerRequest {
+    pub fn job(&self) -> u32 {
+        match self {
+            WorkerRequest::Load { job, .. } | WorkerRequest::Cancel { job } => *job,
+        }
+    }
+
+    pub fn to_js(&self) -> JsValue {
+        let object = Object::new();
+        match self {
+            WorkerRequest::Load {
+                job,
+                brick_id,
+                options,
+                prefer_baked,
+            } => {
+                set(&object, "type", "load");
+                set(&object, "job", *job);
+                set(&object, "brick", brick_id.as_str());
+                set(&object, "threshold", options.instance_threshold as u32);
+                set(&object, "system", options.frame.system as u32);
+                set(&object, "units", options.frame.units as u32);
+                set(&object, "sort", options.transparency_sort as u32);
+                set(&object, "prefer_baked", *prefer_baked);
+            }
+            WorkerRequest::Cancel { job } => {
+                set(&object, "type", "cancel");
+                set(&object, "job", *job);
+            }
+        }
+        object.into()
+    }
+
+    pub fn from_js(value: &JsValue) -> Result<Self, LoadError> {
+        let read = || -> Option<Self> {
+            let job = get(value, "job")?.as_f64()? as u32;
+            let number = |key: &str| Some(get(value, key)?.as_f64()? as u32);
+            Some(match get(value, "type")?.as_string()?.as_str() {
+                "load" => WorkerRequest::Load {
+                    job,
+                    brick_id: get(value, "brick")?.as_string()?,
+                    options: SceneOptions {
+                        instance_threshold: number("threshold")? as usize,
+                        frame: OutputFrame::new(
+                            by_discriminant(&SYSTEMS, number("system")?, |system| system as u32)?,
+                            by_discriminant(&UNITS, number("units")?, |units| units as u32)?,
+                        ),
+                        transparency_sort: by_discriminant(&SORTS, number("sort")?, |sort| {
+                            sort as u32
+                        })?,
+                    },
+                    prefer_baked: get(value, "prefer_baked")?.as_bool()?,
+                },
+                "cancel" => WorkerRequest::Cancel { job },
+                _ => return None,
+            })
+        };
+        read().ok_or_else(|| message_error(&describe_js_error(value)))
+    }
+}
+
+impl WorkerResponse {
+    pub fn job(&self) -> u32 {
+        match self {
+            WorkerResponse::Progress { job, .. }
+            | WorkerResponse::Loaded { job, .. }
+            | WorkerResponse::Failed { job, .. } => *job,
+        }
+    }
+
+    // the message and the buffers to transfer with it
+    pub fn to_js(&self) -> (JsValue, Array) {
+        let object = Object::new();
+        let transfer = Array::new();
+        set(&object, "job", self.job());
+        match self {
+            WorkerResponse::Progress { event, .. } => {
+                set(&object, "type", "progress");
+                set(&object, "event", event.to_js());
+            }
+            WorkerResponse::Loaded { brick, scene, .. } => {
+                let brick = Uint8Array::from(brick.as_slice());
+                let instances = Uint32Array::from(scene.instances.as_slice());
+                let matrices = Float32Array::from(scene.matrices.as_slice());
+                let vertices = Float32Array::from(scene.vertices.as_slice());
+                for buffer in [
+                    brick.buffer(),
+                    instances.buffer(),
+                    matrices.buffer(),
+                    vertices.buffer(),
+                ] {
+                    transfer.push(&buffer);
+                }
+
+                set(&object, "type", "loaded");
+                set(&object, "brick", brick);
+                set(&object, "layout", scene.layout().to_string());
+                set(&object, "instances", instances);
+                set(&object, "matrices", matrices);
+                set(&object, "vertices", vertices);
+            }
+            WorkerResponse::Failed { error, .. } => {
+                set(&object, "type", "failed");
+                set(&object, "error", error.to_js());
+            }
+        }
+        (object.into(), transfer)
+    }
+
+    pub fn from_js(value: &JsValue) -> Result<Self, LoadError> {
+        let read = || -> Option<Self> {
+            let job = get(value, "job")?.as_f64()? as u32;
+            Some(match get(value, "type")?.as_string()?.as_str() {
+                "progress" => WorkerResponse::Progress {
+                    job,
+                    event: LoadEvent::from_js(&get(value, "event")?)?,
+                },
+                // the typed arrays are copied into wasm memory once here and the scene
+                // copies them again into its groups, decoding the brick is a full read of
+                // it on the page's thread. All of it is linear in the size of the model and
+                // well below building the scene, which is what the worker saves
+                "loaded" => {
+                    let typed = |key: &str| get(value, key);
+                    let layout: Value =
+                        serde_json::from_str(&get(value, "layout")?.as_string()?).ok()?;
+                    WorkerResponse::Loaded {
+                        job,
+                        brick: typed("brick")?.dyn_into::<Uint8Array>().ok()?.to_vec(),
+                        scene: ScenePayload::from_layout(
+                            &layout,
+                            typed("instances")?.dyn_into::<Uint32Array>().ok()?.to_vec(),
+                            typed("matrices")?.dyn_into::<Float32Array>().ok()?.to_vec(),
+                            typed("vertices")?.dyn_into::<Float32Array>().ok()?.to_vec(),
+                        )?,
+                    }
+                }
+                "failed" => WorkerResponse::Failed {
+                    job,
+                    error: LoadError::from_js(&get(value, "error")?)?,
+                },
+                _ => return None,
+            })
+        };
+        read().ok_or_else(|| message_error(&describe_js_error(value)))
+    }
+}
+
+// a load the worker has to fetch before it can finish it
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadJob {
+    pub job: u32,
+    pub brick_id: String,
+    pub prefer_baked: bool,
+}
+
+// the worker without the fetching, so it can run natively
+pub struct WorkerCore {
+    post: Box<dyn Fn(WorkerResponse)>,
+    jobs: HashMap<u32, SceneOptions>,
+    cancelled: HashSet<u32>,
+}
+
+impl WorkerCore {
+    pub fn new(post: impl Fn(WorkerResponse) + 'static) -> Self {
+        Self {
+            post: Box::new(post),
+            jobs: HashMap::new(),
+            cancelled: HashSet::new(),
+        }
+    }
+
+    // a load comes back as the job to fetch, cancelling one only marks it
+    pub fn handle(&mut self, request: WorkerRequest) -> Option<LoadJob> {
+        match request {
+            WorkerRequest::Load {
+                job,
+                brick_id,
+                options,
+                prefer_baked,
+            } => {
+                self.jobs.insert(job, options);
+                Some(LoadJob {
+                    job,
+                    brick_id,
+                    prefer_baked,
+                })
+            }
+            WorkerRequest::Cancel { job } => {
+                if self.jobs.contains_key(&job) {
+                    self.cancelled.insert(job);
+                }
+                None
+            }
+        }
+    }
+
+    pub fn is_cancelled(&self, job: u32) -> bool {
+        self.cancelled.contains(&job)
+    }
+
+    pub fn progress(&self, job: u32, event: LoadEvent) {
+        if self.jobs.contains_key(&job) && !self.is_cancelled(job) {
+            (self.post)(WorkerResponse::Progress { job, event });
+        }
+    }
+
+    // builds the scene of a fetched brick, unless the page has given up on it meanwhile.
+    // A scene that was baked with the brick is only placed in the frame
+    pub fn finish(&mut self, job: u32, result: Result<(LDrawBrick, Option<Scene>), LoadError>) {
+        let options = match self.jobs.remove(&job) {
+            Some(options) => options,
+            None => return,
+        };
+        let response = match result {
+            _ if self.cancelled.remove(&job) => WorkerResponse::Failed {
+                job,
+                error: LoadError::cancelled(),
+            },
+            Err(error) => WorkerResponse::Failed { job, error },
+            Ok((brick, baked)) => {
+                (self.post)(WorkerResponse::Progress {
+                    job,
+                    event: LoadEvent::Stage(BuildStage::Scene),
+                });
+                let scene = match baked {
+                    Some(scene) => scene.framed(&options),
+                    None => Scene::build(&brick, &options),
+                };
+                WorkerResponse::Loaded {
+                    job,
+                    brick: baked::encode(&brick),
+                    scene: ScenePayload::from_scene(&scene),
+                }
+            }
+        };
+        (self.post)(response);
+    }
+}
+
+// runs in the worker, worker.ts hands it the messages and a function to post with
+#[wasm_bindgen]
+pub struct LoadWorker {
+    core: Rc<RefCell<WorkerCore>>,
+    aborts: Rc<RefCell<HashMap<u32, AbortController>>>,
+}
+
+#[wasm_bindgen]
+impl LoadWorker {
+    // post is called with (message, transfer)
+    #[wasm_bindgen(constructor)]
+    pub fn new(post: Function) -> Self {
+        let core = WorkerCore::new(move |response| {
+            let (message, transfer) = response.to_js();
+            if let Err(error) = post.call2(&JsValue::NULL, &message, &transfer) {
+                log::warn!("posting from the worker failed: {:?}", error);
+            }
+        });
+        Self {
+            core: Rc::new(RefCell::new(core)),
+            aborts: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn handle(&self, message: JsValue) -> Result<(), JsValue> {
+        let request = WorkerRequest::from_js(&message)?;
+        let cancel = match &request {
+            WorkerRequest::Cancel { job } => Some(*job),
+            _ => None,
+        };
+        let load = self.core.borrow_mut().handle(request);
+        if let Some(job) = cancel {
+            if let Some(controller) = self.aborts.borrow().get(&job) {
+                controller.abort();
+            }
+        }
+
+        if let Some(load) = load {
+            let controller = AbortController::new()?;
+            let signal = controller.signal();
+            self.aborts.borrow_mut().insert(load.job, controller);
+
+            let core = self.core.clone();
+            let aborts = self.aborts.clone();
+            spawn_local(async move {
+                let job = load.job;
+                let forward = {
+                    let core = core.clone();
+                    Closure::<dyn FnMut(JsValue)>::new(move |event: JsValue| {
+                        if let Some(event) = LoadEvent::from_js(&event) {
+                            core.borrow().progress(job, event);
+                        }
+                    })
+                };
+                let progress = Progress::new();
+                progress.add_listener(forward.as_ref().unchecked_ref::<Function>().clone());
+
+                let result =
+                    fetch_brick(&load.brick_id, load.prefer_baked, &signal, &progress).await;
+                aborts.borrow_mut().remove(&load.job);
+                core.borrow_mut().finish(load.job, result);
+            });
+        }
+        Ok(())
+    }
+}
+
+// the page side, a load resolves once its Loaded or Failed comes back
+pub struct WorkerClient {
+    worker: Worker,
+    next_job: Cell<u32>,
+    pending: Rc<RefCell<HashMap<u32, PendingJob>>>,
+    _listener: Closure<dyn FnMut(MessageEvent)>,
+    _error_listener: Closure<dyn FnMut(JsValue)>,
+}
+
+struct PendingJob {
+    progress: Progress,
+    resolve: Function,
+    result: Option<Result<(LDrawBrick, Scene), LoadError>>,
+}
+
+// settles every pending load with the error, nothing more comes back for them
+fn fail_pending(pending: &RefCell<HashMap<u32, PendingJob>>, error: LoadError) {
+    let resolves: Vec<Function> = pending
+        .borrow_mut()
+        .values_mut()
+        .filter(|job| job.result.is_none())
+        .map(|job| {
+            job.result = Some(Err(error.clone()));
+            job.resolve.clone()
+        })
+        .collect();
+    for resolve in resolves {
+        resolve.call0(&JsValue::NULL).ok();
+    }
+}
+
+impl WorkerClient {
+    pub fn new(worker: Worker) -> Self {
+        let pending: Rc<RefCell<HashMap<u32, PendingJob>>> = Rc::new(RefCell::new(HashMap::new()));
+        let listener = {
+            let pending = pending.clone();
+            Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                let response = match WorkerResponse::from_js(&event.data()) {
+                    Ok(response) => response,
+                    Err(error) => return log::warn!("{}", error),
+                };
+                let job = response.job();
+                let result = match response {
+                    // listeners may start other loads, so nothing stays borrowed
+                    WorkerResponse::Progress { event, .. } => {
+                        let progress = pending.borrow().get(&job).map(|job| job.progress.clone());
+                        if let Some(progress) = progress {
+                            progress.emit(event);
+                        }
+                        return;
+                    }
+                    WorkerResponse::Loaded { brick, scene, .. } => {
+                        baked::decode(&brick).map(|brick| (brick, scene.into_scene()))
+                    }
+                    WorkerResponse::Failed { error, .. } => Err(error),
+                };
+                let resolve = match pending.borrow_mut().get_mut(&job) {
+                    Some(job) => {
+                        job.result = Some(result);
+                        job.resolve.clone()
+                    }
+                    None => return,
+                };
+                resolve.call0(&JsValue::NULL).ok();
+            })
+        };
+        worker.set_onmessage(Some(listener.as_ref().unchecked_ref()));
+
+        // a script that failed to load or threw, or a message that could not be
+        // deserialised, the loads waiting on the worker would never finish otherwise
+        let error_listener = {
+            let pending = pending.clone();
+            Closure::<dyn FnMut(JsValue)>::new(move |event: JsValue| {
+                let message = get(&event, "message")
+                    .and_then(|message| message.as_string())
+                    .unwrap_or_else(|| describe_js_error(&event));
+                fail_pending(
+                    &pending,
+                    LoadError::new(
+                        LoadErrorKind::Fetch,
+                        format!("the worker failed: {}", message),
+                    ),
+                );
+            })
+        };
+        worker.set_onerror(Some(error_listener.as_ref().unchecked_ref()));
+        worker.set_onmessageerror(Some(error_listener.as_ref().unchecked_ref()));
+
+        Self {
+            worker,
+            next_job: Cell::new(0),
+            pending,
+            _listener: listener,
+            _error_listener: error_listener,
+        }
+    }
+
+    // aborting the signal cancels the job in the worker as well
+    pub async fn load(
+        &self,
+        brick_id: &str,
+        options: &SceneOptions,
+        prefer_baked: bool,
+        signal: &AbortSignal,
+        progress: &Progress,
+    ) -> Result<(LDrawBrick, Scene), LoadError> {
+        let job = self.next_job.get();
+        self.next_job.set(job + 1);
+
+        let done = Promise::new(&mut |resolve, _| {
+            self.pending.borrow_mut().insert(
+                job,
+                PendingJob {
+                    progress: progress.clone(),
+                    resolve,
+                    result: None,
+                },
+            );
+        });
+        let posted = self
+            .post(&WorkerRequest::Load {
+                job,
+                brick_id: brick_id.to_string(),
+                options: options.clone(),
+                prefer_baked,
+            })
+            .and_then(|_| {
+                if signal.aborted() {
+                    self.post(&WorkerRequest::Cancel { job })
+                } else {
+                    Ok(())
+                }
+            });
+        // nothing will answer for the job, so it is forgotten before anything listens
+        if let Err(error) = posted {
+            self.pending.borrow_mut().remove(&job);
+            return Err(error);
+        }
+
+        let cancel = {
+            let worker = self.worker.clone();
+            Closure::<dyn FnMut()>::new(move || {
+                worker
+                    .post_message(&WorkerRequest::Cancel { job }.to_js())
+                    .ok();
+            })
+        };
+        signal
+            .add_event_listener_with_callback("abort", cancel.as_ref().unchecked_ref())
+            .ok();
+
+        JsFuture::from(done).await.ok();
+        signal
+            .remove_event_listener_with_callback("abort", cancel.as_ref().unchecked_ref())
+            .ok();
+        self.pending
+            .borrow_mut()
+            .remove(&job)
+            .and_then(|job| job.result)
+            .unwrap_or_else(|| Err(LoadError::cancelled()))
+    }
+
+    fn post(&self, request: &WorkerRequest) -> Result<(), LoadError> {
+        self.worker
+            .post_message(&request.to_js())
+            .map_err(|error| LoadError::new(LoadErrorKind::Fetch, describe_js_error(&error)))
+    }
+}
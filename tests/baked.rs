@@ -0,0 +1,108 @@
+mod common;
+
+use ldraw_renderer::{
+    baked::{baked_scene, decode, decode_with_scene, encode, encode_with_scene},
+    coordinates::{CoordinateSystem, OutputFrame, Units},
+    errors::LoadErrorKind,
+    parser::part::LDrawBrick,
+    scene::{Scene, SceneOptions, TransparencySort},
+};
+use three_d::vec3;
+
+fn brick() -> LDrawBrick {
+    common::brick(
+        &[
+            (
+                "model.ldr",
+                "0 Model\n0 !COLOUR Red CODE 4 VALUE #C91A09 EDGE #333333 ALPHA 128\n\
+                 1 4 0 0 0 1 0 0 0 1 0 0 0 1 part.dat\n1 16 40 0 0 0 0 1 0 1 0 -1 0 0 part.dat",
+            ),
+            (
+                "part.dat",
+                "0 Part\n0 !LDRAW_ORG Part\n4 16 0 0 0 1 0 0 1 0 1 0 0 1\n2 24 0 0 0 1 0 0",
+            ),
+        ],
+        "model.ldr",
+    )
+}
+
+#[test]
+fn round_trips_parts_instances_and_colors() {
+    let bytes = encode(&brick());
+    let decoded = decode(&bytes).unwrap();
+
+    assert_eq!(decoded.entry_file, "model.ldr");
+    let red = decoded.colors.get(4).unwrap();
+    assert_eq!((red.value, red.alpha), ([0xC9, 0x1A, 0x09], 128));
+
+    let part = &decoded.files["part.dat"];
+    assert_eq!(part.triangles.len(), 2);
+    assert_eq!(part.lines.len(), 1);
+    assert_eq!(part.lines[0].color.value, 24);
+
+    let model = &decoded.files["model.ldr"];
+    let colors: Vec<u32> = model
+        .subfiles
+        .iter()
+        .map(|subfile| subfile.color.value)
+        .collect();
+    assert_eq!(colors, [4, 16]);
+    assert_eq!(model.subfiles[1].translation.x, 40.0);
+}
+
+#[test]
+fn rejects_stale_or_corrupted_files() {
+    let bytes = encode(&brick());
+
+    let mut version = bytes.clone();
+    version[4] += 1;
+    assert_eq!(decode(&version).unwrap_err().kind, LoadErrorKind::Stale);
+
+    let mut flipped = bytes.clone();
+    let last = flipped.len() - 1;
+    flipped[last] ^= 1;
+    assert_eq!(decode(&flipped).unwrap_err().kind, LoadErrorKind::Stale);
+
+    assert_eq!(decode(&bytes[..10]).unwrap_err().kind, LoadErrorKind::Stale);
+}
+
+#[test]
+fn stores_the_scene_and_exact_positions() {
+    // a vertex off the grid, far from the origin
+    let brick = common::brick(
+        &[
+            (
+                "model.ldr",
+                "0 Model\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 part.dat\n\
+                 1 4 100000 0 0 1 0 0 0 1 0 0 0 1 part.dat",
+            ),
+            (
+                "part.dat",
+                "0 Part\n0 !LDRAW_ORG Part\n3 16 0.123457 0 0 1 0 0 0 0 1",
+            ),
+        ],
+        "model.ldr",
+    );
+    let bytes = encode_with_scene(&brick, &baked_scene(&brick));
+    assert_eq!(decode(&bytes).unwrap().entry_file, "model.ldr");
+    let (decoded, scene) = decode_with_scene(&bytes).unwrap();
+    assert_eq!(decoded.files["part.dat"].triangles[0].x.x, 0.123457);
+    assert!(decode_with_scene(&encode(&brick)).unwrap().1.is_none());
+
+    // placed in the frame, it matches a scene built from the brick
+    let options = SceneOptions {
+        frame: OutputFrame::new(CoordinateSystem::ZUp, Units::Millimetres),
+        transparency_sort: TransparencySort::PerTriangle,
+        ..SceneOptions::default()
+    };
+    let scene = scene.unwrap().framed(&options);
+    let built = Scene::build(&brick, &options);
+    assert_eq!(scene.meshes.len(), built.meshes.len());
+    assert_eq!(scene.triangle_count(), built.triangle_count());
+    let first = |scene: &Scene| scene.meshes[0].groups[0].positions[0];
+    assert_eq!(first(&scene), first(&built));
+    assert_eq!(first(&scene), vec3(0.123457, 0.0, 0.0));
+    for (baked, built) in scene.instances.iter().zip(built.instances.iter()) {
+        assert_eq!(baked.transformation, built.transformation);
+    }
+}
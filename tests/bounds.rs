@@ -0,0 +1,75 @@
+mod common;
+
+use ldraw_renderer::{
+    bounds::{ModelBounds, Obb},
+    coordinates::{CoordinateSystem, OutputFrame, Units},
+    scene::part_instances,
+};
+use three_d::{vec3, InnerSpace, Vector3};
+
+const SQUARE: &str = "0 Square\n0 !LDRAW_ORG Part\n4 16 0 0 0 10 0 0 10 10 0 0 10 0";
+
+// models are loaded as {id}.dat, only the missing part header tells them apart
+#[test]
+fn resolves_a_dat_model_into_its_parts() {
+    let brick = common::brick(
+        &[
+            ("square.dat", SQUARE),
+            ("sub.dat", "0 Sub\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 square.dat"),
+            (
+                "10030.dat",
+                "0 Model\n\
+                 1 4 0 0 0 1 0 0 0 1 0 0 0 1 sub.dat\n\
+                 1 1 100 0 0 1 0 0 0 1 0 0 0 1 square.dat",
+            ),
+        ],
+        "10030.dat",
+    );
+
+    let parts = part_instances(&brick);
+    let names: Vec<&str> = parts.iter().map(|part| part.filename.as_str()).collect();
+    assert_eq!(names, ["square.dat", "square.dat"]);
+    assert_eq!(parts[0].color, 4);
+    assert_eq!(parts[1].source, Some(("10030.dat".to_string(), 3)));
+
+    let bounds = ModelBounds::compute(
+        &brick,
+        OutputFrame::new(CoordinateSystem::LDraw, Units::LDU),
+    );
+    assert_eq!(bounds.parts().len(), 2);
+    assert_eq!(bounds.parts()[1].aabb.min, vec3(100.0, 0.0, 0.0));
+    assert_eq!(bounds.model_aabb().max, vec3(110.0, 10.0, 0.0));
+}
+
+#[test]
+fn fits_oriented_boxes_along_the_principal_axes() {
+    // a 40 x 10 x 20 box turned 45 degrees about y
+    let (sin, cos) = std::f32::consts::FRAC_PI_4.sin_cos();
+    let mut points = Vec::new();
+    for corner in 0..8 {
+        let x = if corner & 1 == 0 { -20.0 } else { 20.0 };
+        let y = if corner & 2 == 0 { -5.0 } else { 5.0 };
+        let z = if corner & 4 == 0 { -10.0 } else { 10.0 };
+        points.push(vec3(x * cos + z * sin, y, z * cos - x * sin) + vec3(3.0, 4.0, 5.0));
+    }
+
+    let obb = Obb::from_points(&points);
+    let mut size = [obb.size().x, obb.size().y, obb.size().z];
+    size.sort_by(f32::total_cmp);
+    for (size, expected) in size.iter().zip([10.0, 20.0, 40.0]) {
+        assert!((size - expected).abs() < 1e-3, "{:?}", obb);
+    }
+    assert!((obb.center - vec3(3.0, 4.0, 5.0)).magnitude() < 1e-3);
+    let [x, y, z]: [Vector3<f32>; 3] = obb.axes;
+    assert!((x.cross(y) - z).magnitude() < 1e-4);
+}
+
+#[test]
+fn survives_nan_coordinates() {
+    let points = [
+        vec3(0.0, 0.0, 0.0),
+        vec3(f32::NAN, 1.0, 0.0),
+        vec3(1.0, 1.0, 1.0),
+    ];
+    Obb::from_points(&points);
+}
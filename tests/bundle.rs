@@ -0,0 +1,186 @@
+use ldraw_renderer::{
+    coordinates::{CoordinateSystem, OutputFrame, Units},
+    parser::{
+        bundle::{Bundle, Resolver},
+        colors::ColorTable,
+    },
+    progress::Progress,
+    progressive::loading_bounds,
+    scene::{pending_parts, Scene, SceneOptions, PLACEHOLDER_COLOR},
+};
+use std::io::{Cursor, Write};
+use three_d::vec3;
+use zip::{write::FileOptions, ZipWriter};
+
+fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, text) in files {
+        zip.start_file(*name, FileOptions::default()).unwrap();
+        zip.write_all(text.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+#[test]
+fn resolves_subfiles_from_the_zip() {
+    let bytes = zip(&[
+        (
+            "3001.dat",
+            "0 Brick\n0 !LDRAW_ORG Part\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 S\\3001s01.DAT\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 stud.dat\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 missing.dat",
+        ),
+        (
+            "s/3001s01.dat",
+            "0 Subpart\n0 !LDRAW_ORG Subpart\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 stud.dat\n3 16 0 0 0 1 0 0 0 0 1",
+        ),
+        ("stud.dat", "0 Stud\n0 !LDRAW_ORG Primitive\n2 24 0 0 0 1 0 0"),
+    ]);
+
+    let bundle = Bundle::read(&bytes).unwrap();
+    assert_eq!(bundle.len(), 3);
+
+    let brick = bundle
+        .resolve("3001.dat", ColorTable::new(), &Progress::new())
+        .unwrap();
+    let mut names: Vec<&str> = brick.files.keys().map(|name| name.as_str()).collect();
+    names.sort();
+    assert_eq!(names, ["3001.dat", "S/3001s01.DAT", "stud.dat"]);
+    assert_eq!(brick.files["S/3001s01.DAT"].triangles.len(), 1);
+
+    // every name is queued once, however often it is referenced
+    let mut resolver = Resolver::new("3001.dat", ColorTable::new());
+    resolver.step(&bundle, &Progress::new()).unwrap();
+    let mut pending: Vec<String> = resolver.pending().into_iter().collect();
+    pending.sort();
+    assert_eq!(pending, ["S/3001s01.DAT", "missing.dat", "stud.dat"]);
+    while !resolver.is_done() {
+        resolver.step(&bundle, &Progress::new()).unwrap();
+    }
+    assert!(resolver.pending().is_empty());
+
+    assert!(bundle
+        .resolve("3002.dat", ColorTable::new(), &Progress::new())
+        .is_err());
+}
+
+#[test]
+fn completes_one_part_after_another() {
+    let bytes = zip(&[
+        (
+            "10030.dat",
+            "0 Model\n1 4 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n1 4 40 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n1 1 0 -24 0 1 0 0 0 1 0 0 0 1 3002.dat",
+        ),
+        (
+            "3001.dat",
+            "0 Brick\n0 !LDRAW_ORG Part\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 stud.dat",
+        ),
+        (
+            "3002.dat",
+            "0 Brick\n0 !LDRAW_ORG Part\n3 16 0 0 0 1 0 0 0 0 1",
+        ),
+        ("stud.dat", "0 Stud\n0 !LDRAW_ORG Primitive\n3 16 0 0 0 1 0 0 0 0 1"),
+    ]);
+    let bundle = Bundle::read(&bytes).unwrap();
+    let progress = Progress::new();
+    let placeholders = |resolver: &Resolver| {
+        let brick = resolver.brick();
+        let pending = resolver.pending();
+        let scene = Scene::build_partial(&brick, &SceneOptions::default(), &pending);
+        let mesh = scene
+            .meshes
+            .iter()
+            .position(|mesh| mesh.key.color == PLACEHOLDER_COLOR);
+        let drawn = scene
+            .instances
+            .iter()
+            .filter(|instance| Some(instance.mesh) == mesh)
+            .count();
+        assert_eq!(drawn, pending_parts(&brick, &pending).len());
+        drawn
+    };
+
+    // models are loaded as {id}.dat too
+    let mut resolver = Resolver::new("10030.dat", ColorTable::new());
+    resolver.step(&bundle, &progress).unwrap();
+    assert_eq!(placeholders(&resolver), 3);
+    let frame = OutputFrame::new(CoordinateSystem::LDraw, Units::LDU);
+    let bounds = loading_bounds(&resolver.brick(), &resolver.pending(), frame);
+    assert_eq!(bounds.min, vec3(-10.0, -28.0, -10.0));
+    assert_eq!(bounds.max, vec3(50.0, 24.0, 10.0));
+
+    // the first brick waits for its stud, then both copies are there at once
+    resolver.step(&bundle, &progress).unwrap();
+    assert_eq!(placeholders(&resolver), 3);
+    resolver.step(&bundle, &progress).unwrap();
+    assert_eq!(placeholders(&resolver), 1);
+
+    while !resolver.is_done() {
+        resolver.step(&bundle, &progress).unwrap();
+    }
+    assert_eq!(placeholders(&resolver), 0);
+
+    // a window extends the scene it started from with the parts that arrived, every step
+    // draws what building it again would
+    let options = SceneOptions::default();
+    let mut resolver = Resolver::new("10030.dat", ColorTable::new());
+    resolver.step(&bundle, &progress).unwrap();
+    let mut pending = resolver.pending();
+    let mut scene = Scene::build_loaded(&resolver.brick(), &options, &pending);
+    assert_eq!(scene.triangle_count(), 0);
+    let mut counts = Vec::new();
+    while !resolver.is_done() {
+        resolver.step(&bundle, &progress).unwrap();
+        let brick = resolver.brick();
+        let now = resolver.pending();
+        scene.extend(Scene::build_arrived(&brick, &options, &pending, &now));
+        assert_eq!(
+            scene.triangle_count(),
+            Scene::build_loaded(&brick, &options, &now).triangle_count()
+        );
+        counts.push(scene.triangle_count());
+        pending = now;
+    }
+    assert_eq!(counts.last(), Some(&3));
+    assert_eq!(resolver.take_fresh().len(), 4);
+    assert_eq!(
+        Scene::build(&resolver.finish(), &SceneOptions::default()).triangle_count(),
+        3
+    );
+}
+
+#[test]
+fn fills_in_submodels_that_arrive_late() {
+    let bytes = zip(&[
+        (
+            "10030.dat",
+            "0 Model\n1 4 0 0 0 1 0 0 0 1 0 0 0 1 sub.ldr\n1 4 0 0 40 -1 0 0 0 1 0 0 0 1 sub.ldr",
+        ),
+        (
+            "sub.ldr",
+            "0 Submodel\n3 16 0 0 0 1 0 0 0 0 1\n1 1 0 0 0 1 0 0 0 1 0 0 0 1 3002.dat",
+        ),
+        (
+            "3002.dat",
+            "0 Brick\n0 !LDRAW_ORG Part\n3 16 0 0 0 1 0 0 0 0 1\n4 16 0 0 0 1 0 0 1 1 0 0 1 0",
+        ),
+    ]);
+    let bundle = Bundle::read(&bytes).unwrap();
+    let progress = Progress::new();
+    let options = SceneOptions::default();
+
+    let mut resolver = Resolver::new("10030.dat", ColorTable::new());
+    resolver.step(&bundle, &progress).unwrap();
+    let mut pending = resolver.pending();
+    let mut scene = Scene::build_loaded(&resolver.brick(), &options, &pending);
+    while !resolver.is_done() {
+        resolver.step(&bundle, &progress).unwrap();
+        let brick = resolver.brick();
+        let now = resolver.pending();
+        scene.extend(Scene::build_arrived(&brick, &options, &pending, &now));
+        let built = Scene::build_loaded(&brick, &options, &now);
+        assert_eq!(scene.triangle_count(), built.triangle_count());
+        assert_eq!(scene.aabb(), built.aabb());
+        pending = now;
+    }
+    // both copies of the submodel with their own triangle and the brick
+    assert_eq!(scene.triangle_count(), 8);
+}
@@ -0,0 +1,80 @@
+use ldraw_renderer::cache::{CacheStorage, FsStorage, MemoryStorage, PartCache};
+use std::cell::Cell;
+use std::future::Future;
+use std::rc::Rc;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+// the native backends never wait, so one poll is enough
+fn now<T>(future: impl Future<Output = T>) -> T {
+    fn raw() -> RawWaker {
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(|_| raw(), |_| {}, |_| {}, |_| {});
+    let waker = unsafe { Waker::from_raw(raw()) };
+    match Box::pin(future)
+        .as_mut()
+        .poll(&mut Context::from_waker(&waker))
+    {
+        Poll::Ready(value) => value,
+        Poll::Pending => panic!("the storage should not wait"),
+    }
+}
+
+#[test]
+fn evicts_the_least_recently_used() {
+    let cache = PartCache::new(MemoryStorage::new(), "2023-01", 10);
+    now(cache.put("a", vec![0; 4])).unwrap();
+    now(cache.put("b", vec![0; 4])).unwrap();
+    // reading a makes b the oldest
+    assert!(now(cache.get("a")).unwrap().is_some());
+    now(cache.put("c", vec![0; 4])).unwrap();
+
+    assert!(now(cache.get("a")).unwrap().is_some());
+    assert!(now(cache.get("b")).unwrap().is_none());
+    assert!(now(cache.get("c")).unwrap().is_some());
+
+    now(cache.put("too big", vec![0; 11])).unwrap();
+    assert!(now(cache.get("too big")).unwrap().is_none());
+}
+
+#[test]
+fn drops_other_library_versions() {
+    let cache = PartCache::new(MemoryStorage::new(), "2023-01", 1000);
+    let invalidated = Rc::new(Cell::new(0));
+    cache.on_invalidate({
+        let invalidated = invalidated.clone();
+        move |version, removed| {
+            assert_eq!(version, "2023-02");
+            invalidated.set(removed);
+        }
+    });
+
+    let versioned = cache.library_key("3001.dat");
+    let hashed = PartCache::<MemoryStorage>::content_key("3002.dat", b"0 Brick");
+    now(cache.put(&versioned, vec![1])).unwrap();
+    now(cache.put(&hashed, vec![2])).unwrap();
+
+    assert_eq!(now(cache.update_library("2023-02")).unwrap(), 1);
+    assert_eq!(invalidated.get(), 1);
+    assert!(now(cache.get(&versioned)).unwrap().is_none());
+    assert_eq!(now(cache.get(&hashed)).unwrap(), Some(vec![2]));
+}
+
+#[test]
+fn keeps_files_across_sessions() {
+    let directory = std::env::temp_dir().join(format!("ldraw-cache-{}", std::process::id()));
+    {
+        let cache = PartCache::new(FsStorage::open(&directory).unwrap(), "2023-01", 1000);
+        now(cache.put("parts/s/3001s01.dat@2023-01", vec![1, 2, 3])).unwrap();
+    }
+
+    let storage = FsStorage::open(&directory).unwrap();
+    let entries = now(storage.entries()).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].bytes, 3);
+    assert_eq!(
+        now(storage.get("parts/s/3001s01.dat@2023-01")).unwrap(),
+        Some(vec![1, 2, 3])
+    );
+    std::fs::remove_dir_all(&directory).unwrap();
+}
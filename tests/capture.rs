@@ -0,0 +1,37 @@
+use ldraw_renderer::capture::{downsample, encode_png, CaptureOptions, MAX_CAPTURE_SIZE};
+
+#[test]
+fn averages_supersampled_blocks() {
+    // 4x2 image, a white and a red-and-transparent 2x2 block
+    let white = [255, 255, 255, 255];
+    let red = [255, 0, 0, 255];
+    let clear = [0, 0, 0, 0];
+    let pixels = vec![white, white, red, clear, white, white, clear, red];
+
+    let target = downsample(&pixels, 4, 2, 2);
+    // the transparent samples only lower the alpha, the colour stays red
+    assert_eq!(target, vec![white, [255, 0, 0, 128]]);
+}
+
+#[test]
+fn reduces_supersampling_to_fit() {
+    let options = CaptureOptions {
+        transparent: false,
+        supersampling: 4,
+    };
+    assert_eq!(options.samples(1024, 512), 4);
+    assert_eq!(options.samples(MAX_CAPTURE_SIZE / 2, 512), 2);
+    assert_eq!(options.samples(MAX_CAPTURE_SIZE, 512), 1);
+
+    let options = CaptureOptions {
+        transparent: false,
+        supersampling: u32::MAX,
+    };
+    assert_eq!(options.samples(1024, 512), MAX_CAPTURE_SIZE / 1024);
+}
+
+#[test]
+fn encodes_png() {
+    let png = encode_png(&[[255, 0, 0, 255]; 6], 3, 2).unwrap();
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+}
@@ -0,0 +1,23 @@
+use ldraw_renderer::parser::{
+    colors::ColorTable,
+    part::{parse_file, split_lines, LDrawBrick},
+};
+use std::collections::HashMap;
+
+// the files are parsed in order into one colour table, so colours have to be defined
+// before the files that use them
+pub fn brick(files: &[(&str, &str)], entry: &str) -> LDrawBrick {
+    let mut colors = ColorTable::new();
+    let files = files
+        .iter()
+        .map(|(name, text)| {
+            let file = parse_file(name, split_lines(text), &mut colors).unwrap();
+            (name.to_string(), file)
+        })
+        .collect::<HashMap<_, _>>();
+    LDrawBrick {
+        entry_file: entry.to_string(),
+        files,
+        colors,
+    }
+}
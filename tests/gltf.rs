@@ -0,0 +1,120 @@
+mod common;
+
+use ldraw_renderer::{
+    export::gltf::{Gltf, GltfOptions},
+    parser::part::LDrawBrick,
+};
+
+// a submodel placed twice, holding one square, next to a red square
+fn brick() -> LDrawBrick {
+    common::brick(
+        &[
+            (
+                "square.dat",
+                "0 Square\n0 !LDRAW_ORG Part\n4 16 0 0 0 1 0 0 1 1 0 0 1 0\n2 24 0 0 0 1 0 0",
+            ),
+            ("sub.ldr", "0 Sub\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 square.dat"),
+            (
+                "model.ldr",
+                "0 Model\n\
+                 1 16 0 0 0 1 0 0 0 1 0 0 0 1 sub.ldr\n\
+                 1 16 0 0 20 1 0 0 0 1 0 0 0 1 sub.ldr\n\
+                 1 4 20 0 0 1 0 0 0 1 0 0 0 1 square.dat",
+            ),
+        ],
+        "model.ldr",
+    )
+}
+
+#[test]
+fn shares_meshes_between_nodes() {
+    let gltf = Gltf::new(&brick(), &GltfOptions::default());
+
+    // one square per colour, the models themselves have no geometry
+    assert_eq!(gltf.json["meshes"].as_array().unwrap().len(), 2);
+    // frame root, model, two submodels with a square each and the red square
+    let nodes = gltf.json["nodes"].as_array().unwrap();
+    assert_eq!(nodes.len(), 7);
+    let squares = nodes
+        .iter()
+        .filter(|node| node["mesh"].as_u64() == Some(0))
+        .count();
+    assert_eq!(squares, 2);
+    assert_eq!(
+        gltf.json["buffers"][0]["byteLength"].as_u64(),
+        Some(gltf.bin.len() as u64)
+    );
+}
+
+#[test]
+fn writes_lines_only_when_asked() {
+    let options = GltfOptions {
+        edges: true,
+        ..GltfOptions::default()
+    };
+    let gltf = Gltf::new(&brick(), &options);
+    let primitives = gltf.json["meshes"][0]["primitives"].as_array().unwrap();
+    assert_eq!(primitives.len(), 2);
+    assert_eq!(primitives[1]["mode"].as_u64(), Some(1));
+
+    let gltf = Gltf::new(&brick(), &GltfOptions::default());
+    assert_eq!(
+        gltf.json["meshes"][0]["primitives"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn packs_a_glb() {
+    let glb = Gltf::new(&brick(), &GltfOptions::default()).to_glb();
+    assert_eq!(&glb[..4], b"glTF");
+    assert_eq!(
+        u32::from_le_bytes([glb[8], glb[9], glb[10], glb[11]]) as usize,
+        glb.len()
+    );
+    assert_eq!(glb.len() % 4, 0);
+}
+
+#[test]
+fn leaves_out_what_draws_nothing() {
+    // the outline is all lines, with edges off it has no primitives
+    let brick = common::brick(
+        &[
+            (
+                "outline.dat",
+                "0 Outline\n0 !LDRAW_ORG Part\n2 24 0 0 0 1 0 0",
+            ),
+            (
+                "model.ldr",
+                "0 Model\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 outline.dat",
+            ),
+        ],
+        "model.ldr",
+    );
+    let gltf = Gltf::new(&brick, &GltfOptions::default());
+    for key in ["meshes", "accessors", "bufferViews", "buffers", "materials"] {
+        assert!(gltf.json.get(key).is_none(), "{}", key);
+    }
+    // only the frame root is left
+    let nodes = gltf.json["nodes"].as_array().unwrap();
+    assert_eq!(nodes.len(), 1);
+    assert!(nodes[0].get("children").is_none());
+    assert_eq!(gltf.files("empty", false).len(), 1);
+
+    let glb = gltf.to_glb();
+    assert_eq!(
+        u32::from_le_bytes([glb[12], glb[13], glb[14], glb[15]]) as usize + 20,
+        glb.len()
+    );
+
+    let options = GltfOptions {
+        edges: true,
+        ..GltfOptions::default()
+    };
+    let gltf = Gltf::new(&brick, &options);
+    assert_eq!(gltf.json["meshes"].as_array().unwrap().len(), 1);
+    assert_eq!(gltf.json["nodes"].as_array().unwrap().len(), 3);
+}
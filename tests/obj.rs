@@ -0,0 +1,66 @@
+mod common;
+
+use ldraw_renderer::{
+    export::obj::{Obj, ObjOptions},
+    smoothing::smooth,
+};
+use three_d::vec3;
+
+#[test]
+fn smooths_shallow_folds_but_not_edges_or_creases() {
+    // two triangles sharing the edge from (0,0,0) to (0,0,1), folded by `height`
+    let fold = |height: f32| {
+        [
+            [
+                vec3(0.0, 0.0, 0.0),
+                vec3(0.0, 0.0, 1.0),
+                vec3(-1.0, 0.0, 0.0),
+            ],
+            [
+                vec3(0.0, 0.0, 1.0),
+                vec3(0.0, 0.0, 0.0),
+                vec3(1.0, height, 0.0),
+            ],
+        ]
+    };
+
+    let shallow = smooth(&fold(0.1), &[]);
+    assert_eq!(shallow.positions.len(), 4);
+    // one normal per outer corner and one for each end of the shared edge
+    assert_eq!(shallow.normals.len(), 4);
+
+    let creased = smooth(&fold(10.0), &[]);
+    assert_eq!(creased.normals.len(), 6);
+
+    let edged = smooth(&fold(0.1), &[[vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0)]]);
+    assert_eq!(edged.normals.len(), 6);
+}
+
+// models are loaded as {id}.dat too, their parts are still grouped
+#[test]
+fn writes_groups_and_ldraw_materials() {
+    for entry in ["model.ldr", "10030.dat"] {
+        let brick = common::brick(
+            &[
+                (
+                    entry,
+                    "0 Model\n0 !COLOUR Red CODE 4 VALUE #C91A09 EDGE #333333\n\
+                     1 4 0 0 0 1 0 0 0 1 0 0 0 1 part.dat\n1 4 40 0 0 1 0 0 0 1 0 0 0 1 part.dat",
+                ),
+                (
+                    "part.dat",
+                    "0 Part\n0 !LDRAW_ORG Part\n4 16 0 0 0 1 0 0 1 0 1 0 0 1",
+                ),
+            ],
+            entry,
+        );
+
+        let obj = Obj::new(&brick, &ObjOptions::default(), "model.mtl");
+        assert!(obj.obj.contains("mtllib model.mtl\n"));
+        assert_eq!(obj.obj.matches("\ng part.dat_").count(), 2, "{}", entry);
+        assert_eq!(obj.obj.matches("\nv ").count(), 8);
+        assert!(obj.obj.contains("usemtl ldraw_4_Red\n"));
+        assert!(obj.obj.contains("\nf 5//5 6//6 7//7\n"));
+        assert!(obj.mtl.starts_with("newmtl ldraw_4_Red\n"));
+    }
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use ldraw_renderer::{
+    coordinates::{CoordinateSystem, OutputFrame, Units},
+    parser::part::LDrawBrick,
+    picking::{Picker, Ray},
+};
+use three_d::vec3;
+
+// two unit squares facing -Z, one of them 10 LDU further back
+fn brick(model: &str) -> LDrawBrick {
+    common::brick(
+        &[
+            (
+                "square.dat",
+                "0 Square\n0 !LDRAW_ORG Part\n4 16 0 0 0 1 0 0 1 1 0 0 1 0",
+            ),
+            (
+                model,
+                "0 Model\n\
+                 1 4 0 0 0 1 0 0 0 1 0 0 0 1 square.dat\n\
+                 1 1 0 0 10 1 0 0 0 1 0 0 0 1 square.dat",
+            ),
+        ],
+        model,
+    )
+}
+
+#[test]
+fn picks_the_nearest_part() {
+    // models are loaded as {id}.dat as well
+    for model in ["model.ldr", "10030.dat"] {
+        let frame = OutputFrame::new(CoordinateSystem::LDraw, Units::LDU);
+        let picker = Picker::new(&brick(model), &frame);
+
+        let hit = picker
+            .cast(&Ray::new(vec3(0.5, 0.5, -5.0), vec3(0.0, 0.0, 1.0)))
+            .unwrap();
+        assert_eq!(hit.part.color, 4);
+        assert_eq!(hit.part.source, Some((model.to_string(), 2)));
+        assert!((hit.distance - 5.0).abs() < 1e-4);
+
+        let hit = picker
+            .cast(&Ray::new(vec3(0.5, 0.5, 20.0), vec3(0.0, 0.0, -1.0)))
+            .unwrap();
+        assert_eq!(hit.part.color, 1);
+        assert_eq!(hit.part.source, Some((model.to_string(), 3)));
+
+        assert!(picker
+            .cast(&Ray::new(vec3(2.0, 0.5, -5.0), vec3(0.0, 0.0, 1.0)))
+            .is_none());
+    }
+}
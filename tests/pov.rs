@@ -0,0 +1,35 @@
+mod common;
+
+use ldraw_renderer::export::pov::{self, PovOptions};
+
+#[test]
+fn declares_each_file_once() {
+    let brick = common::brick(
+        &[
+            (
+                "square.dat",
+                "0 Square\n0 !LDRAW_ORG Part\n4 16 0 0 0 1 0 0 1 0 1 0 0 1",
+            ),
+            (
+                "model.ldr",
+                "0 Model\n\
+                 0 !COLOUR Chrome_Silver CODE 383 VALUE #E0E0E0 EDGE #A4A4A4 CHROME\n\
+                 1 383 0 0 0 1 0 0 0 1 0 0 0 1 square.dat\n\
+                 1 16 20 0 0 1 0 0 0 1 0 0 0 1 square.dat",
+            ),
+        ],
+        "model.ldr",
+    );
+
+    let scene = pov::write(&brick, &PovOptions::default());
+    assert_eq!(
+        scene.matches("#declare ldraw_square_dat = mesh2").count(),
+        1
+    );
+    assert_eq!(scene.matches("    ldraw_square_dat\n").count(), 2);
+    assert_eq!(scene.matches("material { ldraw_color_383 }").count(), 1);
+    assert!(scene.contains("#declare ldraw_color_383 = material"));
+    assert!(scene.contains("reflection { 0.6 metallic }"));
+    assert!(scene.contains("camera {\n  perspective"));
+    assert_eq!(scene.matches("light_source").count(), 3);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use ldraw_renderer::{
+    export::{
+        print::{print_objects, PrintOptions},
+        stl, threemf,
+    },
+    parser::part::LDrawBrick,
+};
+use three_d::{vec3, InnerSpace, Vector3};
+
+fn brick(text: &str) -> LDrawBrick {
+    common::brick(&[("part.dat", text)], "part.dat")
+}
+
+// a closed tetrahedron with one corner 10 LDU above the others
+const TETRAHEDRON: &str = "0 Tetrahedron\n0 !LDRAW_ORG Part\n\
+    3 16 0 0 0 10 0 0 0 0 10\n\
+    3 16 0 0 0 0 -10 0 10 0 0\n\
+    3 16 0 0 0 0 0 10 0 -10 0\n\
+    3 16 10 0 0 0 -10 0 0 0 10";
+
+#[test]
+fn reports_closed_and_open_meshes() {
+    let (objects, report) = print_objects(&brick(TETRAHEDRON), &PrintOptions::default());
+    assert_eq!(objects[0].mesh.positions.len(), 4);
+    assert_eq!(report.triangles, 4);
+    assert!(report.is_manifold());
+
+    let (_, report) = print_objects(
+        &brick("0 Square\n0 !LDRAW_ORG Part\n4 16 0 0 0 1 0 0 1 0 1 0 0 1"),
+        &PrintOptions::default(),
+    );
+    assert_eq!(report.open_edges, 4);
+    assert_eq!(report.non_manifold_edges, 0);
+}
+
+#[test]
+fn orients_onto_the_build_plate() {
+    // the slanted face is the largest, it ends up facing down and resting on z = 0
+    let largest = |orient: bool| {
+        let options = PrintOptions {
+            orient,
+            ..PrintOptions::default()
+        };
+        let (objects, _) = print_objects(&brick(TETRAHEDRON), &options);
+        let mesh = &objects[0].mesh;
+        mesh.triangles
+            .iter()
+            .map(|triangle| mesh.triangle(triangle))
+            .max_by(|a, b| area(a).total_cmp(&area(b)))
+            .unwrap()
+    };
+    let normal = |[a, b, c]: [Vector3<f32>; 3]| (b - a).cross(c - a).normalize();
+
+    assert!(normal(largest(false)).z > -0.9);
+    let face = largest(true);
+    assert!((normal(face) - vec3(0.0, 0.0, -1.0)).magnitude() < 1e-4);
+    for corner in face {
+        assert!(corner.z.abs() < 1e-4);
+    }
+}
+
+fn area([a, b, c]: &[Vector3<f32>; 3]) -> f32 {
+    (b - a).cross(c - a).magnitude() * 0.5
+}
+
+#[test]
+fn writes_stl_and_3mf() {
+    let (objects, _) = print_objects(&brick(TETRAHEDRON), &PrintOptions::default());
+    assert_eq!(stl::write_binary(&objects).len(), 84 + 4 * 50);
+    assert!(stl::write_ascii("part", &objects).starts_with("solid part\n"));
+    assert_eq!(&threemf::write(&objects).unwrap()[..2], b"PK");
+    assert!(threemf::model_xml(&objects).contains("<triangle v1="));
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use ldraw_renderer::{
+    bounds::Aabb,
+    camera::{CameraSetup, Projection},
+    capture::MAX_CAPTURE_SIZE,
+    parser::part::LDrawBrick,
+    props::RenderProps,
+    raster::{RasterScene, Rasterizer},
+    scene::{Scene, SceneOptions},
+};
+use three_d::vec3;
+
+// a red square from -1 to 1 facing -Z, outlined by an edge line along its bottom
+fn brick() -> LDrawBrick {
+    common::brick(
+        &[(
+            "square.dat",
+            "0 Square\n0 !LDRAW_ORG Part\n\
+             4 16 -1 -1 0 1 -1 0 1 1 0 -1 1 0\n\
+             2 24 -1 1 0 1 1 0",
+        )],
+        "square.dat",
+    )
+}
+
+fn render(props: &RenderProps) -> Rasterizer {
+    let brick = brick();
+    let scene = Scene::build(&brick, &SceneOptions::default());
+    let camera = CameraSetup::fit(
+        &Aabb::from_points([vec3(-2.0, -2.0, 0.0), vec3(2.0, 2.0, 0.0)].iter()),
+        vec3(0.0, 0.0, -1.0),
+        vec3(0.0, -1.0, 0.0),
+        Projection::Orthographic,
+        1.0,
+    );
+
+    let mut rasterizer = Rasterizer::new(32, 32).unwrap();
+    rasterizer.clear(props.background());
+    rasterizer.draw(&RasterScene::new(&scene, &brick.colors, props), &camera);
+    rasterizer
+}
+
+#[test]
+fn draws_the_square_over_the_background() {
+    let props = RenderProps::default();
+    let rasterizer = render(&props);
+    let pixel = |x: usize, y: usize| rasterizer.pixels()[y * 32 + x];
+
+    assert_eq!(pixel(0, 0), props.background());
+    assert_ne!(pixel(16, 16), props.background());
+    assert_eq!(pixel(16, 16)[3], 255);
+}
+
+#[test]
+fn is_deterministic() {
+    let props = RenderProps::default();
+    assert_eq!(render(&props).to_png(), render(&props).to_png());
+}
+
+#[test]
+fn turns_down_sizes_it_cannot_allocate() {
+    assert!(Rasterizer::new(0, 32).is_err());
+    assert!(Rasterizer::new(MAX_CAPTURE_SIZE + 1, 1).is_err());
+    assert!(Rasterizer::new(u32::MAX, u32::MAX).is_err());
+    assert!(Rasterizer::new(MAX_CAPTURE_SIZE, 1).is_ok());
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use ldraw_renderer::{
+    coordinates::{CoordinateSystem, OutputFrame, Units},
+    parser::part::LDrawBrick,
+    scene::{Scene, SceneOptions},
+};
+use std::ops::Mul;
+use three_d::Vector3;
+
+// a certified square facing +Z, placed as it is, mirrored in x and inverted
+fn brick(references: &str) -> LDrawBrick {
+    common::brick(
+        &[
+            (
+                "square.dat",
+                "0 Square\n0 !LDRAW_ORG Part\n0 BFC CERTIFY CCW\n3 16 0 0 0 10 0 0 10 10 0",
+            ),
+            (
+                "model.ldr",
+                &format!("0 Model\n0 BFC CERTIFY CCW\n{}", references),
+            ),
+        ],
+        "model.ldr",
+    )
+}
+
+fn options(instance_threshold: usize) -> SceneOptions {
+    SceneOptions {
+        instance_threshold,
+        frame: OutputFrame::new(CoordinateSystem::LDraw, Units::LDU),
+        ..SceneOptions::default()
+    }
+}
+
+// z of the world space normal of every triangle
+fn facing(scene: &Scene) -> Vec<f32> {
+    let mut facing = Vec::new();
+    for instance in scene.instances.iter() {
+        for group in scene.meshes[instance.mesh].groups.iter() {
+            for triangle in group.positions.chunks(3) {
+                let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+                    .map(|point| instance.transformation.mul(point.extend(1.0)).truncate());
+                let normal: Vector3<f32> = (b - a).cross(c - a);
+                facing.push(normal.z.signum());
+            }
+        }
+    }
+    facing
+}
+
+#[test]
+fn instances_repeated_subfiles() {
+    let references = "1 16 0 0 0 1 0 0 0 1 0 0 0 1 square.dat\n\
+                      1 16 20 0 0 1 0 0 0 1 0 0 0 1 square.dat\n\
+                      1 4 40 0 0 1 0 0 0 1 0 0 0 1 square.dat";
+
+    let scene = Scene::build(&brick(references), &options(2));
+    // the model, the square in main colour twice and the red one baked into the model
+    assert_eq!(scene.meshes.len(), 2);
+    assert_eq!(scene.instances.len(), 3);
+    assert_eq!(scene.triangle_count(), 3);
+
+    let scene = Scene::build(&brick(references), &options(8));
+    assert_eq!(scene.meshes.len(), 1);
+    assert_eq!(scene.triangle_count(), 3);
+}
+
+#[test]
+fn keeps_mirrored_references_facing_out() {
+    const PLAIN: &str = "1 16 0 0 0 1 0 0 0 1 0 0 0 1 square.dat";
+    const MIRRORED: &str = "1 16 0 0 0 -1 0 0 0 1 0 0 0 1 square.dat";
+
+    // baked into the model and drawn instanced
+    for threshold in [8, 1] {
+        let facing =
+            |references: &str| facing(&Scene::build(&brick(references), &options(threshold)));
+        let plain = facing(PLAIN);
+        assert_eq!(plain.len(), 1);
+        assert_eq!(facing(MIRRORED), plain);
+
+        let inverted = [-plain[0]];
+        assert_eq!(facing(&format!("0 BFC INVERTNEXT\n{}", PLAIN)), inverted);
+        assert_eq!(facing(&format!("0 BFC INVERTNEXT\n{}", MIRRORED)), inverted);
+    }
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use ldraw_renderer::{
+    scene::PartInstance,
+    selection::{Selection, SelectionCommand, SelectionStyle, UnselectedStyle},
+    smoothing::extrude,
+};
+use three_d::{vec3, InnerSpace, Matrix4, SquareMatrix, Vector3};
+
+fn parts() -> Vec<PartInstance> {
+    ["3001.dat", "3001.dat", "3003.dat"]
+        .iter()
+        .map(|filename| PartInstance {
+            filename: filename.to_string(),
+            color: 4,
+            source: None,
+            transformation: Matrix4::identity(),
+        })
+        .collect()
+}
+
+#[test]
+fn selects_and_deselects_known_instances() {
+    let parts = parts();
+    let mut selection = Selection::new();
+
+    assert!(selection.select(&[0, 2, 7], &parts));
+    assert_eq!(selection.instances().collect::<Vec<_>>(), vec![0, 2]);
+    // nothing new
+    assert!(!selection.select(&[2], &parts));
+
+    assert!(selection.deselect(&[0, 1]));
+    assert!(!selection.deselect(&[1]));
+    assert_eq!(selection.instances().collect::<Vec<_>>(), vec![2]);
+
+    assert!(selection.clear());
+    assert!(!selection.clear());
+    assert!(selection.is_empty());
+}
+
+#[test]
+fn selects_where_the_predicate_holds() {
+    let parts = parts();
+    let mut selection = Selection::new();
+
+    assert!(selection.select_where(&parts, |_, part| part.filename == "3001.dat"));
+    assert!(selection.contains(0) && selection.contains(1) && !selection.contains(2));
+    assert!(!selection.select_where(&parts, |instance, _| instance == 1));
+}
+
+#[test]
+fn changes_style_only_when_it_differs() {
+    let brick = common::brick(&[("model.ldr", "0 Model")], "model.ldr");
+    let parts = parts();
+    let mut selection = Selection::new();
+
+    let style =
+        SelectionCommand::SetStyle(SelectionStyle::Highlight, UnselectedStyle::Dim, [0, 0, 255]);
+    assert!(selection.apply(&style, &parts, &brick));
+    assert!(!selection.apply(&style, &parts, &brick));
+    assert_eq!(selection.style, SelectionStyle::Highlight);
+    assert_eq!(selection.unselected, UnselectedStyle::Dim);
+    assert_eq!(selection.color, [0, 0, 255]);
+
+    assert!(selection.apply(&SelectionCommand::Select(vec![1]), &parts, &brick));
+    assert!(selection.apply(&SelectionCommand::Clear, &parts, &brick));
+}
+
+#[test]
+fn pushes_the_outline_hull_out_along_the_normals() {
+    // an L shaped outline, the inner corner must not be covered by the hull
+    let outline = [
+        vec3(0.0, 0.0, 0.0),
+        vec3(2.0, 0.0, 0.0),
+        vec3(2.0, 1.0, 0.0),
+        vec3(1.0, 1.0, 0.0),
+        vec3(1.0, 2.0, 0.0),
+        vec3(0.0, 2.0, 0.0),
+    ];
+    let mut triangles = Vec::new();
+    for (index, from) in outline.iter().enumerate() {
+        let to = outline[(index + 1) % outline.len()];
+        let up = vec3(0.0, 0.0, 1.0);
+        // walls facing out, wound counter clockwise seen from outside
+        triangles.push([*from, to, to + up]);
+        triangles.push([*from, to + up, *from + up]);
+    }
+
+    let hull = extrude(&triangles, 0.25);
+    assert_eq!(hull.len(), triangles.len());
+    for (grown, triangle) in hull.iter().zip(triangles.iter()) {
+        for (moved, corner) in grown.iter().zip(triangle.iter()) {
+            let offset: Vector3<f32> = moved - corner;
+            assert!((offset.magnitude() - 0.25).abs() < 1e-5);
+            assert_eq!(offset.z, 0.0);
+        }
+    }
+
+    // the inner corner moves into the notch, a scale about the centre would move it away
+    let inner = hull
+        .iter()
+        .flatten()
+        .find(|position| {
+            (position.x - 1.0).abs() < 0.5 && (position.y - 1.0).abs() < 0.5 && position.z == 0.0
+        })
+        .unwrap();
+    assert!(inner.x > 1.0 && inner.y > 1.0);
+}
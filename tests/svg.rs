@@ -0,0 +1,43 @@
+mod common;
+
+use ldraw_renderer::{
+    camera::ViewPreset,
+    export::svg::{self, SvgOptions},
+};
+
+// seen from the front, a square with one edge behind it and one in front, and two
+// conditional lines in front whose control points are on the same and opposite sides
+const PART: &str = "0 Part\n0 !LDRAW_ORG Part\n\
+    0 !COLOUR Hidden CODE 100 VALUE #FF0000 EDGE #000000\n\
+    0 !COLOUR Shown CODE 101 VALUE #00FF00 EDGE #000000\n\
+    0 !COLOUR Active CODE 102 VALUE #0000FF EDGE #000000\n\
+    0 !COLOUR Inactive CODE 103 VALUE #FFFF00 EDGE #000000\n\
+    4 16 -10 -10 0 10 -10 0 10 10 0 -10 10 0\n\
+    2 100 -5 0 10 5 0 10\n\
+    2 101 -5 5 -10 5 5 -10\n\
+    5 102 -5 -5 -10 5 -5 -10 0 -8 -10 0 -9 -10\n\
+    5 103 -5 -2 -10 5 -2 -10 0 -1 -10 0 -3 -10";
+
+#[test]
+fn removes_hidden_lines() {
+    let brick = common::brick(&[("part.dat", PART)], "part.dat");
+
+    let options = SvgOptions {
+        preset: ViewPreset::Front,
+        ..SvgOptions::default()
+    };
+    let drawing = svg::write(&brick, &options).unwrap();
+    assert!(drawing.starts_with("<svg "));
+    assert_eq!(drawing.matches("<g class=\"part part-0\"").count(), 2);
+    assert!(drawing.contains("<path class=\"edges\""));
+    assert!(!drawing.contains("stroke=\"#ff0000\""));
+    assert!(drawing.contains("stroke=\"#00ff00\""));
+    assert!(drawing.contains("stroke=\"#0000ff\""));
+    assert!(!drawing.contains("stroke=\"#ffff00\""));
+
+    let options = SvgOptions {
+        width: u32::MAX,
+        ..options
+    };
+    assert!(svg::write(&brick, &options).is_err());
+}
@@ -3,7 +3,18 @@
 #![cfg(target_arch = "wasm32")]
 
 extern crate wasm_bindgen_test;
+use js_sys::Promise;
+use ldraw_renderer::{
+    debug::{debug_stats, DebugStats},
+    progressive::LoadedBrick,
+    RenderingNever,
+};
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
 use wasm_bindgen_test::*;
+use web_sys::HtmlCanvasElement;
+
+mod common;
 
 wasm_bindgen_test_configure!(run_in_browser);
 
@@ -11,3 +22,58 @@ wasm_bindgen_test_configure!(run_in_browser);
 fn pass() {
     assert_eq!(1 + 1, 2);
 }
+
+#[wasm_bindgen_test]
+fn no_windows_without_rendering() {
+    let stats = debug_stats();
+    assert_eq!(stats.live_windows, 0);
+    assert_eq!(stats.frame_geometries, 0);
+}
+
+#[wasm_bindgen_test]
+async fn releases_a_deleted_window() {
+    let baseline = debug_stats();
+    let rendering = RenderingNever::new();
+    let proxy = rendering.get_proxy();
+    rendering.run();
+
+    let canvas: HtmlCanvasElement = web_sys::window()
+        .unwrap()
+        .document()
+        .unwrap()
+        .create_element("canvas")
+        .unwrap()
+        .dyn_into()
+        .unwrap();
+    let brick = common::brick(
+        &[(
+            "square.dat",
+            "0 Square\n0 !LDRAW_ORG Part\n4 16 0 0 0 10 0 0 10 10 0 0 10 0",
+        )],
+        "square.dat",
+    );
+    let id = proxy.open_window(canvas, LoadedBrick::complete(brick, None));
+    let opened = wait_for(|stats| stats.live_windows > baseline.live_windows).await;
+    assert_eq!(opened.live_windows, baseline.live_windows + 1);
+    assert!(opened.frame_geometries > baseline.frame_geometries);
+
+    proxy.delete_window(id);
+    assert_eq!(wait_for(|stats| stats == baseline).await, baseline);
+}
+
+// the event loop handles the events between tasks, so the stats are polled
+async fn wait_for(done: impl Fn(DebugStats) -> bool) -> DebugStats {
+    for _ in 0..100 {
+        if done(debug_stats()) {
+            break;
+        }
+        let tick = Promise::new(&mut |resolve, _| {
+            web_sys::window()
+                .unwrap()
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, 10)
+                .unwrap();
+        });
+        JsFuture::from(tick).await.unwrap();
+    }
+    debug_stats()
+}
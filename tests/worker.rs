@@ -0,0 +1,114 @@
+mod common;
+
+use ldraw_renderer::{
+    baked,
+    errors::LoadErrorKind,
+    parser::part::LDrawBrick,
+    progress::{BuildStage, LoadEvent},
+    scene::{Scene, SceneOptions},
+    worker::{LoadJob, ScenePayload, WorkerCore, WorkerRequest, WorkerResponse},
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn brick() -> LDrawBrick {
+    common::brick(
+        &[
+            (
+                "3001.dat",
+                "0 Brick\n0 !LDRAW_ORG Part\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 stud.dat\n1 16 20 0 0 1 0 0 0 1 0 0 0 1 stud.dat\n2 24 0 0 0 1 0 0",
+            ),
+            (
+                "stud.dat",
+                "0 Stud\n0 !LDRAW_ORG Primitive\n3 16 0 0 0 1 0 0 0 0 1",
+            ),
+        ],
+        "3001.dat",
+    )
+}
+
+fn core() -> (WorkerCore, Rc<RefCell<Vec<WorkerResponse>>>) {
+    let posted = Rc::new(RefCell::new(Vec::new()));
+    let core = WorkerCore::new({
+        let posted = posted.clone();
+        move |response| posted.borrow_mut().push(response)
+    });
+    (core, posted)
+}
+
+fn load(job: u32) -> WorkerRequest {
+    WorkerRequest::Load {
+        job,
+        brick_id: "3001".to_string(),
+        options: SceneOptions::default(),
+        prefer_baked: false,
+    }
+}
+
+#[test]
+fn builds_the_scene_into_flat_buffers() {
+    let (mut core, posted) = core();
+    assert_eq!(
+        core.handle(load(7)),
+        Some(LoadJob {
+            job: 7,
+            brick_id: "3001".to_string(),
+            prefer_baked: false,
+        })
+    );
+    core.progress(
+        7,
+        LoadEvent::FilesResolved {
+            resolved: 1,
+            total: 2,
+        },
+    );
+    core.finish(7, Ok((brick(), None)));
+
+    let posted = posted.borrow();
+    assert_eq!(posted.len(), 3);
+    assert!(matches!(
+        &posted[1],
+        WorkerResponse::Progress {
+            job: 7,
+            event: LoadEvent::Stage(BuildStage::Scene),
+        }
+    ));
+    let (bytes, payload) = match &posted[2] {
+        WorkerResponse::Loaded {
+            job: 7,
+            brick,
+            scene,
+        } => (brick, scene),
+        other => panic!("expected the loaded scene, got {:?}", other),
+    };
+
+    let expected = Scene::build(&brick(), &SceneOptions::default());
+    assert_eq!(payload, &ScenePayload::from_scene(&expected));
+    let scene = payload.clone().into_scene();
+    assert_eq!(scene.triangle_count(), expected.triangle_count());
+    assert_eq!(scene.instances.len(), expected.instances.len());
+    assert_eq!(ScenePayload::from_scene(&scene), *payload);
+    assert_eq!(baked::decode(bytes).unwrap().entry_file, "3001.dat");
+}
+
+#[test]
+fn answers_cancelled_jobs_once() {
+    let (mut core, posted) = core();
+    core.handle(load(1));
+    assert_eq!(core.handle(WorkerRequest::Cancel { job: 1 }), None);
+    assert!(core.is_cancelled(1));
+
+    core.progress(1, LoadEvent::Stage(BuildStage::Scene));
+    core.finish(1, Ok((brick(), None)));
+    core.finish(1, Ok((brick(), None)));
+
+    let posted = posted.borrow();
+    assert_eq!(posted.len(), 1);
+    match &posted[0] {
+        WorkerResponse::Failed { job: 1, error } => {
+            assert_eq!(error.kind, LoadErrorKind::Cancelled)
+        }
+        other => panic!("expected the job to fail, got {:?}", other),
+    }
+}
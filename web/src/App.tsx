@@ -1,12 +1,24 @@
 import { useCallback, useContext, useEffect, useState } from "react"
 import { RenderingContext } from "./context"
+import { CaptureOptions, LightingPreset, PickResult, Projection, Quality, RenderProps, ViewPreset, WindowHandle } from "ldraw-renderer"
 
 const BRICKS = ["3001", "3002", "3005"]
+const VIEWS = ["LDraw", "Front", "Back", "Left", "Right", "Top", "Bottom"] as const
+const LIGHTING = ["Studio", "Soft", "Flat"] as const
+const QUALITY = ["Medium", "Low", "High"] as const
 
 function App() {
   const rendering = useContext(RenderingContext)
   const [red, setRed] = useState(0);
   const [brick, setBrick] = useState("3001");
+  const [handle, setHandle] = useState<WindowHandle>();
+  const [error, setError] = useState<string>();
+  const [status, setStatus] = useState<string>();
+  const [picked, setPicked] = useState<string>();
+  const [orthographic, setOrthographic] = useState(false);
+  const [edges, setEdges] = useState(false);
+  const [lighting, setLighting] = useState<typeof LIGHTING[number]>("Studio");
+  const [quality, setQuality] = useState<typeof QUALITY[number]>("Medium");
 
   const redChanged = useCallback((val: number) => {
     setRed(val);
@@ -18,25 +30,107 @@ function App() {
 
 
   useEffect(() => {
-    rendering.update_prop(red)
-  }, [red])
+    if (handle === undefined) return
+    const props = new RenderProps()
+    props.set_background(204, 204, 204, 255)
+    // tints the main colour of the brick
+    props.set_color_override(16, red, 0, 0, 255)
+    props.show_edges = edges
+    props.lighting = LightingPreset[lighting]
+    props.quality = Quality[quality]
+    props.projection = orthographic ? Projection.Orthographic : Projection.Perspective
+    handle.update(props)
+    props.free()
+  }, [handle, red, edges, lighting, quality, orthographic])
 
   useEffect(() => {
-    const windowRef = rendering.create_window("canvas1", brick);
+    const controller = new AbortController()
+    let created: WindowHandle | undefined
+    setError(undefined)
+    const onEvent = (event: any) => {
+      switch (event.type) {
+        case "files": setStatus(`loading ${event.resolved}/${event.total} files`); break
+        case "stage": setStatus(`building ${event.stage}`); break
+        case "warning": console.warn(`${event.file}:${event.line} ${event.message}`); break
+        case "first_frame": setStatus(undefined); break
+      }
+    }
+    rendering.create_window("canvas1", brick, controller.signal, onEvent)
+      .then(windowHandle => {
+        created = windowHandle
+        windowHandle.on_click((result?: PickResult) => {
+          windowHandle.clear_selection()
+          if (result) windowHandle.select(Uint32Array.of(result.instance))
+          setPicked(result
+            ? `${result.filename} in ${result.color_name}${result.source_file ? ` (${result.source_file}:${result.source_line})` : ""}`
+            : undefined)
+          result?.free()
+        })
+        setHandle(windowHandle)
+      })
+      .catch(e => {
+        if (e.kind !== "cancelled") setError(`${e.kind}${e.file ? ` in ${e.file}` : ""}${e.line ? `:${e.line}` : ""}: ${e.message}`)
+      })
     return () => {
-      rendering.delete_window(0) // use windowref but async shit
+      controller.abort()
+      setHandle(undefined)
+      created?.dispose()
+      created?.free()
     }
   }, [brick])
 
+  const capture = useCallback(async () => {
+    if (!handle) return
+    const options = new CaptureOptions()
+    options.transparent = true
+    options.supersampling = 2
+    const png: Uint8Array = await handle.capture(1920, 1080, options)
+    options.free()
+    const link = document.createElement("a")
+    link.href = URL.createObjectURL(new Blob([png], { type: "image/png" }))
+    link.download = `${brick}.png`
+    link.click()
+    // revoking right away can cancel the download before it starts
+    setTimeout(() => URL.revokeObjectURL(link.href), 0)
+  }, [handle, brick])
+
   return (
     <div className="App">
       <canvas id="canvas1" style={{ display: "block", width: "100%", height: "50%" }}></canvas>
+      {error && <p className="error">{error}</p>}
+      {status && <p className="status">{status}</p>}
+      {picked && <p className="picked">{picked}</p>}
       <input type="range" min="0" max="255" className="slider" id="red" value={red} onChange={e => redChanged(parseInt(e.target.value))} />
       <select onChange={e => brickChanged(e.target.value)}>
         {BRICKS.map(brick =>
           <option value={brick}>{brick}</option>
         )}
       </select>
+      <select onChange={e => handle?.set_view(ViewPreset[e.target.value as typeof VIEWS[number]])}>
+        {VIEWS.map(view =>
+          <option value={view}>{view}</option>
+        )}
+      </select>
+      <label>
+        <input type="checkbox" checked={orthographic} onChange={e => setOrthographic(e.target.checked)} />
+        orthographic
+      </label>
+      <label>
+        <input type="checkbox" checked={edges} onChange={e => setEdges(e.target.checked)} />
+        edges
+      </label>
+      <select value={lighting} onChange={e => setLighting(e.target.value as typeof LIGHTING[number])}>
+        {LIGHTING.map(preset =>
+          <option value={preset}>{preset}</option>
+        )}
+      </select>
+      <select value={quality} onChange={e => setQuality(e.target.value as typeof QUALITY[number])}>
+        {QUALITY.map(level =>
+          <option value={level}>{level}</option>
+        )}
+      </select>
+      <button onClick={() => handle?.fit_to_bounds()}>fit</button>
+      <button onClick={() => capture()}>capture</button>
     </div>
   )
 }
@@ -5,10 +5,12 @@ import * as wasm from "ldraw-renderer"
 export const RenderingContext = createContext((() => {
     const rendering = new wasm.RenderingNever();
     const proxy = rendering.get_proxy();
-    try {
-        rendering.run()
-    } catch (error) {
-        console.error(error)
+    // parts load on this thread by default, so placeholders show while files arrive. ?worker
+    // moves loading into a web worker and draws the model once it is complete
+    if (new URLSearchParams(window.location.search).has("worker")) {
+        proxy.set_worker(new Worker(new URL("./worker.ts", import.meta.url), { type: "module" }))
     }
+    // spawns the event loop and returns
+    rendering.run()
     return proxy
 })());
\ No newline at end of file
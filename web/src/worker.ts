@@ -0,0 +1,13 @@
+import { LoadWorker } from "ldraw-renderer"
+
+// fetches, parses and builds the scenes of parts off the main thread, the messages are
+// described in src/worker.rs
+const worker = new LoadWorker((message: unknown, transfer: Transferable[]) => self.postMessage(message, { transfer }))
+
+self.onmessage = (event: MessageEvent) => {
+    try {
+        worker.handle(event.data)
+    } catch (error) {
+        console.error(error)
+    }
+}
@@ -6,4 +6,9 @@ import topLevelAwait from "vite-plugin-top-level-await"
 // https://vitejs.dev/config/
 export default defineConfig({
   plugins: [react(), wasm(), topLevelAwait()],
+  // the worker loads the same wasm module
+  worker: {
+    format: "es",
+    plugins: [wasm(), topLevelAwait()],
+  },
 })